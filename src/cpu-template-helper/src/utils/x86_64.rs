@@ -71,6 +71,7 @@ impl From<CpuidModifierMap> for Vec<CpuidLeafModifier> {
                 leaf_modifier.modifiers.push(CpuidRegisterModifier {
                     register: modifier_key.register,
                     bitmap: modifier_value,
+                    passthrough: false,
                 });
             } else {
                 leaf_modifiers.push(CpuidLeafModifier {
@@ -80,6 +81,7 @@ impl From<CpuidModifierMap> for Vec<CpuidLeafModifier> {
                     modifiers: vec![CpuidRegisterModifier {
                         register: modifier_key.register,
                         bitmap: modifier_value,
+                        passthrough: false,
                     }],
                 });
             }
@@ -126,6 +128,7 @@ impl From<MsrModifierMap> for Vec<RegisterModifier> {
             .map(|(modifier_key, modifier_value)| RegisterModifier {
                 addr: modifier_key.0,
                 bitmap: modifier_value,
+                source: None,
             })
             .collect::<Vec<_>>();
         modifier_vec.sort_by_key(|modifier| modifier.addr);
@@ -141,6 +144,7 @@ macro_rules! cpuid_reg_modifier {
                 filter: u32::MAX.into(),
                 value: $value,
             },
+            passthrough: false,
         }
     };
     ($register:expr, $value:expr, $filter:expr) => {
@@ -150,6 +154,7 @@ macro_rules! cpuid_reg_modifier {
                 filter: $filter,
                 value: $value,
             },
+            passthrough: false,
         }
     };
 }
@@ -173,6 +178,7 @@ macro_rules! msr_modifier {
                 filter: u64::MAX,
                 value: $value,
             },
+            source: None,
         }
     };
     ($addr:expr, $value:expr, $filter:expr) => {
@@ -182,6 +188,7 @@ macro_rules! msr_modifier {
                 filter: $filter,
                 value: $value,
             },
+            source: None,
         }
     };
 }
@@ -4,6 +4,7 @@
 // Benchmarking cases:
 //   * `CustomCpuTemplate` JSON deserialization
 //   * `CustomCpuTemplate` JSON serialization
+//   * `CustomCpuTemplate` conversion to and from the compact form
 
 use std::mem::size_of_val;
 
@@ -40,6 +41,48 @@ pub fn cpu_template_benchmark(c: &mut Criterion) {
     c.bench_function("serialize_cpu_template", |b| {
         b.iter(|| bench_serialize_cpu_template(&test_cpu_template))
     });
+
+    #[cfg(all(target_arch = "x86_64", feature = "compact-cpu-templates"))]
+    compact_cpu_template_benchmark(c);
+}
+
+#[cfg(all(target_arch = "x86_64", feature = "compact-cpu-templates"))]
+pub fn compact_cpu_template_benchmark(c: &mut Criterion) {
+    use vmm::cpu_config::x86_64::compact::CompactCpuTemplate;
+    use vmm::cpu_config::x86_64::static_cpu_templates::{c3, t2, t2a, t2cl, t2s};
+
+    let templates = [c3::c3(), t2::t2(), t2s::t2s(), t2cl::t2cl(), t2a::t2a()];
+    let compact_templates: Vec<_> = templates.iter().map(CompactCpuTemplate::from).collect();
+    let footprint: usize = templates
+        .iter()
+        .map(CustomCpuTemplate::memory_footprint)
+        .sum();
+    let compact_footprint: usize = compact_templates
+        .iter()
+        .map(CompactCpuTemplate::memory_footprint)
+        .sum();
+    println!(
+        "Static templates memory footprint: [{}] bytes, compact: [{}] bytes.",
+        footprint, compact_footprint
+    );
+
+    c.bench_function("compact_cpu_template", |b| {
+        b.iter(|| {
+            templates
+                .iter()
+                .map(CompactCpuTemplate::from)
+                .collect::<Vec<_>>()
+        })
+    });
+
+    c.bench_function("expand_compact_cpu_template", |b| {
+        b.iter(|| {
+            compact_templates
+                .iter()
+                .map(CustomCpuTemplate::from)
+                .collect::<Vec<_>>()
+        })
+    });
 }
 
 criterion_group! {
@@ -5,7 +5,7 @@ use std::arch::x86_64::__cpuid as host_cpuid;
 use std::cmp::{Eq, Ordering, PartialEq, PartialOrd};
 
 /// Structure representing x86_64 CPU model.
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub struct CpuModel {
     /// Extended family.
     pub extended_family: u8,
@@ -762,9 +762,8 @@ pub fn configure_system_for_boot(
         use crate::cpu_config::x86_64::cpuid;
         let cpuid = cpuid::Cpuid::try_from(vmm.vm.supported_cpuid().clone())
             .map_err(GuestConfigError::CpuidFromKvmCpuid)?;
-        let msrs = vcpus[0]
-            .kvm_vcpu
-            .get_msrs(cpu_template.msr_index_iter())
+        let msrs = cpu_template
+            .read_msrs(|addrs| vcpus[0].kvm_vcpu.get_msrs(addrs.iter().copied()))
             .map_err(GuestConfigError::VcpuIoctl)?;
         CpuConfiguration { cpuid, msrs }
     };
@@ -788,8 +787,22 @@ pub fn configure_system_for_boot(
     };
 
     // Apply CPU template to the base CpuConfiguration.
+    #[cfg(all(target_arch = "x86_64", feature = "cpu-template-metrics"))]
+    let cpu_config = cpu_config
+        .apply_template_with_metrics(cpu_template, &crate::logger::METRICS.cpu_template)?;
+    #[cfg(not(all(target_arch = "x86_64", feature = "cpu-template-metrics")))]
     let cpu_config = CpuConfiguration::apply_template(cpu_config, cpu_template)?;
 
+    // Source MSRs of the template are only read, they are not set unless the template also
+    // modifies them.
+    #[cfg(target_arch = "x86_64")]
+    let cpu_config = {
+        let msr_index_set = cpu_template.msr_index_set();
+        let CpuConfiguration { cpuid, mut msrs } = cpu_config;
+        msrs.retain(|addr, _| msr_index_set.touches_msr(*addr));
+        CpuConfiguration { cpuid, msrs }
+    };
+
     let vcpu_config = VcpuConfig {
         vcpu_count: vm_config.vcpu_count,
         smt: vm_config.smt,
@@ -21,11 +21,15 @@ mod common_types {
 
 use std::borrow::Cow;
 use std::fmt::Debug;
+use std::marker::PhantomData;
 
 pub use common_types::*;
-use serde::de::Error as SerdeError;
+use serde::de::value::MapAccessDeserializer;
+use serde::de::{Error as SerdeError, MapAccess, Visitor};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
+use crate::cpu_config::templates_serde::deserialize_from_str_u128;
+
 /// Error for GetCpuTemplate trait.
 #[derive(Debug, thiserror::Error, displaydoc::Display, PartialEq, Eq)]
 pub enum GetCpuTemplateError {
@@ -175,6 +179,98 @@ where
     pub fn apply(&self, value: V) -> V {
         (value & !self.filter) | self.value
     }
+
+    /// Creates a filter from a `(filter, value)` pair, rejecting values with bits set outside
+    /// of the filter.
+    pub fn checked_from((filter, value): (V, V)) -> Option<Self> {
+        ((value & !filter) == V::zero()).then_some(RegisterValueFilter { filter, value })
+    }
+
+    /// Combines two filters into one that has the same effect as applying `self` and then
+    /// `other`. Bits covered by `other` take precedence over bits covered by `self`.
+    #[inline]
+    pub fn compose(&self, other: &Self) -> Self {
+        RegisterValueFilter {
+            filter: self.filter | other.filter,
+            value: (self.value & !other.filter) | other.value,
+        }
+    }
+
+    /// Checks if the filter pins every bit of a register of `width` bits, e.g. 32 for CPUID
+    /// registers and 64 for MSRs.
+    pub fn is_full(&self, width: usize) -> bool {
+        (0..V::BITS).take(width).all(|i| self.filter.bit(i))
+    }
+
+    /// Checks if the filter has no effect, i.e. it consists only of don't-care bits.
+    pub fn is_noop(&self) -> bool {
+        self.filter == V::zero() && self.value == V::zero()
+    }
+
+    /// Returns the filter with every bit from `width` up cleared in both the filter and the value,
+    /// i.e. don't-care, e.g. to use a 64 bit MSR filter for a 32 bit CPUID register.
+    pub fn clamped_to_width(self, width: u32) -> Self {
+        if width >= V::BITS {
+            return self;
+        }
+        let mask = !(!V::zero() << width);
+        RegisterValueFilter {
+            filter: self.filter & mask,
+            value: self.value & mask,
+        }
+    }
+
+    /// Pins bit `idx` to 1.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `idx` is not lower than the number of bits of `V`.
+    pub fn set_bit(&mut self, idx: u32) {
+        let mask = Self::bit_mask(idx);
+        self.filter |= mask;
+        self.value |= mask;
+    }
+
+    /// Pins bit `idx` to 0.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `idx` is not lower than the number of bits of `V`.
+    pub fn clear_bit(&mut self, idx: u32) {
+        let mask = Self::bit_mask(idx);
+        self.filter |= mask;
+        self.value = self.value & !mask;
+    }
+
+    /// Leaves bit `idx` unchanged when the filter is applied.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `idx` is not lower than the number of bits of `V`.
+    pub fn dont_care(&mut self, idx: u32) {
+        let mask = Self::bit_mask(idx);
+        self.filter = self.filter & !mask;
+        self.value = self.value & !mask;
+    }
+
+    fn bit_mask(idx: u32) -> V {
+        assert!(
+            idx < V::BITS,
+            "Bit index {idx} out of range for {} bits",
+            V::BITS
+        );
+        V::one() << idx
+    }
+}
+
+impl<V> From<(V, V)> for RegisterValueFilter<V>
+where
+    V: Numeric,
+{
+    /// Creates a filter from a `(filter, value)` pair, without any validation.
+    fn from((filter, value): (V, V)) -> Self {
+        RegisterValueFilter { filter, value }
+    }
 }
 
 impl<V> Serialize for RegisterValueFilter<V>
@@ -208,36 +304,74 @@ where
     }
 }
 
-impl<'de, V> Deserialize<'de> for RegisterValueFilter<V>
+/// Object form of a [`RegisterValueFilter`], with `filter` and `value` written as numbers
+/// prefixed with `0x` or `0b`, e.g. `{"filter": "0xf0", "value": "0x50"}`.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RegisterValueFilterObject {
+    #[serde(deserialize_with = "deserialize_from_str_u128")]
+    filter: u128,
+    #[serde(deserialize_with = "deserialize_from_str_u128")]
+    value: u128,
+}
+
+/// Converts `value` to `V`, or returns `None` if it has bits set above `V::BITS`.
+fn numeric_from_u128<V: Numeric>(value: u128) -> Option<V> {
+    (0..u128::BITS)
+        .filter(|i| value & (1 << i) != 0)
+        .try_fold(V::zero(), |result, i| {
+            (i < V::BITS).then(|| result | (V::one() << i))
+        })
+}
+
+struct RegisterValueFilterVisitor<V>(PhantomData<V>);
+
+impl<'de, V> Visitor<'de> for RegisterValueFilterVisitor<V>
 where
     V: Numeric + Debug,
 {
+    type Value = RegisterValueFilter<V>;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("a bitmap string or an object with a filter and a value")
+    }
+
     /// Deserialize a composite bitmap string into a value pair
     /// input string: "010x"
     /// result: {
     ///     filter: 1110
     ///     value: 0100
     /// }
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    fn visit_str<E>(self, original_str: &str) -> Result<Self::Value, E>
     where
-        D: Deserializer<'de>,
+        E: SerdeError,
     {
-        let original_str = <String as Deserialize>::deserialize(deserializer)?;
-
-        let stripped_str = original_str.strip_prefix("0b").unwrap_or(&original_str);
+        let stripped_str = original_str.strip_prefix("0b").unwrap_or(original_str);
+
+        // Single spaces may separate groups of bits, as emitted by some older tools. Leading,
+        // trailing or repeated spaces are most likely a mistake, so they are rejected.
+        if stripped_str.starts_with(' ')
+            || stripped_str.ends_with(' ')
+            || stripped_str.contains("  ")
+        {
+            return Err(E::custom(format!(
+                "Failed to parse string [{}] as a bitmap - spaces are only allowed between bits",
+                original_str
+            )));
+        }
 
         let (mut filter, mut value) = (V::zero(), V::zero());
         let mut i = 0;
         for s in stripped_str.as_bytes().iter().rev() {
             if V::BITS == i {
-                return Err(D::Error::custom(format!(
+                return Err(E::custom(format!(
                     "Failed to parse string [{}] as a bitmap - string is too long",
                     original_str
                 )));
             }
 
             match s {
-                b'_' => continue,
+                b'_' | b' ' => continue,
                 b'x' => {}
                 b'0' => {
                     filter |= V::one() << i;
@@ -247,7 +381,7 @@ where
                     value |= V::one() << i;
                 }
                 c => {
-                    return Err(D::Error::custom(format!(
+                    return Err(E::custom(format!(
                         "Failed to parse string [{}] as a bitmap - unknown character: {}",
                         original_str, c
                     )))
@@ -257,6 +391,43 @@ where
         }
         Ok(RegisterValueFilter { filter, value })
     }
+
+    /// Deserialize a `{"filter": .., "value": ..}` object into a value pair, rejecting values
+    /// with bits set outside of the filter.
+    fn visit_map<A>(self, map: A) -> Result<Self::Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let object = RegisterValueFilterObject::deserialize(MapAccessDeserializer::new(map))?;
+        let (Some(filter), Some(value)) = (
+            numeric_from_u128::<V>(object.filter),
+            numeric_from_u128::<V>(object.value),
+        ) else {
+            return Err(A::Error::custom(format!(
+                "Failed to parse bitmap object - filter and value must fit in {} bits",
+                V::BITS
+            )));
+        };
+        RegisterValueFilter::checked_from((filter, value)).ok_or_else(|| {
+            A::Error::custom(
+                "Failed to parse bitmap object - value has bits outside of the filter",
+            )
+        })
+    }
+}
+
+impl<'de, V> Deserialize<'de> for RegisterValueFilter<V>
+where
+    V: Numeric + Debug,
+{
+    /// Deserialize either a composite bitmap string or a `{"filter": .., "value": ..}` object
+    /// into a value pair
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(RegisterValueFilterVisitor(PhantomData))
+    }
 }
 
 /// Trait for numeric types
@@ -297,6 +468,12 @@ macro_rules! impl_numeric {
                 1
             }
         }
+
+        impl From<RegisterValueFilter<$type>> for ($type, $type) {
+            fn from(filter: RegisterValueFilter<$type>) -> Self {
+                (filter.filter, filter.value)
+            }
+        }
     };
 }
 
@@ -363,4 +540,179 @@ mod tests {
         let deserialized: Result<RegisterValueFilter<u8>, _> = serde_json::from_str(serialized);
         deserialized.unwrap_err();
     }
+
+    #[test]
+    fn test_register_value_filter_deserialize_object() {
+        let parse = |s: &str| serde_json::from_str::<RegisterValueFilter<u8>>(s);
+
+        let expected_rvf = RegisterValueFilter::<u8> {
+            filter: 0b11110000,
+            value: 0b01010000,
+        };
+        assert_eq!(
+            parse(r#"{"filter": "0xf0", "value": "0b01010000"}"#).unwrap(),
+            expected_rvf
+        );
+        assert_eq!(parse("\"0b0101xxxx\"").unwrap(), expected_rvf);
+
+        for serialized in [
+            // Value bits outside of the filter.
+            r#"{"filter": "0xf0", "value": "0x0f"}"#,
+            // Wider than the register.
+            r#"{"filter": "0x1f0", "value": "0x0"}"#,
+            r#"{"filter": "0xf0"}"#,
+            r#"{"filter": "0xf0", "value": "0x0", "mask": "0x0"}"#,
+            r#"{"filter": "240", "value": "0"}"#,
+        ] {
+            parse(serialized).unwrap_err();
+        }
+
+        let rvf = serde_json::from_str::<RegisterValueFilter<u128>>(
+            r#"{"filter": "0xffff0000000000000000000000000000", "value": "0x1"}"#,
+        );
+        rvf.unwrap_err();
+        let rvf = serde_json::from_str::<RegisterValueFilter<u128>>(
+            r#"{"filter": "0xffff0000000000000000000000000001", "value": "0x1"}"#,
+        )
+        .unwrap();
+        assert_eq!(rvf.filter, 0xffff_0000_0000_0000_0000_0000_0000_0001);
+    }
+
+    #[test]
+    fn test_register_value_filter_compose() {
+        let first = RegisterValueFilter::<u8> {
+            filter: 0b0000_1111,
+            value: 0b0000_0101,
+        };
+        let second = RegisterValueFilter::<u8> {
+            filter: 0b0011_1100,
+            value: 0b0010_1000,
+        };
+
+        let composed = first.compose(&second);
+        assert_eq!(
+            composed,
+            RegisterValueFilter {
+                filter: 0b0011_1111,
+                value: 0b0010_1001,
+            }
+        );
+        for value in 0..=u8::MAX {
+            assert_eq!(composed.apply(value), second.apply(first.apply(value)));
+        }
+    }
+
+    #[test]
+    fn test_register_value_filter_deserialize_spaced() {
+        let parse = |s: &str| serde_json::from_str::<RegisterValueFilter<u64>>(s);
+
+        let compact = parse("\"0b00001111xxxx\"").unwrap();
+        assert_eq!(parse("\"0000 1111 xxxx\"").unwrap(), compact);
+        assert_eq!(parse("\"0b0000 1111_xxxx\"").unwrap(), compact);
+
+        for serialized in [
+            "\" 0000 1111\"",
+            "\"0000 1111 \"",
+            "\"0000  1111\"",
+            "\"0b 0000\"",
+            "\"0000\\t1111\"",
+        ] {
+            parse(serialized).unwrap_err();
+        }
+    }
+
+    #[test]
+    fn test_register_value_filter_tuple_conversion() {
+        let rvf = RegisterValueFilter::<u64>::from((0b1100, 0b0110));
+        assert_eq!(
+            rvf,
+            RegisterValueFilter {
+                filter: 0b1100,
+                value: 0b0110,
+            }
+        );
+        assert_eq!(<(u64, u64)>::from(rvf), (0b1100, 0b0110));
+
+        assert_eq!(
+            RegisterValueFilter::<u64>::checked_from((0b1100, 0b0100)),
+            Some(RegisterValueFilter {
+                filter: 0b1100,
+                value: 0b0100,
+            })
+        );
+        assert_eq!(
+            RegisterValueFilter::<u64>::checked_from((0b1100, 0b0110)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_register_value_filter_build_by_bit() {
+        let mut rvf = RegisterValueFilter::<u64>::default();
+        rvf.set_bit(0);
+        rvf.set_bit(3);
+        rvf.clear_bit(1);
+        rvf.set_bit(63);
+        // Later calls override earlier ones for the same bit.
+        rvf.set_bit(5);
+        rvf.dont_care(5);
+        rvf.clear_bit(3);
+
+        let mut expected = String::from("\"0b1");
+        expected.push_str(&"x".repeat(59));
+        expected.push_str("0x01\"");
+        assert_eq!(
+            rvf,
+            serde_json::from_str::<RegisterValueFilter<u64>>(&expected).unwrap()
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Bit index 64 out of range for 64 bits")]
+    fn test_register_value_filter_set_bit_out_of_range() {
+        RegisterValueFilter::<u64>::default().set_bit(64);
+    }
+
+    #[test]
+    fn test_register_value_filter_is_full_is_noop() {
+        let full = RegisterValueFilter::<u64> {
+            filter: u64::MAX,
+            value: 0x1234,
+        };
+        assert!(full.is_full(64));
+        assert!(full.is_full(32));
+        assert!(!full.is_noop());
+
+        let partial = RegisterValueFilter::<u64> {
+            filter: 0xffff_ffff,
+            value: 0x0,
+        };
+        assert!(partial.is_full(32));
+        assert!(!partial.is_full(64));
+        assert!(!partial.is_noop());
+
+        let empty = RegisterValueFilter::<u64>::default();
+        assert!(!empty.is_full(32));
+        assert!(empty.is_noop());
+    }
+
+    #[test]
+    fn test_register_value_filter_clamped_to_width() {
+        let bitmap = RegisterValueFilter::<u64> {
+            filter: 0xffff_0000_ffff_00ff,
+            value: 0x1234_0000_5678_0012,
+        };
+        let clamped = bitmap.clamped_to_width(32);
+        assert_eq!(
+            clamped,
+            RegisterValueFilter {
+                filter: 0xffff_00ff,
+                value: 0x5678_0012,
+            }
+        );
+
+        assert_eq!(bitmap.clamped_to_width(64), bitmap);
+        assert_eq!(bitmap.clamped_to_width(128), bitmap);
+        assert_eq!(bitmap.clamped_to_width(0), RegisterValueFilter::default());
+    }
 }
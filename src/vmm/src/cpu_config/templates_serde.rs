@@ -48,6 +48,7 @@ macro_rules! deserialize_from_str {
 
 deserialize_from_str!(deserialize_from_str_u32, u32);
 deserialize_from_str!(deserialize_from_str_u64, u64);
+deserialize_from_str!(deserialize_from_str_u128, u128);
 
 #[cfg(test)]
 mod tests {
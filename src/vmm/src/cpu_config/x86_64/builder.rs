@@ -0,0 +1,175 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::cpu_config::templates::{CustomCpuTemplate, RegisterValueFilter};
+use crate::cpu_config::x86_64::cpuid::KvmCpuidFlags;
+use crate::cpu_config::x86_64::custom_cpu_template::{
+    CpuidLeafModifier, CpuidRegister, CpuidRegisterModifier, RegisterModifier,
+};
+use crate::cpu_config::x86_64::validation::ValidationContext;
+use crate::cpu_config::x86_64::TemplateError;
+
+/// Builder of custom CPU templates that are validated with
+/// [`CustomCpuTemplate::validate_all`] when built.
+///
+/// ```ignore
+/// let template = TemplateBuilder::new()
+///     .cpuid(0x1, 0x0, KvmCpuidFlags::EMPTY)
+///     .set(CpuidRegister::Ecx, RegisterValueFilter { filter: 0b1, value: 0b0 })
+///     .msr(0x10a, RegisterValueFilter { filter: 0b1000_0000, value: 0b0 })
+///     .build()?;
+/// ```
+#[derive(Debug, Default, Clone)]
+pub struct TemplateBuilder {
+    template: CustomCpuTemplate,
+    ctx: ValidationContext,
+}
+
+impl TemplateBuilder {
+    /// Create a builder of an empty template, validated in the default [`ValidationContext`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Validate the template in `ctx` when it is built.
+    pub fn validation_context(mut self, ctx: ValidationContext) -> Self {
+        self.ctx = ctx;
+        self
+    }
+
+    /// Start modifying the registers of CPUID `leaf` and `subleaf`.
+    pub fn cpuid(self, leaf: u32, subleaf: u32, flags: KvmCpuidFlags) -> CpuidLeafBuilder {
+        CpuidLeafBuilder {
+            builder: self,
+            leaf_modifier: CpuidLeafModifier {
+                leaf,
+                subleaf,
+                flags,
+                modifiers: vec![],
+            },
+        }
+    }
+
+    /// Modify the MSR at `addr` with `bitmap`.
+    pub fn msr(mut self, addr: u32, bitmap: RegisterValueFilter<u64>) -> Self {
+        self.template.msr_modifiers.push(RegisterModifier {
+            addr,
+            bitmap,
+            source: None,
+        });
+        self
+    }
+
+    /// Build the template, failing with every error found by
+    /// [`CustomCpuTemplate::validate_all`].
+    pub fn build(self) -> Result<CustomCpuTemplate, Vec<TemplateError>> {
+        self.template.validate_all(&self.ctx)?;
+        Ok(self.template)
+    }
+}
+
+/// Builder of the register modifiers of a CPUID leaf, see [`TemplateBuilder::cpuid`].
+#[derive(Debug, Clone)]
+pub struct CpuidLeafBuilder {
+    builder: TemplateBuilder,
+    leaf_modifier: CpuidLeafModifier,
+}
+
+impl CpuidLeafBuilder {
+    /// Modify `register` of the leaf with `bitmap`.
+    pub fn set(mut self, register: CpuidRegister, bitmap: RegisterValueFilter<u32>) -> Self {
+        self.leaf_modifier.modifiers.push(CpuidRegisterModifier {
+            register,
+            bitmap,
+            passthrough: false,
+        });
+        self
+    }
+
+    /// Finish the leaf and start modifying the registers of another one, see
+    /// [`TemplateBuilder::cpuid`].
+    pub fn cpuid(self, leaf: u32, subleaf: u32, flags: KvmCpuidFlags) -> CpuidLeafBuilder {
+        self.finish().cpuid(leaf, subleaf, flags)
+    }
+
+    /// Finish the leaf and modify an MSR, see [`TemplateBuilder::msr`].
+    pub fn msr(self, addr: u32, bitmap: RegisterValueFilter<u64>) -> TemplateBuilder {
+        self.finish().msr(addr, bitmap)
+    }
+
+    /// Finish the leaf and build the template, see [`TemplateBuilder::build`].
+    pub fn build(self) -> Result<CustomCpuTemplate, Vec<TemplateError>> {
+        self.finish().build()
+    }
+
+    /// Add the leaf to the template.
+    pub fn finish(mut self) -> TemplateBuilder {
+        self.builder
+            .template
+            .cpuid_modifiers
+            .push(self.leaf_modifier);
+        self.builder
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu_config::x86_64::test_utils::build_test_template;
+    use crate::cpu_config::x86_64::validation::{KvmCpuidFlagsError, NoopModifierPolicy};
+
+    #[test]
+    fn test_build() {
+        let expected = build_test_template();
+        let leaf_modifier = &expected.cpuid_modifiers[0];
+        let mut leaf_builder = TemplateBuilder::new().cpuid(
+            leaf_modifier.leaf,
+            leaf_modifier.subleaf,
+            leaf_modifier.flags,
+        );
+        for reg_modifier in &leaf_modifier.modifiers {
+            leaf_builder = leaf_builder.set(reg_modifier.register.clone(), reg_modifier.bitmap);
+        }
+        let template = leaf_builder
+            .msr(0x9999, RegisterValueFilter::default())
+            .msr(0x8000, RegisterValueFilter::default())
+            .build()
+            .unwrap();
+        assert_eq!(template, expected);
+
+        let template = TemplateBuilder::new()
+            .cpuid(0x1, 0x0, KvmCpuidFlags::EMPTY)
+            .set(CpuidRegister::Ecx, RegisterValueFilter::from((0b1, 0b0)))
+            .cpuid(0x7, 0x0, KvmCpuidFlags::SIGNIFICANT_INDEX)
+            .build()
+            .unwrap();
+        assert_eq!(template.cpuid_modifiers.len(), 2);
+        assert!(template.cpuid_modifiers[1].modifiers.is_empty());
+        assert!(template.msr_modifiers.is_empty());
+    }
+
+    #[test]
+    fn test_build_invalid() {
+        // Leaf 0x7 requires the SIGNIFICANT_INDEX flag.
+        let errors = TemplateBuilder::new()
+            .cpuid(0x7, 0x0, KvmCpuidFlags::EMPTY)
+            .set(CpuidRegister::Ebx, RegisterValueFilter::from((0b1, 0b1)))
+            .build()
+            .unwrap_err();
+        assert_eq!(errors.len(), 1, "{errors:?}");
+        assert!(matches!(
+            errors[0],
+            TemplateError::KvmCpuidFlags(KvmCpuidFlagsError::MissingSignificantIndex(0x7))
+        ));
+
+        // Modifiers without effect are only rejected if the validation context says so.
+        let builder = TemplateBuilder::new().msr(0x10a, RegisterValueFilter::default());
+        builder.clone().build().unwrap();
+        let ctx = ValidationContext {
+            noop_modifier_policy: Some(NoopModifierPolicy::Error),
+            ..Default::default()
+        };
+        let errors = builder.validation_context(ctx).build().unwrap_err();
+        assert_eq!(errors.len(), 1, "{errors:?}");
+    }
+}
@@ -0,0 +1,92 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fmt::{Display, Formatter};
+
+use aws_lc_rs::digest;
+
+use crate::cpu_config::templates::CustomCpuTemplate;
+
+/// Versioned algorithm used to compute a [`TemplateChecksum`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumAlgorithm {
+    /// SHA-256 digest of the raw template bytes.
+    Sha256V1,
+}
+
+impl Display for ChecksumAlgorithm {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChecksumAlgorithm::Sha256V1 => write!(f, "sha256-v1"),
+        }
+    }
+}
+
+/// Checksum of the bytes a custom CPU template was loaded from.
+///
+/// Unlike comparing templates by their effect, the checksum changes with any change to the
+/// template file, including formatting, so it pins exactly what was loaded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TemplateChecksum {
+    /// Algorithm used to compute the digest.
+    pub algorithm: ChecksumAlgorithm,
+    /// Digest of the template bytes.
+    pub digest: Vec<u8>,
+}
+
+impl TemplateChecksum {
+    /// Check whether `bytes` match the checksum.
+    pub fn verify(&self, bytes: &[u8]) -> bool {
+        match self.algorithm {
+            ChecksumAlgorithm::Sha256V1 => {
+                digest::digest(&digest::SHA256, bytes).as_ref() == self.digest.as_slice()
+            }
+        }
+    }
+}
+
+impl Display for TemplateChecksum {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:", self.algorithm)?;
+        self.digest
+            .iter()
+            .try_for_each(|byte| write!(f, "{byte:02x}"))
+    }
+}
+
+impl CustomCpuTemplate {
+    /// Compute the checksum of the bytes of a custom CPU template using the latest algorithm.
+    pub fn checksum(bytes: &[u8]) -> TemplateChecksum {
+        TemplateChecksum {
+            algorithm: ChecksumAlgorithm::Sha256V1,
+            digest: digest::digest(&digest::SHA256, bytes).as_ref().to_vec(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu_config::x86_64::test_utils::TEST_TEMPLATE_JSON;
+
+    #[test]
+    fn test_checksum_known_digest() {
+        // Test vector from FIPS 180-2.
+        let checksum = CustomCpuTemplate::checksum(b"abc");
+        assert_eq!(checksum.algorithm, ChecksumAlgorithm::Sha256V1);
+        assert_eq!(
+            checksum.to_string(),
+            "sha256-v1:ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn test_checksum_verify() {
+        let checksum = CustomCpuTemplate::checksum(TEST_TEMPLATE_JSON.as_bytes());
+        assert!(checksum.verify(TEST_TEMPLATE_JSON.as_bytes()));
+
+        // Reformatting the template changes the checksum even if the template is the same.
+        let reformatted = TEST_TEMPLATE_JSON.replace("    ", "  ");
+        assert!(!checksum.verify(reformatted.as_bytes()));
+    }
+}
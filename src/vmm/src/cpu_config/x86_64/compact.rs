@@ -0,0 +1,207 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::mem::{size_of, size_of_val};
+
+use crate::cpu_config::templates::{CustomCpuTemplate, KvmCapability, RegisterValueFilter};
+use crate::cpu_config::x86_64::cpuid::KvmCpuidFlags;
+use crate::cpu_config::x86_64::custom_cpu_template::{
+    CpuidLeafModifier, CpuidRegisterModifier, MsrFieldSource, RegisterModifier,
+};
+
+/// CPUID leaf modifier of a [`CompactCpuTemplate`]. Its register modifiers are the next
+/// `num_modifiers` entries of the list shared by all leaves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct CompactCpuidLeaf {
+    leaf: u32,
+    subleaf: u32,
+    flags: KvmCpuidFlags,
+    num_modifiers: u32,
+}
+
+/// MSR modifier of a [`CompactCpuTemplate`], without its field source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct CompactMsrModifier {
+    addr: u32,
+    bitmap: RegisterValueFilter<u64>,
+}
+
+/// Compact form of a [`CustomCpuTemplate`], for keeping many templates in memory, e.g. in a
+/// cache. Lists are boxed slices without spare capacity, the CPUID register modifiers of all
+/// leaves share a single list, and the field sources of MSR modifiers are kept apart, as most
+/// modifiers do not have one.
+///
+/// Converting a template to the compact form and back gives an equal template.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompactCpuTemplate {
+    kvm_capabilities: Box<[KvmCapability]>,
+    cpuid_leaves: Box<[CompactCpuidLeaf]>,
+    cpuid_modifiers: Box<[CpuidRegisterModifier]>,
+    msr_modifiers: Box<[CompactMsrModifier]>,
+    /// Field sources of MSR modifiers, by index in `msr_modifiers`.
+    msr_sources: Box<[(usize, MsrFieldSource)]>,
+}
+
+impl CompactCpuTemplate {
+    /// Number of bytes used by the template, including its heap allocations.
+    pub fn memory_footprint(&self) -> usize {
+        size_of::<Self>()
+            + size_of_val(&*self.kvm_capabilities)
+            + size_of_val(&*self.cpuid_leaves)
+            + size_of_val(&*self.cpuid_modifiers)
+            + size_of_val(&*self.msr_modifiers)
+            + size_of_val(&*self.msr_sources)
+    }
+}
+
+impl CustomCpuTemplate {
+    /// Number of bytes used by the template, including its heap allocations, see
+    /// [`CompactCpuTemplate::memory_footprint`].
+    pub fn memory_footprint(&self) -> usize {
+        let cpuid_modifiers: usize = self
+            .cpuid_modifiers
+            .iter()
+            .map(|leaf_modifier| {
+                leaf_modifier.modifiers.capacity() * size_of::<CpuidRegisterModifier>()
+            })
+            .sum();
+        size_of::<Self>()
+            + self.kvm_capabilities.capacity() * size_of::<KvmCapability>()
+            + self.cpuid_modifiers.capacity() * size_of::<CpuidLeafModifier>()
+            + cpuid_modifiers
+            + self.msr_modifiers.capacity() * size_of::<RegisterModifier>()
+    }
+}
+
+impl From<&CustomCpuTemplate> for CompactCpuTemplate {
+    fn from(template: &CustomCpuTemplate) -> Self {
+        CompactCpuTemplate {
+            kvm_capabilities: template.kvm_capabilities.iter().cloned().collect(),
+            cpuid_leaves: template
+                .cpuid_modifiers
+                .iter()
+                .map(|leaf_modifier| CompactCpuidLeaf {
+                    leaf: leaf_modifier.leaf,
+                    subleaf: leaf_modifier.subleaf,
+                    flags: leaf_modifier.flags,
+                    num_modifiers: u32::try_from(leaf_modifier.modifiers.len())
+                        .expect("Too many register modifiers in a CPUID leaf"),
+                })
+                .collect(),
+            cpuid_modifiers: template
+                .cpuid_modifiers
+                .iter()
+                .flat_map(|leaf_modifier| leaf_modifier.modifiers.iter().cloned())
+                .collect(),
+            msr_modifiers: template
+                .msr_modifiers
+                .iter()
+                .map(|modifier| CompactMsrModifier {
+                    addr: modifier.addr,
+                    bitmap: modifier.bitmap,
+                })
+                .collect(),
+            msr_sources: template
+                .msr_modifiers
+                .iter()
+                .enumerate()
+                .filter_map(|(index, modifier)| Some((index, modifier.source?)))
+                .collect(),
+        }
+    }
+}
+
+impl From<&CompactCpuTemplate> for CustomCpuTemplate {
+    fn from(compact: &CompactCpuTemplate) -> Self {
+        let mut reg_modifiers = compact.cpuid_modifiers.iter();
+        let mut msr_modifiers: Vec<_> = compact
+            .msr_modifiers
+            .iter()
+            .map(|modifier| RegisterModifier {
+                addr: modifier.addr,
+                bitmap: modifier.bitmap,
+                source: None,
+            })
+            .collect();
+        for (index, source) in compact.msr_sources.iter() {
+            msr_modifiers[*index].source = Some(*source);
+        }
+
+        CustomCpuTemplate {
+            kvm_capabilities: compact.kvm_capabilities.to_vec(),
+            cpuid_modifiers: compact
+                .cpuid_leaves
+                .iter()
+                .map(|leaf| CpuidLeafModifier {
+                    leaf: leaf.leaf,
+                    subleaf: leaf.subleaf,
+                    flags: leaf.flags,
+                    modifiers: reg_modifiers
+                        .by_ref()
+                        .take(leaf.num_modifiers as usize)
+                        .cloned()
+                        .collect(),
+                })
+                .collect(),
+            msr_modifiers,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu_config::x86_64::custom_cpu_template::CpuidRegister;
+    use crate::cpu_config::x86_64::static_cpu_templates::{c3, t2, t2a, t2cl, t2s};
+    use crate::cpu_config::x86_64::test_utils::build_test_template;
+
+    fn realistic_templates() -> Vec<CustomCpuTemplate> {
+        let mut template = build_test_template();
+        template.kvm_capabilities = vec![KvmCapability::Add(1), KvmCapability::Remove(2)];
+        template.cpuid_modifiers[0].modifiers.push(CpuidRegisterModifier {
+            register: CpuidRegister::Edx,
+            bitmap: RegisterValueFilter::default(),
+            passthrough: true,
+        });
+        template.msr_modifiers[1].source = Some(MsrFieldSource {
+            msr: 0x10a,
+            bits: [0, 3],
+            default: Some(0xf),
+        });
+
+        vec![
+            c3::c3(),
+            t2::t2(),
+            t2s::t2s(),
+            t2cl::t2cl(),
+            t2a::t2a(),
+            template,
+            CustomCpuTemplate::default(),
+        ]
+    }
+
+    #[test]
+    fn test_compact_template_lossless() {
+        for template in realistic_templates() {
+            let compact = CompactCpuTemplate::from(&template);
+            assert_eq!(CustomCpuTemplate::from(&compact), template);
+        }
+    }
+
+    #[test]
+    fn test_compact_template_memory_footprint() {
+        let templates = realistic_templates();
+        let footprint: usize = templates
+            .iter()
+            .map(CustomCpuTemplate::memory_footprint)
+            .sum();
+        let compact_footprint: usize = templates
+            .iter()
+            .map(|template| CompactCpuTemplate::from(template).memory_footprint())
+            .sum();
+        assert!(
+            compact_footprint < footprint,
+            "{compact_footprint} >= {footprint}"
+        );
+    }
+}
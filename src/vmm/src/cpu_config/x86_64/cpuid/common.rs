@@ -3,7 +3,7 @@
 #![allow(clippy::restriction)]
 
 /// Error type for [`get_cpuid`].
-#[derive(Debug, thiserror::Error, displaydoc::Display, PartialEq, Eq)]
+#[derive(Debug, Clone, thiserror::Error, displaydoc::Display, PartialEq, Eq)]
 pub enum GetCpuidError {
     /// Un-supported leaf: {0}
     UnsupportedLeaf(u32),
@@ -4,74 +4,38 @@
 /// Guest config sub-module specifically useful for
 /// config templates.
 use std::borrow::Cow;
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt::Debug;
+use std::ops::{Add, AddAssign};
 
-use serde::de::Error as SerdeError;
+use serde::de::{Error as SerdeError, IntoDeserializer, Visitor};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 use crate::arch::x86_64::cpu_model::CpuModel;
 use crate::cpu_config::templates::{
-    CpuTemplateType, GetCpuTemplate, GetCpuTemplateError, KvmCapability, RegisterValueFilter,
+    CpuTemplateType, GetCpuTemplate, GetCpuTemplateError, KvmCapability, Numeric,
+    RegisterValueFilter,
 };
 use crate::cpu_config::templates_serde::*;
 use crate::cpu_config::x86_64::cpuid::common::get_vendor_id_from_host;
 use crate::cpu_config::x86_64::cpuid::{KvmCpuidFlags, VENDOR_ID_AMD, VENDOR_ID_INTEL};
+use crate::cpu_config::x86_64::drift::cpuid_register_value;
+use crate::cpu_config::x86_64::host::HostSnapshot;
 use crate::cpu_config::x86_64::static_cpu_templates::{c3, t2, t2a, t2cl, t2s, StaticCpuTemplate};
+use crate::cpu_config::x86_64::{CpuConfiguration, CpuConfigurationError};
 use crate::logger::warn;
 
 impl GetCpuTemplate for Option<CpuTemplateType> {
     fn get_cpu_template(&self) -> Result<Cow<CustomCpuTemplate>, GetCpuTemplateError> {
-        use GetCpuTemplateError::*;
-
         match self {
             Some(template_type) => match template_type {
                 CpuTemplateType::Custom(template) => Ok(Cow::Borrowed(template)),
                 CpuTemplateType::Static(template) => {
-                    let vendor_id = get_vendor_id_from_host().map_err(GetCpuVendor)?;
-                    match template {
-                        StaticCpuTemplate::C3 => {
-                            if &vendor_id != VENDOR_ID_INTEL {
-                                return Err(CpuVendorMismatched);
-                            }
-                            if !CpuModel::get_cpu_model().is_at_least_cascade_lake() {
-                                warn!(
-                                    "On processors that do not enumerate FBSDP_NO, PSDP_NO and \
-                                     SBDR_SSDP_NO on IA32_ARCH_CAPABILITIES MSR, the guest kernel \
-                                     does not apply the mitigation against MMIO stale data \
-                                     vulnerability."
-                                );
-                            }
-                            Ok(Cow::Owned(c3::c3()))
-                        }
-                        StaticCpuTemplate::T2 => {
-                            if &vendor_id != VENDOR_ID_INTEL {
-                                return Err(CpuVendorMismatched);
-                            }
-                            Ok(Cow::Owned(t2::t2()))
-                        }
-                        StaticCpuTemplate::T2S => {
-                            if &vendor_id != VENDOR_ID_INTEL {
-                                return Err(CpuVendorMismatched);
-                            }
-                            Ok(Cow::Owned(t2s::t2s()))
-                        }
-                        StaticCpuTemplate::T2CL => {
-                            if &vendor_id != VENDOR_ID_INTEL {
-                                return Err(CpuVendorMismatched);
-                            } else if !CpuModel::get_cpu_model().is_at_least_cascade_lake() {
-                                return Err(InvalidCpuModel);
-                            }
-                            Ok(Cow::Owned(t2cl::t2cl()))
-                        }
-                        StaticCpuTemplate::T2A => {
-                            if &vendor_id != VENDOR_ID_AMD {
-                                return Err(CpuVendorMismatched);
-                            }
-                            Ok(Cow::Owned(t2a::t2a()))
-                        }
-                        StaticCpuTemplate::None => {
-                            Err(InvalidStaticCpuTemplate(StaticCpuTemplate::None))
-                        }
-                    }
+                    let vendor_id =
+                        get_vendor_id_from_host().map_err(GetCpuTemplateError::GetCpuVendor)?;
+                    static_cpu_template(*template, &vendor_id, &CpuModel::get_cpu_model())
+                        .map(Cow::Owned)
                 }
             },
             None => Ok(Cow::Owned(CustomCpuTemplate::default())),
@@ -79,6 +43,236 @@ impl GetCpuTemplate for Option<CpuTemplateType> {
     }
 }
 
+/// Get the custom CPU template of a static CPU template for a host with the given CPU vendor
+/// and model.
+pub(crate) fn static_cpu_template(
+    template: StaticCpuTemplate,
+    vendor_id: &[u8; 12],
+    cpu_model: &CpuModel,
+) -> Result<CustomCpuTemplate, GetCpuTemplateError> {
+    use GetCpuTemplateError::*;
+
+    match template {
+        StaticCpuTemplate::C3 => {
+            if vendor_id != VENDOR_ID_INTEL {
+                return Err(CpuVendorMismatched);
+            }
+            if !cpu_model.is_at_least_cascade_lake() {
+                warn!(
+                    "On processors that do not enumerate FBSDP_NO, PSDP_NO and SBDR_SSDP_NO on \
+                     IA32_ARCH_CAPABILITIES MSR, the guest kernel does not apply the mitigation \
+                     against MMIO stale data vulnerability."
+                );
+            }
+            Ok(c3::c3())
+        }
+        StaticCpuTemplate::T2 => {
+            if vendor_id != VENDOR_ID_INTEL {
+                return Err(CpuVendorMismatched);
+            }
+            Ok(t2::t2())
+        }
+        StaticCpuTemplate::T2S => {
+            if vendor_id != VENDOR_ID_INTEL {
+                return Err(CpuVendorMismatched);
+            }
+            Ok(t2s::t2s())
+        }
+        StaticCpuTemplate::T2CL => {
+            if vendor_id != VENDOR_ID_INTEL {
+                return Err(CpuVendorMismatched);
+            } else if !cpu_model.is_at_least_cascade_lake() {
+                return Err(InvalidCpuModel);
+            }
+            Ok(t2cl::t2cl())
+        }
+        StaticCpuTemplate::T2A => {
+            if vendor_id != VENDOR_ID_AMD {
+                return Err(CpuVendorMismatched);
+            }
+            Ok(t2a::t2a())
+        }
+        StaticCpuTemplate::None => Err(InvalidStaticCpuTemplate(StaticCpuTemplate::None)),
+    }
+}
+
+/// Resolve a CPU template for each of `requests`, like
+/// [`GetCpuTemplate::get_cpu_template`]. The CPU vendor and model of the host are queried at
+/// most once for all requests.
+pub fn resolve_all(
+    requests: &[Option<CpuTemplateType>],
+) -> Vec<Result<Cow<CustomCpuTemplate>, GetCpuTemplateError>> {
+    let mut host = None;
+    requests
+        .iter()
+        .map(|request| match request {
+            Some(CpuTemplateType::Static(template)) => {
+                let (vendor_id, cpu_model) = host.get_or_insert_with(|| {
+                    (get_vendor_id_from_host(), CpuModel::get_cpu_model())
+                });
+                let vendor_id = vendor_id
+                    .clone()
+                    .map_err(GetCpuTemplateError::GetCpuVendor)?;
+                static_cpu_template(*template, &vendor_id, cpu_model).map(Cow::Owned)
+            }
+            request => request.get_cpu_template(),
+        })
+        .collect()
+}
+
+/// CPU template resolved by [`get_cpu_template_with_fallback`].
+#[derive(Debug, PartialEq, Eq)]
+pub struct FallbackCpuTemplate<'a> {
+    /// Resolved CPU template.
+    pub template: Cow<'a, CustomCpuTemplate>,
+    /// Whether the requested static CPU template was replaced by a compatible one.
+    pub downgraded: bool,
+}
+
+/// Resolve a CPU template like [`GetCpuTemplate::get_cpu_template`], but fall back to the nearest
+/// compatible static CPU template if the requested one is not permitted on the current CPU model.
+pub fn get_cpu_template_with_fallback(
+    cpu_template: &Option<CpuTemplateType>,
+) -> Result<FallbackCpuTemplate, GetCpuTemplateError> {
+    let static_template = match cpu_template.get_cpu_template() {
+        Ok(template) => {
+            return Ok(FallbackCpuTemplate {
+                template,
+                downgraded: false,
+            })
+        }
+        Err(GetCpuTemplateError::InvalidCpuModel) => match cpu_template {
+            Some(CpuTemplateType::Static(static_template)) => *static_template,
+            _ => return Err(GetCpuTemplateError::InvalidCpuModel),
+        },
+        Err(err) => return Err(err),
+    };
+
+    let mut candidate = static_template.fallback();
+    while let Some(fallback_template) = candidate {
+        match Some(CpuTemplateType::Static(fallback_template)).get_cpu_template() {
+            Ok(template) => {
+                warn!(
+                    "{static_template} CPU template is not supported on the current CPU model, \
+                     using {fallback_template} CPU template instead."
+                );
+                return Ok(FallbackCpuTemplate {
+                    template: Cow::Owned(template.into_owned()),
+                    downgraded: true,
+                });
+            }
+            Err(GetCpuTemplateError::InvalidCpuModel) => candidate = fallback_template.fallback(),
+            Err(err) => return Err(err),
+        }
+    }
+    Err(GetCpuTemplateError::InvalidCpuModel)
+}
+
+/// Check whether static CPU templates `a` and `b` produce the same CPUID and MSR values when
+/// applied to a vCPU of `host`. [`StaticCpuTemplate::None`] leaves the host configuration
+/// unchanged, and a template that cannot be used on `host` never equals another one.
+pub fn static_templates_equal_on_host(
+    a: StaticCpuTemplate,
+    b: StaticCpuTemplate,
+    host: &HostSnapshot,
+) -> bool {
+    let host_config = CpuConfiguration {
+        cpuid: host.cpuid.clone(),
+        msrs: host.msrs.iter().map(|(addr, value)| (*addr, *value)).collect(),
+    };
+    let apply = |template: StaticCpuTemplate| {
+        let template = match template {
+            StaticCpuTemplate::None => CustomCpuTemplate::default(),
+            template => static_cpu_template(template, &host.vendor, &host.model).ok()?,
+        };
+        host_config.clone().apply_template(&template).ok()
+    };
+
+    match (apply(a), apply(b)) {
+        (Some(config_a), Some(config_b)) => config_a == config_b,
+        _ => false,
+    }
+}
+
+/// Errors associated with materializing a static CPU template on a host, see
+/// [`CustomCpuTemplate::materialize_static`].
+#[derive(Debug, PartialEq, Eq, thiserror::Error, displaydoc::Display)]
+pub enum MaterializeError {
+    /// Failed to resolve static CPU template: {0}
+    Static(#[from] GetCpuTemplateError),
+    /// Failed to apply static CPU template to the host: {0}
+    Apply(#[from] CpuConfigurationError),
+}
+
+impl CustomCpuTemplate {
+    /// Get a custom template that sets every bit of each CPUID register and MSR modified by
+    /// `template` to the value `template` gives it on `host`, i.e. without don't-care bits.
+    /// Applying the result to `host` gives the same CPUID and MSR values as applying `template`.
+    ///
+    /// Modifiers of all sub-leaves, see [`SUBLEAF_WILDCARD`], are expanded to the sub-leaves of
+    /// `host`. Passthrough CPUID register modifiers leave their register out.
+    pub fn materialize_static(
+        template: StaticCpuTemplate,
+        host: &HostSnapshot,
+    ) -> Result<CustomCpuTemplate, MaterializeError> {
+        let static_template = static_cpu_template(template, &host.vendor, &host.model)?;
+        let host_config = CpuConfiguration {
+            cpuid: host.cpuid.clone(),
+            msrs: host.msrs.iter().map(|(addr, value)| (*addr, *value)).collect(),
+        };
+        let config = host_config.apply_template(&static_template)?;
+
+        let mut materialized = CustomCpuTemplate {
+            kvm_capabilities: static_template.kvm_capabilities.clone(),
+            ..Default::default()
+        };
+        for leaf_modifier in &static_template.cpuid_modifiers {
+            let entries = config.cpuid.inner().iter().filter(|(key, _)| {
+                key.leaf == leaf_modifier.leaf
+                    && (leaf_modifier.subleaf == SUBLEAF_WILDCARD
+                        || key.subleaf == leaf_modifier.subleaf)
+            });
+            for (key, entry) in entries {
+                let modifiers = leaf_modifier
+                    .modifiers
+                    .iter()
+                    .filter(|reg_modifier| !reg_modifier.passthrough)
+                    .map(|reg_modifier| CpuidRegisterModifier {
+                        register: reg_modifier.register.clone(),
+                        bitmap: RegisterValueFilter {
+                            filter: u32::MAX,
+                            value: cpuid_register_value(&entry.result, &reg_modifier.register),
+                        },
+                        passthrough: false,
+                    })
+                    .collect();
+                materialized.cpuid_modifiers.push(CpuidLeafModifier {
+                    leaf: key.leaf,
+                    subleaf: key.subleaf,
+                    flags: leaf_modifier.flags,
+                    modifiers,
+                });
+            }
+        }
+        for addr in static_template.msr_index_iter() {
+            // Applying the template fails if it modifies an MSR the host does not have.
+            if let Some(value) = config.msrs.get(&addr) {
+                materialized.msr_modifiers.push(RegisterModifier {
+                    addr,
+                    bitmap: RegisterValueFilter {
+                        filter: u64::MAX,
+                        value: *value,
+                    },
+                    source: None,
+                });
+            }
+        }
+
+        materialized.coalesce();
+        Ok(materialized)
+    }
+}
+
 /// CPUID register enumeration
 #[allow(missing_docs)]
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, Hash, Ord, PartialOrd)]
@@ -90,19 +284,140 @@ pub enum CpuidRegister {
 }
 
 /// Target register to be modified by a bitmap.
+///
+/// In templates, the bitmap can be combined with an optional `op` (see [`CpuidRegisterOp`]),
+/// which is translated to a plain bitmap on deserialization.
+///
+/// A register can also be marked as intentionally left unchanged with
+/// `{ "register": "ecx", "passthrough": true }`, which applies as a bitmap of only don't-care
+/// bits but is not reported as a mistake.
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, Hash)]
+#[serde(try_from = "SerializedCpuidRegisterModifier")]
 pub struct CpuidRegisterModifier {
     /// CPUID register to be modified by the bitmap.
-    #[serde(
-        deserialize_with = "deserialize_cpuid_register",
-        serialize_with = "serialize_cpuid_register"
-    )]
+    #[serde(serialize_with = "serialize_cpuid_register")]
     pub register: CpuidRegister,
     /// Bit mapping to be applied as a modifier to the
     /// register's value at the address provided.
     pub bitmap: RegisterValueFilter<u32>,
+    /// Whether the register is documented as intentionally left unchanged, in which case the
+    /// bitmap has only don't-care bits.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub passthrough: bool,
+}
+
+/// Operation used to modify a CPUID register in templates.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CpuidRegisterOp {
+    /// Set the bits of the register to the bitmap, leaving the bits marked `x` unchanged.
+    #[default]
+    Set,
+    /// Set the bits that are 1 in the bitmap, leaving the other bits unchanged.
+    Or,
+    /// Clear the bits that are not 1 in the bitmap, leaving the other bits unchanged.
+    And,
 }
 
+/// Serialized form of a [`CpuidRegisterModifier`].
+#[derive(Debug, Deserialize)]
+struct SerializedCpuidRegisterModifier {
+    #[serde(deserialize_with = "deserialize_cpuid_register")]
+    register: CpuidRegister,
+    #[serde(default)]
+    op: CpuidRegisterOp,
+    bitmap: Option<RegisterValueFilter<u32>>,
+    #[serde(default)]
+    passthrough: bool,
+}
+
+impl TryFrom<SerializedCpuidRegisterModifier> for CpuidRegisterModifier {
+    type Error = String;
+
+    fn try_from(modifier: SerializedCpuidRegisterModifier) -> Result<Self, Self::Error> {
+        if modifier.passthrough {
+            let sets_bits = modifier.bitmap.is_some_and(|bitmap| !bitmap.is_noop());
+            if sets_bits || modifier.op != CpuidRegisterOp::Set {
+                return Err(format!(
+                    "Passthrough modifier of CPUID register {:?} must not change any bit",
+                    modifier.register
+                ));
+            }
+            return Ok(CpuidRegisterModifier {
+                register: modifier.register,
+                bitmap: RegisterValueFilter::default(),
+                passthrough: true,
+            });
+        }
+
+        let Some(bitmap) = modifier.bitmap else {
+            return Err(String::from("missing field `bitmap`"));
+        };
+        let bits = bitmap.value;
+        let bitmap = match modifier.op {
+            CpuidRegisterOp::Set => bitmap,
+            CpuidRegisterOp::Or => RegisterValueFilter {
+                filter: bits,
+                value: bits,
+            },
+            CpuidRegisterOp::And => RegisterValueFilter {
+                filter: !bits,
+                value: 0,
+            },
+        };
+        Ok(CpuidRegisterModifier {
+            register: modifier.register,
+            bitmap,
+            passthrough: false,
+        })
+    }
+}
+
+/// Precomputed set of the MSRs modified by a CPU template, see
+/// [`CustomCpuTemplate::msr_index_set`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct MsrIndexSet(HashSet<u32>);
+
+impl MsrIndexSet {
+    /// Check if the template modifies the MSR at `addr`, in constant time.
+    pub fn touches_msr(&self, addr: u32) -> bool {
+        self.0.contains(&addr)
+    }
+}
+
+/// Errors associated with bitmaps that do not fit the width of the register they modify.
+#[derive(Debug, PartialEq, Eq, thiserror::Error, displaydoc::Display)]
+pub enum WidthError {
+    /// Bitmap of CPUID register {0:?} sets bits {1:#x} beyond the 32 bits of the register.
+    Cpuid(CpuidRegister, u64),
+}
+
+impl CpuidRegisterModifier {
+    /// Create a modifier of a CPUID register from a 64 bit bitmap, rejecting bitmaps that
+    /// filter or set any bit beyond the 32 bits of the register. Prefer this over building
+    /// the modifier from its fields when the bitmap is computed.
+    pub fn new_checked(
+        register: CpuidRegister,
+        bitmap: RegisterValueFilter<u64>,
+    ) -> Result<Self, WidthError> {
+        match (u32::try_from(bitmap.filter), u32::try_from(bitmap.value)) {
+            (Ok(filter), Ok(value)) => Ok(CpuidRegisterModifier {
+                register,
+                bitmap: RegisterValueFilter { filter, value },
+                passthrough: false,
+            }),
+            _ => {
+                let high_bits = (bitmap.filter | bitmap.value) & !u64::from(u32::MAX);
+                Err(WidthError::Cpuid(register, high_bits))
+            }
+        }
+    }
+}
+
+/// Sub-leaf value of a [`CpuidLeafModifier`] that applies to all sub-leaves of its leaf present
+/// on the host. Written as `"*"` in templates.
+pub const SUBLEAF_WILDCARD: u32 = u32::MAX;
+
 /// Composite type that holistically provides
 /// the location of a specific register being used
 /// in the context of a CPUID tree.
@@ -114,10 +429,10 @@ pub struct CpuidLeafModifier {
         serialize_with = "serialize_to_hex_str"
     )]
     pub leaf: u32,
-    /// Sub-Leaf value.
+    /// Sub-Leaf value, or [`SUBLEAF_WILDCARD`] to modify all sub-leaves of the leaf.
     #[serde(
-        deserialize_with = "deserialize_from_str_u32",
-        serialize_with = "serialize_to_hex_str"
+        deserialize_with = "deserialize_subleaf",
+        serialize_with = "serialize_subleaf"
     )]
     pub subleaf: u32,
     /// KVM feature flags for this leaf-subleaf.
@@ -127,6 +442,46 @@ pub struct CpuidLeafModifier {
     pub modifiers: Vec<CpuidRegisterModifier>,
 }
 
+/// Serialized form of a [`CpuidLeafModifier`], which can alternatively group the modifiers of
+/// several sub-leaves of the leaf by sub-leaf:
+///
+/// `{ "leaf": "0x7", "flags": 1, "subleaves": { "0x0": { "modifiers": [...] }, ... } }`
+///
+/// Each sub-leaf is expanded into its own [`CpuidLeafModifier`], which inherits the flags of the
+/// leaf unless the sub-leaf sets its own.
+#[derive(Debug, Deserialize)]
+struct SerializedCpuidLeafModifier {
+    #[serde(deserialize_with = "deserialize_from_str_u32")]
+    leaf: u32,
+    #[serde(default, deserialize_with = "deserialize_optional_subleaf")]
+    subleaf: Option<u32>,
+    #[serde(deserialize_with = "deserialize_kvm_cpuid_flags")]
+    flags: KvmCpuidFlags,
+    modifiers: Option<Vec<CpuidRegisterModifier>>,
+    subleaves: Option<BTreeMap<SerializedSubleaf, SerializedSubleafModifier>>,
+}
+
+/// Sub-leaf of the grouped form of a [`SerializedCpuidLeafModifier`].
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct SerializedSubleaf(u32);
+
+impl<'de> Deserialize<'de> for SerializedSubleaf {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserialize_subleaf(deserializer).map(SerializedSubleaf)
+    }
+}
+
+/// Modifiers of a sub-leaf in the grouped form of a [`SerializedCpuidLeafModifier`].
+#[derive(Debug, Deserialize)]
+struct SerializedSubleafModifier {
+    #[serde(default, deserialize_with = "deserialize_optional_kvm_cpuid_flags")]
+    flags: Option<KvmCpuidFlags>,
+    modifiers: Vec<CpuidRegisterModifier>,
+}
+
 /// Wrapper type to containing x86_64 CPU config modifiers.
 #[derive(Debug, Default, Clone, Eq, PartialEq, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
@@ -136,7 +491,7 @@ pub struct CustomCpuTemplate {
     #[serde(default)]
     pub kvm_capabilities: Vec<KvmCapability>,
     /// Modifiers for CPUID configuration.
-    #[serde(default)]
+    #[serde(default, deserialize_with = "deserialize_cpuid_modifiers")]
     pub cpuid_modifiers: Vec<CpuidLeafModifier>,
     /// Modifiers for model specific registers.
     #[serde(default)]
@@ -149,386 +504,3139 @@ impl CustomCpuTemplate {
         self.msr_modifiers.iter().map(|modifier| modifier.addr)
     }
 
-    /// Validate the correctness of the template.
-    pub fn validate(&self) -> Result<(), serde_json::Error> {
-        Ok(())
+    /// Get an iterator of the indices of the MSRs the CPU template copies fields from, see
+    /// [`RegisterModifier::source`].
+    pub fn msr_source_iter(&self) -> impl Iterator<Item = u32> + '_ {
+        self.msr_modifiers
+            .iter()
+            .filter_map(|modifier| Some(modifier.source?.msr))
     }
-}
-
-/// Wrapper of a mask defined as a bitmap to apply
-/// changes to a given register's value.
-#[derive(Debug, Default, Clone, Copy, Eq, PartialEq, Serialize, Deserialize, Hash)]
-pub struct RegisterModifier {
-    /// Pointer of the location to be bit mapped.
-    #[serde(
-        deserialize_with = "deserialize_from_str_u32",
-        serialize_with = "serialize_to_hex_str"
-    )]
-    pub addr: u32,
-    /// Bit mapping to be applied as a modifier to the
-    /// register's value at the address provided.
-    pub bitmap: RegisterValueFilter<u64>,
-}
 
-fn deserialize_kvm_cpuid_flags<'de, D>(deserializer: D) -> Result<KvmCpuidFlags, D::Error>
-where
-    D: Deserializer<'de>,
-{
-    let flag = u32::deserialize(deserializer)?;
-    Ok(KvmCpuidFlags(flag))
-}
+    /// Build the set of MSR indices that are modified by the CPU template, for constant time
+    /// [`MsrIndexSet::touches_msr`] checks on hot paths where
+    /// [`CustomCpuTemplate::msr_index_iter`] would be too slow.
+    ///
+    /// The set is not updated when the template changes, so it should be built once the
+    /// template is final and kept alongside it.
+    pub fn msr_index_set(&self) -> MsrIndexSet {
+        MsrIndexSet(self.msr_index_iter().collect())
+    }
 
-fn deserialize_cpuid_register<'de, D>(deserializer: D) -> Result<CpuidRegister, D::Error>
-where
-    D: Deserializer<'de>,
-{
-    let cpuid_register_str = String::deserialize(deserializer)?;
+    /// Get a copy of the template suitable for logging, in which the bitmaps of the modifiers of
+    /// the MSRs in `redact_addrs` are replaced with all don't-care bits. The addresses and order
+    /// of all modifiers are kept.
+    pub fn redacted(&self, redact_addrs: &HashSet<u32>) -> CustomCpuTemplate {
+        let mut template = self.clone();
+        for modifier in &mut template.msr_modifiers {
+            if redact_addrs.contains(&modifier.addr) {
+                modifier.bitmap = RegisterValueFilter::default();
+            }
+        }
+        template
+    }
 
-    Ok(match cpuid_register_str.as_str() {
-        "eax" => CpuidRegister::Eax,
-        "ebx" => CpuidRegister::Ebx,
-        "ecx" => CpuidRegister::Ecx,
-        "edx" => CpuidRegister::Edx,
-        _ => {
-            return Err(D::Error::custom(
-                "Invalid CPUID register. Must be one of [eax, ebx, ecx, edx]",
-            ))
+    /// Get a copy of the template without its MSR modifiers.
+    pub fn without_msrs(&self) -> CustomCpuTemplate {
+        CustomCpuTemplate {
+            msr_modifiers: vec![],
+            ..self.clone()
         }
-    })
-}
+    }
 
-fn serialize_cpuid_register<S>(cpuid_reg: &CpuidRegister, serializer: S) -> Result<S::Ok, S::Error>
-where
-    S: Serializer,
-{
-    match cpuid_reg {
-        CpuidRegister::Eax => serializer.serialize_str("eax"),
-        CpuidRegister::Ebx => serializer.serialize_str("ebx"),
-        CpuidRegister::Ecx => serializer.serialize_str("ecx"),
-        CpuidRegister::Edx => serializer.serialize_str("edx"),
+    /// Get a copy of the template without its CPUID modifiers.
+    pub fn without_cpuid(&self) -> CustomCpuTemplate {
+        CustomCpuTemplate {
+            cpuid_modifiers: vec![],
+            ..self.clone()
+        }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use serde_json::Value;
+    /// Rewrite the address of every MSR modifier and of every MSR copied from, see
+    /// [`RegisterModifier::source`], found in `map` to the address it maps to, e.g. to port a
+    /// template across a renumbering of synthetic MSRs. Addresses not in `map` are left as is.
+    ///
+    /// Fails without modifying the template if two different MSRs would end up at the same
+    /// address.
+    pub fn remap_msr_addrs(&mut self, map: &HashMap<u32, u32>) -> Result<(), MsrRemapError> {
+        let remap = |addr: u32| map.get(&addr).copied().unwrap_or(addr);
 
-    use super::*;
-    use crate::cpu_config::x86_64::test_utils::{build_test_template, TEST_TEMPLATE_JSON};
+        let mut sources = HashMap::new();
+        for modifier in &self.msr_modifiers {
+            let addr = remap(modifier.addr);
+            match sources.insert(addr, modifier.addr) {
+                Some(source) if source != modifier.addr => {
+                    return Err(MsrRemapError {
+                        first: source,
+                        second: modifier.addr,
+                        addr,
+                    })
+                }
+                _ => {}
+            }
+        }
+        for modifier in &mut self.msr_modifiers {
+            modifier.addr = remap(modifier.addr);
+            if let Some(source) = &mut modifier.source {
+                source.msr = remap(source.msr);
+            }
+        }
+        Ok(())
+    }
 
-    #[test]
-    fn test_get_cpu_template_with_no_template() {
-        // Test `get_cpu_template()` when no template is provided. The empty owned
-        // `CustomCpuTemplate` should be returned.
-        let cpu_template = None;
-        assert_eq!(
-            cpu_template.get_cpu_template().unwrap(),
-            Cow::Owned(CustomCpuTemplate::default()),
-        );
+    /// Retain only the MSR modifiers for which `f` returns `true`, like [`Vec::retain`].
+    pub fn retain_msrs(&mut self, f: impl FnMut(&RegisterModifier) -> bool) {
+        self.msr_modifiers.retain(f);
     }
 
-    #[test]
-    fn test_get_cpu_template_with_c3_static_template() {
-        // Test `get_cpu_template()` when C3 static CPU template is specified. The owned
-        // `CustomCpuTemplate` should be returned if CPU vendor is Intel. Otherwise, it should fail.
-        let cpu_template = Some(CpuTemplateType::Static(StaticCpuTemplate::C3));
-        if &get_vendor_id_from_host().unwrap() == VENDOR_ID_INTEL {
-            assert_eq!(
-                cpu_template.get_cpu_template().unwrap(),
-                Cow::Owned(c3::c3())
-            );
-        } else {
-            assert_eq!(
-                cpu_template.get_cpu_template().unwrap_err(),
-                GetCpuTemplateError::CpuVendorMismatched,
-            );
-        }
+    /// Retain only the CPUID leaf modifiers for which `f` returns `true`, like [`Vec::retain`].
+    pub fn retain_cpuid(&mut self, f: impl FnMut(&CpuidLeafModifier) -> bool) {
+        self.cpuid_modifiers.retain(f);
     }
 
-    #[test]
-    fn test_get_cpu_template_with_t2_static_template() {
-        // Test `get_cpu_template()` when T2 static CPU template is specified. The owned
-        // `CustomCpuTemplate` should be returned if CPU vendor is Intel. Otherwise, it should fail.
-        let cpu_template = Some(CpuTemplateType::Static(StaticCpuTemplate::T2));
-        if &get_vendor_id_from_host().unwrap() == VENDOR_ID_INTEL {
-            assert_eq!(
-                cpu_template.get_cpu_template().unwrap(),
-                Cow::Owned(t2::t2())
-            );
-        } else {
-            assert_eq!(
-                cpu_template.get_cpu_template().unwrap_err(),
-                GetCpuTemplateError::CpuVendorMismatched,
-            );
+    /// Validate the correctness of the template.
+    pub fn validate(&self) -> Result<(), serde_json::Error> {
+        // Bits of each MSR a field is copied into.
+        let mut fields = HashMap::new();
+        for modifier in &self.msr_modifiers {
+            if let Some(field) = fields.get(&modifier.addr) {
+                if modifier.source.is_some() || modifier.bitmap.filter & field != 0 {
+                    return Err(serde_json::Error::custom(
+                        MsrSourceError::FieldOverwritten(modifier.addr),
+                    ));
+                }
+            }
+            if let Some(source) = modifier.source {
+                if source.copy_into(modifier.bitmap.filter, 0).is_none() {
+                    return Err(serde_json::Error::custom(MsrSourceError::FieldMismatch(
+                        modifier.addr,
+                    )));
+                }
+                fields.insert(modifier.addr, modifier.bitmap.filter);
+            }
         }
+        // Modifiers without effect are allowed, but most likely a mistake.
+        for noop_modifier in self.noop_modifiers() {
+            warn!("CPU template: {noop_modifier}");
+        }
+        Ok(())
     }
 
-    #[test]
-    fn test_get_cpu_template_with_t2s_static_template() {
-        // Test `get_cpu_template()` when T2S static CPU template is specified. The owned
-        // `CustomCpuTemplate` should be returned if CPU vendor is Intel. Otherwise, it should fail.
-        let cpu_template = Some(CpuTemplateType::Static(StaticCpuTemplate::T2S));
-        if &get_vendor_id_from_host().unwrap() == VENDOR_ID_INTEL {
-            assert_eq!(
-                cpu_template.get_cpu_template().unwrap(),
-                Cow::Owned(t2s::t2s())
-            );
-        } else {
-            assert_eq!(
-                cpu_template.get_cpu_template().unwrap_err(),
-                GetCpuTemplateError::CpuVendorMismatched,
-            );
+    /// Check if the template has no effect, i.e. it has no KVM capabilities and all its
+    /// modifiers consist only of don't-care bits.
+    pub fn is_effectively_empty(&self) -> bool {
+        self.kvm_capabilities.is_empty()
+            && self.into_iter().all(|modifier| match modifier {
+                ModifierRef::Cpuid(_, reg_modifier) => reg_modifier.bitmap.is_noop(),
+                ModifierRef::Msr(modifier) => modifier.bitmap.is_noop(),
+            })
+    }
+
+    /// Get the effect of the template as a map from each targeted register to the filter applied
+    /// to it. Multiple modifiers targeting the same register are coalesced in the order they
+    /// appear in the template. The template is expected to be valid, see
+    /// [`CustomCpuTemplate::validate`].
+    pub fn effect_map(&self) -> EffectMap {
+        let mut effect_map = EffectMap::default();
+
+        for leaf_modifier in &self.cpuid_modifiers {
+            for reg_modifier in &leaf_modifier.modifiers {
+                let key = CpuidEffectKey {
+                    leaf: leaf_modifier.leaf,
+                    subleaf: leaf_modifier.subleaf,
+                    flags: leaf_modifier.flags,
+                    register: reg_modifier.register.clone(),
+                };
+                if reg_modifier.passthrough {
+                    effect_map.cpuid_passthrough.insert(key.clone());
+                }
+                effect_map
+                    .cpuid
+                    .entry(key)
+                    .and_modify(|bitmap| *bitmap = bitmap.compose(&reg_modifier.bitmap))
+                    .or_insert(reg_modifier.bitmap);
+            }
+        }
+
+        for modifier in &self.msr_modifiers {
+            effect_map
+                .msrs
+                .entry(modifier.addr)
+                .and_modify(|bitmap| *bitmap = bitmap.compose(&modifier.bitmap))
+                .or_insert(modifier.bitmap);
+            if modifier.source.is_some() {
+                effect_map.msr_sources.insert(modifier.addr, *modifier);
+            }
         }
+
+        effect_map
     }
 
-    #[test]
-    fn test_t2cl_template_equality() {
-        // For coverage purposes, this test forces usage of T2CL and bypasses
-        // validation that is generally applied which usually enforces that T2CL
-        // can only be used on Cascade Lake (or newer) CPUs.
-        let t2cl_custom_template = CpuTemplateType::Custom(t2cl::t2cl());
-        // This test also demonstrates the difference in concept between custom and static
-        // templates, while practically T2CL is consistent for the user, in code
-        // the static template of T2CL, and the custom template of T2CL are not equivalent.
-        assert_ne!(
-            t2cl_custom_template,
-            CpuTemplateType::Static(StaticCpuTemplate::T2CL)
-        );
+    /// Get the effect of the template on the CPUID entry with the given leaf, subleaf and flags,
+    /// as the coalesced filters of EAX, EBX, ECX and EDX, in this order. Registers the template
+    /// does not modify are `None`. Modifiers with [`SUBLEAF_WILDCARD`] only match a `subleaf` of
+    /// [`SUBLEAF_WILDCARD`].
+    pub fn effect_on_leaf(
+        &self,
+        leaf: u32,
+        subleaf: u32,
+        flags: KvmCpuidFlags,
+    ) -> [Option<RegisterValueFilter<u32>>; 4] {
+        let effect_map = self.effect_map();
+        [
+            CpuidRegister::Eax,
+            CpuidRegister::Ebx,
+            CpuidRegister::Ecx,
+            CpuidRegister::Edx,
+        ]
+        .map(|register| {
+            let key = CpuidEffectKey {
+                leaf,
+                subleaf,
+                flags,
+                register,
+            };
+            effect_map.cpuid.get(&key).copied()
+        })
     }
 
-    #[test]
+    /// Coalesce all modifiers targeting the same register into a single modifier and drop
+    /// modifiers that do not change any bit. Modifiers are sorted by the register they target.
+    ///
+    /// The values the template produces for every register are unchanged. A modifier with a
+    /// [`RegisterModifier::source`] is kept as it is, after the modifier of the other bits of
+    /// its MSR. A CPUID register with a modifier marked as passthrough, see
+    /// [`CpuidRegisterModifier::passthrough`], keeps a passthrough modifier if no other modifier
+    /// changes any of its bits.
+    pub fn coalesce(&mut self) {
+        self.set_effect_map(self.effect_map());
+    }
+
+    /// Replace all modifiers with one modifier per register of `effect_map`, dropping those that
+    /// do not change any bit unless they are marked as passthrough, followed by the modifiers in
+    /// `effect_map.msr_sources`.
+    fn set_effect_map(&mut self, effect_map: EffectMap) {
+        let mut cpuid_modifiers = Vec::<CpuidLeafModifier>::new();
+        for (key, bitmap) in effect_map.cpuid {
+            let passthrough = bitmap.is_noop();
+            if passthrough && !effect_map.cpuid_passthrough.contains(&key) {
+                continue;
+            }
+            let reg_modifier = CpuidRegisterModifier {
+                register: key.register,
+                bitmap,
+                passthrough,
+            };
+            match cpuid_modifiers.last_mut() {
+                Some(leaf_modifier)
+                    if leaf_modifier.leaf == key.leaf
+                        && leaf_modifier.subleaf == key.subleaf
+                        && leaf_modifier.flags == key.flags =>
+                {
+                    leaf_modifier.modifiers.push(reg_modifier)
+                }
+                _ => cpuid_modifiers.push(CpuidLeafModifier {
+                    leaf: key.leaf,
+                    subleaf: key.subleaf,
+                    flags: key.flags,
+                    modifiers: vec![reg_modifier],
+                }),
+            }
+        }
+
+        self.cpuid_modifiers = cpuid_modifiers;
+        self.msr_modifiers.clear();
+        for (addr, bitmap) in effect_map.msrs {
+            let source = effect_map.msr_sources.get(&addr).copied();
+            // The bits of a copied field are set by the modifier with the source.
+            let bitmap = without_field(&bitmap, source.as_ref());
+            if !bitmap.is_noop() {
+                self.msr_modifiers.push(RegisterModifier {
+                    addr,
+                    bitmap,
+                    source: None,
+                });
+            }
+            self.msr_modifiers.extend(source);
+        }
+    }
+
+    /// Get a template with only the effects this template and `other` agree on, i.e. the bits
+    /// both templates set to the same value in the same register, and the KVM capabilities
+    /// both templates change in the same way. A field copied into an MSR is only kept if both
+    /// templates copy it from the same source, see [`RegisterModifier::source`]. The modifiers
+    /// of the resulting template are coalesced, see [`CustomCpuTemplate::coalesce`].
+    pub fn intersect(&self, other: &CustomCpuTemplate) -> CustomCpuTemplate {
+        let effect_map = self.effect_map();
+        let other_effect_map = other.effect_map();
+
+        let mut intersection = EffectMap {
+            cpuid_passthrough: effect_map
+                .cpuid_passthrough
+                .intersection(&other_effect_map.cpuid_passthrough)
+                .cloned()
+                .collect(),
+            ..Default::default()
+        };
+        for (key, bitmap) in effect_map.cpuid {
+            if let Some(other_bitmap) = other_effect_map.cpuid.get(&key) {
+                intersection.cpuid.insert(key, agreement(&bitmap, other_bitmap));
+            }
+        }
+        for (addr, bitmap) in effect_map.msrs {
+            if let Some(other_bitmap) = other_effect_map.msrs.get(&addr) {
+                let mut bitmap = agreement(&bitmap, other_bitmap);
+                let source = effect_map.msr_sources.get(&addr);
+                let other_source = other_effect_map.msr_sources.get(&addr);
+                if source == other_source {
+                    if let Some(source) = source {
+                        intersection.msr_sources.insert(addr, *source);
+                    }
+                } else {
+                    // The values of bits in a copied field are not known until the template is
+                    // applied.
+                    bitmap = without_field(&bitmap, source);
+                    bitmap = without_field(&bitmap, other_source);
+                }
+                intersection.msrs.insert(addr, bitmap);
+            }
+        }
+
+        let mut template = CustomCpuTemplate {
+            kvm_capabilities: self
+                .kvm_capabilities
+                .iter()
+                .filter(|capability| other.kvm_capabilities.contains(capability))
+                .cloned()
+                .collect(),
+            ..Default::default()
+        };
+        template.set_effect_map(intersection);
+        template
+    }
+
+    /// Merge `other` into this template. Bits both templates set to different values are
+    /// resolved according to `strategy`, and all other bits set by either template are kept.
+    /// A field copied into an MSR, see [`RegisterModifier::source`], is kept as a whole or
+    /// overwritten as a whole, and the merge fails if that is not possible. The merge also fails
+    /// if one template modifies a CPUID register in all sub-leaves of a leaf, see
+    /// [`SUBLEAF_WILDCARD`], and the other in a single sub-leaf of the same leaf. KVM
+    /// capabilities of `other` not already in this template are appended. The modifiers of the
+    /// merged template are coalesced, see [`CustomCpuTemplate::coalesce`].
+    pub fn merge(
+        &self,
+        other: &CustomCpuTemplate,
+        strategy: MergeStrategy,
+    ) -> Result<CustomCpuTemplate, MergeConflictError> {
+        let mut effect_map = self.effect_map();
+        let other_effect_map = other.effect_map();
+
+        if let Some(key) = find_mixed_subleaves(&effect_map, &other_effect_map)
+            .or_else(|| find_mixed_subleaves(&other_effect_map, &effect_map))
+        {
+            return Err(MergeConflictError::SubleafWildcard {
+                leaf: key.leaf,
+                register: key.register.clone(),
+            });
+        }
+        effect_map
+            .cpuid_passthrough
+            .extend(other_effect_map.cpuid_passthrough);
+        for (key, bitmap) in other_effect_map.cpuid {
+            match effect_map.cpuid.entry(key) {
+                Entry::Vacant(entry) => {
+                    entry.insert(bitmap);
+                }
+                Entry::Occupied(mut entry) => {
+                    let merged = merge_bitmaps(entry.get(), &bitmap, strategy).map_err(|bits| {
+                        MergeConflictError::Cpuid {
+                            leaf: entry.key().leaf,
+                            subleaf: entry.key().subleaf,
+                            register: entry.key().register.clone(),
+                            bits,
+                        }
+                    })?;
+                    entry.insert(merged);
+                }
+            }
+        }
+        for (addr, bitmap) in other_effect_map.msrs {
+            let other_source = other_effect_map.msr_sources.get(&addr).copied();
+            match effect_map.msrs.entry(addr) {
+                Entry::Vacant(entry) => {
+                    entry.insert(bitmap);
+                    effect_map.msr_sources.extend(other_source.map(|source| (addr, source)));
+                }
+                Entry::Occupied(mut entry) => {
+                    let source = effect_map.msr_sources.remove(&addr);
+                    let source = merge_msr_sources(
+                        (source, entry.get()),
+                        (other_source, &bitmap),
+                        strategy,
+                    )
+                    .ok_or(MergeConflictError::MsrSource(addr))?;
+                    let merged = merge_bitmaps(entry.get(), &bitmap, strategy)
+                        .map_err(|bits| MergeConflictError::Msr { addr, bits })?;
+                    entry.insert(merged);
+                    effect_map.msr_sources.extend(source.map(|source| (addr, source)));
+                }
+            }
+        }
+
+        let mut kvm_capabilities = self.kvm_capabilities.clone();
+        for capability in &other.kvm_capabilities {
+            if !kvm_capabilities.contains(capability) {
+                kvm_capabilities.push(capability.clone());
+            }
+        }
+        let mut template = CustomCpuTemplate {
+            kvm_capabilities,
+            ..Default::default()
+        };
+        template.set_effect_map(effect_map);
+        Ok(template)
+    }
+
+    /// Get a copy of the template whose modifiers only affect bits `lo` to `hi`, inclusive. Bits
+    /// outside of the window become don't-care in every modifier. For CPUID registers, the
+    /// window is capped at bit 31.
+    pub fn masked_to_range(&self, lo: u32, hi: u32) -> CustomCpuTemplate {
+        let cpuid_mask = window_mask::<u32>(lo, hi);
+        let msr_mask = window_mask::<u64>(lo, hi);
+
+        let mut template = self.clone();
+        for leaf_modifier in &mut template.cpuid_modifiers {
+            for reg_modifier in &mut leaf_modifier.modifiers {
+                reg_modifier.bitmap.filter &= cpuid_mask;
+                reg_modifier.bitmap.value &= cpuid_mask;
+            }
+        }
+        for modifier in &mut template.msr_modifiers {
+            let filter = modifier.bitmap.filter & msr_mask;
+            if filter == 0 {
+                modifier.source = None;
+            }
+            if let Some(source) = &mut modifier.source {
+                // Narrow the source field along with the filter.
+                let lo = source.bits[0] + filter.trailing_zeros()
+                    - modifier.bitmap.filter.trailing_zeros();
+                source.bits = [lo, lo + filter.count_ones() - 1];
+            }
+            modifier.bitmap.filter = filter;
+            modifier.bitmap.value &= msr_mask;
+        }
+        template
+    }
+
+    /// Check if this template and `other` modify any common CPUID register or MSR, regardless of
+    /// the bits they modify. A [`SUBLEAF_WILDCARD`] modifier overlaps with every sub-leaf of the
+    /// same leaf.
+    pub fn overlaps(&self, other: &CustomCpuTemplate) -> bool {
+        let mut registers = HashSet::new();
+        let mut leaf_registers = HashSet::new();
+        for leaf_modifier in &self.cpuid_modifiers {
+            for reg_modifier in &leaf_modifier.modifiers {
+                let register = &reg_modifier.register;
+                registers.insert((leaf_modifier.leaf, leaf_modifier.subleaf, register));
+                leaf_registers.insert((leaf_modifier.leaf, register));
+            }
+        }
+
+        let cpuid_overlaps = other.into_iter().any(|modifier| {
+            let ModifierRef::Cpuid(leaf_modifier, reg_modifier) = modifier else {
+                return false;
+            };
+            let (leaf, register) = (leaf_modifier.leaf, &reg_modifier.register);
+            match leaf_modifier.subleaf {
+                SUBLEAF_WILDCARD => leaf_registers.contains(&(leaf, register)),
+                subleaf => {
+                    registers.contains(&(leaf, subleaf, register))
+                        || registers.contains(&(leaf, SUBLEAF_WILDCARD, register))
+                }
+            }
+        });
+        if cpuid_overlaps {
+            return true;
+        }
+
+        let msrs = self.msr_index_set();
+        other.msr_index_iter().any(|addr| msrs.touches_msr(addr))
+    }
+
+    /// Estimate the number of `KVM_SET_CPUID2` and `KVM_SET_MSRS` ioctls issued when applying
+    /// the template to `num_vcpus` vCPUs. Each vCPU gets all of its CPUID entries and all of its
+    /// MSRs in a single call of each kind, which is issued even if the template changes no
+    /// CPUID register or MSR, so the estimate is the same for every template.
+    pub fn kvm_op_estimate(&self, num_vcpus: usize) -> OpEstimate {
+        OpEstimate {
+            cpuid_ops: num_vcpus,
+            msr_ops: num_vcpus,
+        }
+    }
+
+    /// Get the number of modifiers, of both CPUID registers and MSRs, by the number of bits
+    /// they filter.
+    pub fn modifier_histogram(&self) -> BTreeMap<u32, usize> {
+        let mut histogram = BTreeMap::new();
+        for modifier in self {
+            let filtered_bits = match modifier {
+                ModifierRef::Cpuid(_, reg_modifier) => reg_modifier.bitmap.filter.count_ones(),
+                ModifierRef::Msr(modifier) => modifier.bitmap.filter.count_ones(),
+            };
+            *histogram.entry(filtered_bits).or_insert(0) += 1;
+        }
+        histogram
+    }
+
+    /// Get a one-line summary of the template for list views, e.g.
+    /// "5 CPUID leaves, 4 MSRs, 1 KVM capability".
+    pub fn describe(&self) -> String {
+        let count_of = |count: usize, singular: &str, plural: &str| match count {
+            1 => format!("1 {singular}"),
+            _ => format!("{count} {plural}"),
+        };
+
+        let leaves: BTreeSet<_> = self.cpuid_modifiers.iter().map(|leaf| leaf.leaf).collect();
+        let mut parts = vec![
+            count_of(leaves.len(), "CPUID leaf", "CPUID leaves"),
+            count_of(self.msr_modifiers.len(), "MSR", "MSRs"),
+        ];
+        if !self.kvm_capabilities.is_empty() {
+            parts.push(count_of(
+                self.kvm_capabilities.len(),
+                "KVM capability",
+                "KVM capabilities",
+            ));
+        }
+        parts.join(", ")
+    }
+
+    /// Get the distinct raw [`KvmCpuidFlags`] values of the CPUID leaf modifiers of the template.
+    pub fn distinct_flags(&self) -> BTreeSet<u32> {
+        self.cpuid_modifiers.iter().map(|leaf| leaf.flags.0).collect()
+    }
+
+    /// Remove register modifiers that consist only of don't-care bits, and then CPUID leaf
+    /// modifiers left without register modifiers. Unlike [`CustomCpuTemplate::coalesce`], the
+    /// remaining modifiers are kept as they are and in their original order.
+    pub fn prune_noop(&mut self) {
+        self.cpuid_modifiers.retain_mut(|leaf_modifier| {
+            let was_empty = leaf_modifier.modifiers.is_empty();
+            leaf_modifier
+                .modifiers
+                .retain(|reg_modifier| !reg_modifier.bitmap.is_noop());
+            was_empty || !leaf_modifier.modifiers.is_empty()
+        });
+        self.msr_modifiers.retain(|modifier| !modifier.bitmap.is_noop());
+    }
+
+    /// Remove modifiers whose effect is entirely overridden by later modifiers targeting the
+    /// same register. Unlike [`CustomCpuTemplate::coalesce`], the remaining modifiers are kept
+    /// as they are and in their original order.
+    ///
+    /// The values the template produces for every register are unchanged.
+    pub fn remove_shadowed(&mut self) {
+        // Walk the modifiers backwards, so the combined effect of all later modifiers on a
+        // register is known when a modifier is visited.
+        let mut later_cpuid = BTreeMap::new();
+        let mut cpuid_modifiers = Vec::new();
+        for mut leaf_modifier in std::mem::take(&mut self.cpuid_modifiers).into_iter().rev() {
+            let was_empty = leaf_modifier.modifiers.is_empty();
+            let mut modifiers = Vec::new();
+            for reg_modifier in leaf_modifier.modifiers.into_iter().rev() {
+                let key = CpuidEffectKey {
+                    leaf: leaf_modifier.leaf,
+                    subleaf: leaf_modifier.subleaf,
+                    flags: leaf_modifier.flags,
+                    register: reg_modifier.register.clone(),
+                };
+                if !is_shadowed(&mut later_cpuid, key, &reg_modifier.bitmap) {
+                    modifiers.push(reg_modifier);
+                }
+            }
+            // A leaf modifier left without register modifiers is only dropped if all of them
+            // were shadowed, as a later leaf modifier then sets the same flags.
+            if was_empty || !modifiers.is_empty() {
+                modifiers.reverse();
+                leaf_modifier.modifiers = modifiers;
+                cpuid_modifiers.push(leaf_modifier);
+            }
+        }
+        cpuid_modifiers.reverse();
+        self.cpuid_modifiers = cpuid_modifiers;
+
+        let mut later_msrs = BTreeMap::new();
+        let mut msr_modifiers: Vec<_> = std::mem::take(&mut self.msr_modifiers)
+            .into_iter()
+            .rev()
+            .filter(|modifier| !is_shadowed(&mut later_msrs, modifier.addr, &modifier.bitmap))
+            .collect();
+        msr_modifiers.reverse();
+        self.msr_modifiers = msr_modifiers;
+    }
+}
+
+/// Get a mask of bits `lo` to `hi`, inclusive, ignoring bits beyond the width of `V`.
+fn window_mask<V: Numeric>(lo: u32, hi: u32) -> V {
+    let mut mask = V::zero();
+    for i in lo..=hi.min(V::BITS - 1) {
+        mask |= V::one() << i;
+    }
+    mask
+}
+
+/// How to resolve bits that two merged templates set to different values, see
+/// [`CustomCpuTemplate::merge`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum MergeStrategy {
+    /// The value of the template merged in wins.
+    #[default]
+    LaterWins,
+    /// The value of the template merged into wins.
+    EarlierWins,
+    /// Fail the merge.
+    ErrorOnConflict,
+}
+
+/// Errors associated with merging templates that set the same bits to different values.
+#[derive(Debug, PartialEq, Eq, thiserror::Error, displaydoc::Display)]
+pub enum MergeConflictError {
+    /// Templates set bits {bits:#x} of CPUID leaf {leaf:#x}, subleaf {subleaf:#x}, register {register:?} to different values.
+    Cpuid {
+        /// Leaf value.
+        leaf: u32,
+        /// Sub-Leaf value.
+        subleaf: u32,
+        /// CPUID register.
+        register: CpuidRegister,
+        /// Mask of the conflicting bits.
+        bits: u32,
+    },
+    /// Templates set bits {bits:#x} of MSR {addr:#x} to different values.
+    Msr {
+        /// MSR address.
+        addr: u32,
+        /// Mask of the conflicting bits.
+        bits: u64,
+    },
+    /// Templates copy different fields into MSR {0:#x}, or only part of a copied field is overwritten.
+    MsrSource(u32),
+    /// Templates modify register {register:?} of CPUID leaf {leaf:#x} both in all sub-leaves and in a single sub-leaf.
+    SubleafWildcard {
+        /// Leaf value.
+        leaf: u32,
+        /// CPUID register.
+        register: CpuidRegister,
+    },
+}
+
+/// Merge `rhs` into the template with [`MergeStrategy::LaterWins`], see
+/// [`CustomCpuTemplate::merge`].
+///
+/// # Panics
+///
+/// Panics if the merge fails, see [`MergeConflictError`].
+impl Add for CustomCpuTemplate {
+    type Output = CustomCpuTemplate;
+
+    fn add(self, rhs: CustomCpuTemplate) -> CustomCpuTemplate {
+        self.merge(&rhs, MergeStrategy::LaterWins)
+            .unwrap_or_else(|err| panic!("Failed to merge CPU templates: {err}"))
+    }
+}
+
+/// Merge `rhs` into the template with [`MergeStrategy::LaterWins`], see
+/// [`CustomCpuTemplate::merge`].
+///
+/// # Panics
+///
+/// Panics if the merge fails, see [`MergeConflictError`].
+impl AddAssign for CustomCpuTemplate {
+    fn add_assign(&mut self, rhs: CustomCpuTemplate) {
+        *self = std::mem::take(self) + rhs;
+    }
+}
+
+/// Error associated with remapping the MSR addresses of a template, see
+/// [`CustomCpuTemplate::remap_msr_addrs`].
+#[derive(Debug, PartialEq, Eq, thiserror::Error, displaydoc::Display)]
+/// Remapping MSRs {first:#x} and {second:#x} would make both of them modify MSR {addr:#x}.
+pub struct MsrRemapError {
+    /// Address of the first MSR.
+    pub first: u32,
+    /// Address of the second MSR.
+    pub second: u32,
+    /// Address both MSRs would be remapped to.
+    pub addr: u32,
+}
+
+/// Merge the filter `later` into `earlier` according to `strategy`, or get the mask of the
+/// conflicting bits.
+fn merge_bitmaps<V: Numeric>(
+    earlier: &RegisterValueFilter<V>,
+    later: &RegisterValueFilter<V>,
+    strategy: MergeStrategy,
+) -> Result<RegisterValueFilter<V>, V> {
+    match strategy {
+        MergeStrategy::LaterWins => Ok(earlier.compose(later)),
+        MergeStrategy::EarlierWins => Ok(later.compose(earlier)),
+        MergeStrategy::ErrorOnConflict => {
+            let conflicting = earlier.filter & later.filter & (earlier.value ^ later.value);
+            if conflicting == V::zero() {
+                Ok(earlier.compose(later))
+            } else {
+                Err(conflicting)
+            }
+        }
+    }
+}
+
+/// Merge the modifiers with a [`RegisterModifier::source`] of an MSR, given along with the
+/// filters of the MSR they are part of, according to `strategy`. Returns `None` if the merged
+/// filter would need more than one source, or would copy only part of a field.
+fn merge_msr_sources(
+    earlier: (Option<RegisterModifier>, &RegisterValueFilter<u64>),
+    later: (Option<RegisterModifier>, &RegisterValueFilter<u64>),
+    strategy: MergeStrategy,
+) -> Option<Option<RegisterModifier>> {
+    if earlier.0 == later.0 {
+        return Some(earlier.0);
+    }
+    let ((winner, winner_bitmap), (loser, _)) = match strategy {
+        MergeStrategy::LaterWins => (later, earlier),
+        MergeStrategy::EarlierWins => (earlier, later),
+        MergeStrategy::ErrorOnConflict => {
+            // Bits of a field conflict with any bit the other template sets.
+            let overlaps = |source: Option<RegisterModifier>, bitmap: &RegisterValueFilter<u64>| {
+                source.is_some_and(|source| source.bitmap.filter & bitmap.filter != 0)
+            };
+            if overlaps(earlier.0, later.1) || overlaps(later.0, earlier.1) {
+                return None;
+            }
+            (later, earlier)
+        }
+    };
+    let Some(loser) = loser else {
+        return Some(winner);
+    };
+    let overwritten = loser.bitmap.filter & winner_bitmap.filter;
+    if overwritten == loser.bitmap.filter {
+        Some(winner)
+    } else if overwritten == 0 && winner.is_none() {
+        Some(Some(loser))
+    } else {
+        None
+    }
+}
+
+/// Find a CPUID register `wildcards` modifies in all sub-leaves of a leaf, see
+/// [`SUBLEAF_WILDCARD`], that `subleaves` modifies in a single sub-leaf of the same leaf.
+/// Registers whose filters do not change any bit are ignored.
+fn find_mixed_subleaves<'a>(
+    wildcards: &'a EffectMap,
+    subleaves: &EffectMap,
+) -> Option<&'a CpuidEffectKey> {
+    wildcards
+        .cpuid
+        .iter()
+        .filter(|(key, bitmap)| key.subleaf == SUBLEAF_WILDCARD && !bitmap.is_noop())
+        .map(|(key, _)| key)
+        .find(|wildcard| {
+            subleaves.cpuid.iter().any(|(key, bitmap)| {
+                key.leaf == wildcard.leaf
+                    && key.register == wildcard.register
+                    && key.subleaf != SUBLEAF_WILDCARD
+                    && !bitmap.is_noop()
+            })
+        })
+}
+
+/// Get `bitmap` without the bits of the field copied by `source`, if any.
+fn without_field(
+    bitmap: &RegisterValueFilter<u64>,
+    source: Option<&RegisterModifier>,
+) -> RegisterValueFilter<u64> {
+    let field = source.map_or(0, |source| source.bitmap.filter);
+    RegisterValueFilter {
+        filter: bitmap.filter & !field,
+        value: bitmap.value & !field,
+    }
+}
+
+/// Get a filter covering only the bits that both `bitmap` and `other` set to the same value.
+fn agreement<V: Numeric>(
+    bitmap: &RegisterValueFilter<V>,
+    other: &RegisterValueFilter<V>,
+) -> RegisterValueFilter<V> {
+    let filter = bitmap.filter & other.filter & !(bitmap.value ^ other.value);
+    RegisterValueFilter {
+        filter,
+        value: bitmap.value & filter,
+    }
+}
+
+/// Estimated number of KVM ioctls needed to apply a CPU template to all vCPUs.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct OpEstimate {
+    /// Number of `KVM_SET_CPUID2` calls.
+    pub cpuid_ops: usize,
+    /// Number of `KVM_SET_MSRS` calls.
+    pub msr_ops: usize,
+}
+
+/// Check if `bitmap` has no effect when followed by the filter stored for `key`, i.e. the
+/// combined filter of the later modifiers of the register. Otherwise, add `bitmap` to it.
+fn is_shadowed<K, V>(
+    later: &mut BTreeMap<K, RegisterValueFilter<V>>,
+    key: K,
+    bitmap: &RegisterValueFilter<V>,
+) -> bool
+where
+    K: Ord,
+    V: Numeric + Debug,
+{
+    match later.entry(key) {
+        Entry::Occupied(mut entry) => {
+            let composed = bitmap.compose(entry.get());
+            if &composed == entry.get() {
+                return true;
+            }
+            entry.insert(composed);
+        }
+        Entry::Vacant(entry) => {
+            entry.insert(*bitmap);
+        }
+    }
+    false
+}
+
+/// Reference to a single modifier of a custom CPU template.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ModifierRef<'a> {
+    /// CPUID register modifier together with the leaf modifier containing it.
+    Cpuid(&'a CpuidLeafModifier, &'a CpuidRegisterModifier),
+    /// MSR modifier.
+    Msr(&'a RegisterModifier),
+}
+
+/// Iterator over all modifiers of a custom CPU template.
+///
+/// CPUID register modifiers are yielded first, in the order of their leaf modifiers, followed
+/// by MSR modifiers.
+#[derive(Debug, Clone)]
+pub struct ModifierIter<'a> {
+    leaf_modifiers: std::slice::Iter<'a, CpuidLeafModifier>,
+    current_leaf: Option<(
+        &'a CpuidLeafModifier,
+        std::slice::Iter<'a, CpuidRegisterModifier>,
+    )>,
+    msr_modifiers: std::slice::Iter<'a, RegisterModifier>,
+}
+
+impl<'a> Iterator for ModifierIter<'a> {
+    type Item = ModifierRef<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((leaf_modifier, reg_modifiers)) = &mut self.current_leaf {
+                if let Some(reg_modifier) = reg_modifiers.next() {
+                    return Some(ModifierRef::Cpuid(*leaf_modifier, reg_modifier));
+                }
+            }
+            match self.leaf_modifiers.next() {
+                Some(leaf_modifier) => {
+                    self.current_leaf = Some((leaf_modifier, leaf_modifier.modifiers.iter()));
+                }
+                None => return self.msr_modifiers.next().map(ModifierRef::Msr),
+            }
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a CustomCpuTemplate {
+    type Item = ModifierRef<'a>;
+    type IntoIter = ModifierIter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        ModifierIter {
+            leaf_modifiers: self.cpuid_modifiers.iter(),
+            current_leaf: None,
+            msr_modifiers: self.msr_modifiers.iter(),
+        }
+    }
+}
+
+/// Location of a single CPUID register targeted by a CPU template.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct CpuidEffectKey {
+    /// Leaf value.
+    pub leaf: u32,
+    /// Sub-Leaf value.
+    pub subleaf: u32,
+    /// KVM feature flags for this leaf-subleaf.
+    pub flags: KvmCpuidFlags,
+    /// CPUID register.
+    pub register: CpuidRegister,
+}
+
+/// Coalesced effect of a CPU template, keyed by the targeted register.
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub struct EffectMap {
+    /// Filters to be applied to CPUID registers.
+    pub cpuid: BTreeMap<CpuidEffectKey, RegisterValueFilter<u32>>,
+    /// CPUID registers with a modifier marked as passthrough, see
+    /// [`CpuidRegisterModifier::passthrough`]. Coalescing only keeps the mark of registers whose
+    /// filter in `cpuid` does not change any bit.
+    pub cpuid_passthrough: BTreeSet<CpuidEffectKey>,
+    /// Filters to be applied to MSRs, keyed by MSR address.
+    pub msrs: BTreeMap<u32, RegisterValueFilter<u64>>,
+    /// Modifiers with a [`RegisterModifier::source`], keyed by MSR address. The bits of their
+    /// fields are also part of the filters in `msrs`, with the value of the modifier.
+    pub msr_sources: BTreeMap<u32, RegisterModifier>,
+}
+
+/// Wrapper of a mask defined as a bitmap to apply
+/// changes to a given register's value.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq, Serialize, Deserialize, Hash)]
+pub struct RegisterModifier {
+    /// Pointer of the location to be bit mapped.
+    #[serde(
+        deserialize_with = "deserialize_from_str_u32",
+        serialize_with = "serialize_to_hex_str"
+    )]
+    pub addr: u32,
+    /// Bit mapping to be applied as a modifier to the
+    /// register's value at the address provided.
+    pub bitmap: RegisterValueFilter<u64>,
+    /// MSR field copied into the bits of the filter of `bitmap` when the template is applied,
+    /// replacing the value of `bitmap`. Only one field can be copied into an MSR, and later
+    /// modifiers of the MSR can not change its bits.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<MsrFieldSource>,
+}
+
+impl RegisterModifier {
+    /// Get the bitmap to apply to the MSR, with the field of its source MSR, if any, copied into
+    /// the value. `source_value` looks up the value of an MSR before the template is applied,
+    /// falling back to [`MsrFieldSource::default`] if the MSR is not found.
+    pub fn resolve_bitmap(
+        &self,
+        source_value: impl Fn(u32) -> Option<u64>,
+    ) -> Result<RegisterValueFilter<u64>, MsrSourceError> {
+        let Some(source) = self.source else {
+            return Ok(self.bitmap);
+        };
+        let value = source_value(source.msr)
+            .or(source.default)
+            .ok_or(MsrSourceError::SourceNotPresent(source.msr))?;
+        let value = source
+            .copy_into(self.bitmap.filter, value)
+            .ok_or(MsrSourceError::FieldMismatch(self.addr))?;
+        Ok(RegisterValueFilter {
+            filter: self.bitmap.filter,
+            value,
+        })
+    }
+}
+
+/// Field of an MSR to copy into the MSR modified by a [`RegisterModifier`].
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq, Serialize, Deserialize, Hash)]
+#[serde(deny_unknown_fields)]
+pub struct MsrFieldSource {
+    /// Address of the MSR to copy the field from.
+    #[serde(
+        deserialize_with = "deserialize_from_str_u32",
+        serialize_with = "serialize_to_hex_str"
+    )]
+    pub msr: u32,
+    /// Lowest and highest bit of the field, inclusive.
+    pub bits: [u32; 2],
+    /// Value of the MSR to copy the field from if the MSR can not be read.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_optional_from_str_u64",
+        serialize_with = "serialize_optional_to_hex_str",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub default: Option<u64>,
+}
+
+impl MsrFieldSource {
+    /// Get the field of `source_value` shifted into the bits of `filter`. Returns `None` if the
+    /// field is not within 64 bits, or if the bits of `filter` are not a contiguous range as
+    /// wide as the field.
+    pub fn copy_into(&self, filter: u64, source_value: u64) -> Option<u64> {
+        let [lo, hi] = self.bits;
+        if lo > hi || hi >= u64::BITS || filter == 0 {
+            return None;
+        }
+        let mask = u64::MAX >> (u64::BITS - 1 - (hi - lo));
+        let shift = filter.trailing_zeros();
+        if filter >> shift != mask {
+            return None;
+        }
+        Some(((source_value >> lo) & mask) << shift)
+    }
+}
+
+/// Errors associated with copying the field of a source MSR into an MSR modifier.
+#[derive(Debug, PartialEq, Eq, thiserror::Error, displaydoc::Display)]
+pub enum MsrSourceError {
+    /// Source MSR {0:#x} of a template modifier is not present.
+    SourceNotPresent(u32),
+    /// Filter of the modifier of MSR {0:#x} does not match the bits of its source field.
+    FieldMismatch(u32),
+    /// Modifiers of MSR {0:#x} copy more than one field into it, or change bits of a copied field.
+    FieldOverwritten(u32),
+}
+
+struct KvmCpuidFlagsVisitor;
+
+impl<'de> Visitor<'de> for KvmCpuidFlagsVisitor {
+    type Value = KvmCpuidFlags;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("a number or a string prefixed with '0x' or '0b'")
+    }
+
+    fn visit_u64<E>(self, value: u64) -> Result<Self::Value, E>
+    where
+        E: SerdeError,
+    {
+        u32::try_from(value).map(KvmCpuidFlags).map_err(|_| {
+            E::custom(format!(
+                "Failed to parse [{}] as KVM CPUID flags - value is too large",
+                value
+            ))
+        })
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+    where
+        E: SerdeError,
+    {
+        deserialize_from_str_u32(value.into_deserializer()).map(KvmCpuidFlags)
+    }
+}
+
+/// Deserializes KVM CPUID flags from either a JSON number or a string using the same number
+/// system prefixes as leaves and subleaves.
+fn deserialize_kvm_cpuid_flags<'de, D>(deserializer: D) -> Result<KvmCpuidFlags, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserializer.deserialize_any(KvmCpuidFlagsVisitor)
+}
+
+fn deserialize_optional_kvm_cpuid_flags<'de, D>(
+    deserializer: D,
+) -> Result<Option<KvmCpuidFlags>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserialize_kvm_cpuid_flags(deserializer).map(Some)
+}
+
+/// Deserializes CPUID leaf modifiers, expanding the ones grouped by sub-leaf, see
+/// [`SerializedCpuidLeafModifier`].
+fn deserialize_cpuid_modifiers<'de, D>(deserializer: D) -> Result<Vec<CpuidLeafModifier>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let mut leaf_modifiers = Vec::new();
+    for serialized in Vec::<SerializedCpuidLeafModifier>::deserialize(deserializer)? {
+        match (serialized.subleaf, serialized.modifiers, serialized.subleaves) {
+            (Some(subleaf), Some(modifiers), None) => leaf_modifiers.push(CpuidLeafModifier {
+                leaf: serialized.leaf,
+                subleaf,
+                flags: serialized.flags,
+                modifiers,
+            }),
+            (None, None, Some(subleaves)) => {
+                for (subleaf, modifier) in subleaves {
+                    leaf_modifiers.push(CpuidLeafModifier {
+                        leaf: serialized.leaf,
+                        subleaf: subleaf.0,
+                        flags: modifier.flags.unwrap_or(serialized.flags),
+                        modifiers: modifier.modifiers,
+                    });
+                }
+            }
+            _ => {
+                return Err(D::Error::custom(format!(
+                    "CPUID leaf modifier {:#x} must have either `subleaf` and `modifiers`, or \
+                     `subleaves`",
+                    serialized.leaf
+                )))
+            }
+        }
+    }
+    Ok(leaf_modifiers)
+}
+
+fn deserialize_optional_from_str_u64<'de, D>(deserializer: D) -> Result<Option<u64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserialize_from_str_u64(deserializer).map(Some)
+}
+
+fn serialize_optional_to_hex_str<S>(number: &Option<u64>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    match number {
+        Some(number) => serialize_to_hex_str(number, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+fn deserialize_optional_subleaf<'de, D>(deserializer: D) -> Result<Option<u32>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserialize_subleaf(deserializer).map(Some)
+}
+
+fn deserialize_subleaf<'de, D>(deserializer: D) -> Result<u32, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let subleaf_str = String::deserialize(deserializer)?;
+    match subleaf_str.as_str() {
+        "*" => Ok(SUBLEAF_WILDCARD),
+        _ => deserialize_from_str_u32(subleaf_str.into_deserializer()),
+    }
+}
+
+fn serialize_subleaf<S>(subleaf: &u32, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    match *subleaf {
+        SUBLEAF_WILDCARD => serializer.serialize_str("*"),
+        _ => serialize_to_hex_str(subleaf, serializer),
+    }
+}
+
+fn deserialize_cpuid_register<'de, D>(deserializer: D) -> Result<CpuidRegister, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let cpuid_register_str = String::deserialize(deserializer)?;
+
+    Ok(match cpuid_register_str.as_str() {
+        "eax" => CpuidRegister::Eax,
+        "ebx" => CpuidRegister::Ebx,
+        "ecx" => CpuidRegister::Ecx,
+        "edx" => CpuidRegister::Edx,
+        _ => {
+            return Err(D::Error::custom(
+                "Invalid CPUID register. Must be one of [eax, ebx, ecx, edx]",
+            ))
+        }
+    })
+}
+
+fn serialize_cpuid_register<S>(cpuid_reg: &CpuidRegister, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    match cpuid_reg {
+        CpuidRegister::Eax => serializer.serialize_str("eax"),
+        CpuidRegister::Ebx => serializer.serialize_str("ebx"),
+        CpuidRegister::Ecx => serializer.serialize_str("ecx"),
+        CpuidRegister::Edx => serializer.serialize_str("edx"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::*;
+    use crate::cpu_config::x86_64::test_utils::{build_test_template, TEST_TEMPLATE_JSON};
+
+    #[test]
+    fn test_get_cpu_template_with_no_template() {
+        // Test `get_cpu_template()` when no template is provided. The empty owned
+        // `CustomCpuTemplate` should be returned.
+        let cpu_template = None;
+        assert_eq!(
+            cpu_template.get_cpu_template().unwrap(),
+            Cow::Owned(CustomCpuTemplate::default()),
+        );
+    }
+
+    #[test]
+    fn test_get_cpu_template_with_c3_static_template() {
+        // Test `get_cpu_template()` when C3 static CPU template is specified. The owned
+        // `CustomCpuTemplate` should be returned if CPU vendor is Intel. Otherwise, it should fail.
+        let cpu_template = Some(CpuTemplateType::Static(StaticCpuTemplate::C3));
+        if &get_vendor_id_from_host().unwrap() == VENDOR_ID_INTEL {
+            assert_eq!(
+                cpu_template.get_cpu_template().unwrap(),
+                Cow::Owned(c3::c3())
+            );
+        } else {
+            assert_eq!(
+                cpu_template.get_cpu_template().unwrap_err(),
+                GetCpuTemplateError::CpuVendorMismatched,
+            );
+        }
+    }
+
+    #[test]
+    fn test_get_cpu_template_with_t2_static_template() {
+        // Test `get_cpu_template()` when T2 static CPU template is specified. The owned
+        // `CustomCpuTemplate` should be returned if CPU vendor is Intel. Otherwise, it should fail.
+        let cpu_template = Some(CpuTemplateType::Static(StaticCpuTemplate::T2));
+        if &get_vendor_id_from_host().unwrap() == VENDOR_ID_INTEL {
+            assert_eq!(
+                cpu_template.get_cpu_template().unwrap(),
+                Cow::Owned(t2::t2())
+            );
+        } else {
+            assert_eq!(
+                cpu_template.get_cpu_template().unwrap_err(),
+                GetCpuTemplateError::CpuVendorMismatched,
+            );
+        }
+    }
+
+    #[test]
+    fn test_get_cpu_template_with_t2s_static_template() {
+        // Test `get_cpu_template()` when T2S static CPU template is specified. The owned
+        // `CustomCpuTemplate` should be returned if CPU vendor is Intel. Otherwise, it should fail.
+        let cpu_template = Some(CpuTemplateType::Static(StaticCpuTemplate::T2S));
+        if &get_vendor_id_from_host().unwrap() == VENDOR_ID_INTEL {
+            assert_eq!(
+                cpu_template.get_cpu_template().unwrap(),
+                Cow::Owned(t2s::t2s())
+            );
+        } else {
+            assert_eq!(
+                cpu_template.get_cpu_template().unwrap_err(),
+                GetCpuTemplateError::CpuVendorMismatched,
+            );
+        }
+    }
+
+    #[test]
+    fn test_t2cl_template_equality() {
+        // For coverage purposes, this test forces usage of T2CL and bypasses
+        // validation that is generally applied which usually enforces that T2CL
+        // can only be used on Cascade Lake (or newer) CPUs.
+        let t2cl_custom_template = CpuTemplateType::Custom(t2cl::t2cl());
+        // This test also demonstrates the difference in concept between custom and static
+        // templates, while practically T2CL is consistent for the user, in code
+        // the static template of T2CL, and the custom template of T2CL are not equivalent.
+        assert_ne!(
+            t2cl_custom_template,
+            CpuTemplateType::Static(StaticCpuTemplate::T2CL)
+        );
+    }
+
+    #[test]
     fn test_get_cpu_template_with_t2cl_static_template() {
         // Test `get_cpu_template()` when T2CL static CPU template is specified. The owned
         // `CustomCpuTemplate` should be returned if CPU vendor is Intel. Otherwise, it should fail.
         let cpu_template = Some(CpuTemplateType::Static(StaticCpuTemplate::T2CL));
         if &get_vendor_id_from_host().unwrap() == VENDOR_ID_INTEL {
             if CpuModel::get_cpu_model().is_at_least_cascade_lake() {
-                assert_eq!(
-                    cpu_template.get_cpu_template().unwrap(),
-                    Cow::Owned(t2cl::t2cl())
-                );
+                assert_eq!(
+                    cpu_template.get_cpu_template().unwrap(),
+                    Cow::Owned(t2cl::t2cl())
+                );
+            } else {
+                assert_eq!(
+                    cpu_template.get_cpu_template().unwrap_err(),
+                    GetCpuTemplateError::InvalidCpuModel,
+                )
+            }
+        } else {
+            assert_eq!(
+                cpu_template.get_cpu_template().unwrap_err(),
+                GetCpuTemplateError::CpuVendorMismatched,
+            );
+        }
+    }
+
+    #[test]
+    fn test_get_cpu_template_with_t2a_static_template() {
+        // Test `get_cpu_template()` when T2A static CPU template is specified. The owned
+        // `CustomCpuTemplate` should be returned if CPU vendor is AMD. Otherwise it should fail.
+        let cpu_template = Some(CpuTemplateType::Static(StaticCpuTemplate::T2A));
+        if &get_vendor_id_from_host().unwrap() == VENDOR_ID_AMD {
+            assert_eq!(
+                cpu_template.get_cpu_template().unwrap(),
+                Cow::Owned(t2a::t2a())
+            );
+        } else {
+            assert_eq!(
+                cpu_template.get_cpu_template().unwrap_err(),
+                GetCpuTemplateError::CpuVendorMismatched,
+            );
+        }
+    }
+
+    #[test]
+    fn test_get_cpu_template_with_none_static_template() {
+        // Test `get_cpu_template()` when no static CPU template is provided.
+        // `InvalidStaticCpuTemplate` error should be returned because it is no longer valid and
+        // was replaced with `None` of `Option<CpuTemplateType>`.
+        let cpu_template = Some(CpuTemplateType::Static(StaticCpuTemplate::None));
+        assert_eq!(
+            cpu_template.get_cpu_template().unwrap_err(),
+            GetCpuTemplateError::InvalidStaticCpuTemplate(StaticCpuTemplate::None)
+        );
+
+        // Test the Display for StaticCpuTemplate
+        assert_eq!(format!("{}", StaticCpuTemplate::None), "None");
+    }
+
+    #[test]
+    fn test_get_cpu_template_with_custom_template() {
+        // Test `get_cpu_template()` when a custom CPU template is provided. The borrowed
+        // `CustomCpuTemplate` should be returned.
+        let inner_cpu_template = CustomCpuTemplate::default();
+        let cpu_template = Some(CpuTemplateType::Custom(inner_cpu_template.clone()));
+        assert_eq!(
+            cpu_template.get_cpu_template().unwrap(),
+            Cow::Borrowed(&inner_cpu_template)
+        );
+    }
+
+    #[test]
+    fn test_malformed_json() {
+        // Misspelled field name, register
+        let cpu_template_result = serde_json::from_str::<CustomCpuTemplate>(
+            r#"{
+                    "cpuid_modifiers": [
+                        {
+                            "leaf": "0x80000001",
+                            "subleaf": "0b000111",
+                            "flags": 0,
+                            "modifiers": [
+                                {
+                                    "register": "ekx",
+                                    "bitmap": "0bx00100xxx1xxxxxxxxxxxxxxxxxxxxx1"
+                                }
+                            ]
+                        },
+                    ],
+                }"#,
+        );
+        assert!(cpu_template_result
+            .unwrap_err()
+            .to_string()
+            .contains("Invalid CPUID register. Must be one of [eax, ebx, ecx, edx]"));
+
+        // Malformed MSR register address
+        let cpu_template_result = serde_json::from_str::<CustomCpuTemplate>(
+            r#"{
+                    "msr_modifiers":  [
+                        {
+                            "addr": "0jj0",
+                            "bitmap": "0bx00100xxx1xxxx00xxx1xxxxxxxxxxx1"
+                        },
+                    ]
+                }"#,
+        );
+        let error_msg: String = cpu_template_result.unwrap_err().to_string();
+        // Formatted error expected clarifying the number system prefix is missing
+        assert!(
+            error_msg.contains("No supported number system prefix found in value"),
+            "{}",
+            error_msg
+        );
+
+        // Malformed CPUID leaf address
+        let cpu_template_result = serde_json::from_str::<CustomCpuTemplate>(
+            r#"{
+                    "cpuid_modifiers": [
+                        {
+                            "leaf": "k",
+                            "subleaf": "0b000111",
+                            "flags": 0,
+                            "modifiers": [
+                                {
+                                    "register": "eax",
+                                    "bitmap": "0bx00100xxx1xxxxxxxxxxxxxxxxxxxxx1"
+                                }
+                            ]
+                        },
+                    ],
+                }"#,
+        );
+        let error_msg: String = cpu_template_result.unwrap_err().to_string();
+        // Formatted error expected clarifying the number system prefix is missing
+        assert!(
+            error_msg.contains("No supported number system prefix found in value"),
+            "{}",
+            error_msg
+        );
+        // Malformed 64-bit bitmap - filter failed
+        let cpu_template_result = serde_json::from_str::<CustomCpuTemplate>(
+            r#"{
+                    "msr_modifiers":  [
+                        {
+                            "addr": "0x200",
+                            "bitmap": "0bx0?1_0_0x_?x1xxxx00xxx1xxxxxxxxxxx1"
+                        },
+                    ]
+                }"#,
+        );
+        assert!(cpu_template_result.unwrap_err().to_string().contains(
+            "Failed to parse string [0bx0?1_0_0x_?x1xxxx00xxx1xxxxxxxxxxx1] as a bitmap"
+        ));
+        // Malformed 64-bit bitmap - value failed
+        let cpu_template_result = serde_json::from_str::<CustomCpuTemplate>(
+            r#"{
+                    "msr_modifiers":  [
+                        {
+                            "addr": "0x200",
+                            "bitmap": "0bx00100x0x1xxxx05xxx1xxxxxxxxxxx1"
+                        },
+                    ]
+                }"#,
+        );
+        assert!(cpu_template_result
+            .unwrap_err()
+            .to_string()
+            .contains("Failed to parse string [0bx00100x0x1xxxx05xxx1xxxxxxxxxxx1] as a bitmap"));
+    }
+
+    #[test]
+    fn test_deserialization_lifecycle() {
+        let cpu_template = serde_json::from_str::<CustomCpuTemplate>(TEST_TEMPLATE_JSON)
+            .expect("Failed to deserialize custom CPU template.");
+        assert_eq!(5, cpu_template.cpuid_modifiers.len());
+        assert_eq!(4, cpu_template.msr_modifiers.len());
+    }
+
+    #[test]
+    fn test_serialization_lifecycle() {
+        let template = build_test_template();
+        let template_json_str_result = serde_json::to_string_pretty(&template);
+        let template_json = template_json_str_result.unwrap();
+
+        let deserialization_result = serde_json::from_str::<CustomCpuTemplate>(&template_json);
+        assert_eq!(template, deserialization_result.unwrap());
+    }
+
+    /// Test to confirm that templates for different CPU architectures have
+    /// a size bitmask that is supported by the architecture when serialized to JSON.
+    #[test]
+    fn test_bitmap_width() {
+        let mut cpuid_checked = false;
+        let mut msr_checked = false;
+
+        let template = build_test_template();
+
+        let x86_template_str =
+            serde_json::to_string(&template).expect("Error serializing x86 template");
+        let json_tree: Value = serde_json::from_str(&x86_template_str)
+            .expect("Error deserializing x86 template JSON string");
+
+        // Check that bitmaps for CPUID values are 32-bits in width
+        if let Some(cpuid_modifiers_root) = json_tree.get("cpuid_modifiers") {
+            let cpuid_mod_node = &cpuid_modifiers_root.as_array().unwrap()[0];
+            if let Some(modifiers_node) = cpuid_mod_node.get("modifiers") {
+                let mod_node = &modifiers_node.as_array().unwrap()[0];
+                if let Some(bit_map_str) = mod_node.get("bitmap") {
+                    // 32-bit width with a "0b" prefix for binary-formatted numbers
+                    assert_eq!(bit_map_str.as_str().unwrap().len(), 34);
+                    cpuid_checked = true;
+                }
+            }
+        }
+
+        // Check that bitmaps for MSRs are 64-bits in width
+        if let Some(msr_modifiers_root) = json_tree.get("msr_modifiers") {
+            let msr_mod_node = &msr_modifiers_root.as_array().unwrap()[0];
+            if let Some(bit_map_str) = msr_mod_node.get("bitmap") {
+                // 64-bit width with a "0b" prefix for binary-formatted numbers
+                assert_eq!(bit_map_str.as_str().unwrap().len(), 66);
+                assert!(bit_map_str.as_str().unwrap().starts_with("0b"));
+                msr_checked = true;
+            }
+        }
+
+        assert!(
+            cpuid_checked,
+            "CPUID bitmap width in a x86_64 template was not tested."
+        );
+        assert!(
+            msr_checked,
+            "MSR bitmap width in a x86_64 template was not tested."
+        );
+    }
+
+    #[test]
+    fn test_effect_map_coalesces_modifiers() {
+        let mut template = build_test_template();
+        template.cpuid_modifiers.push(CpuidLeafModifier {
+            leaf: 0x3,
+            subleaf: 0x0,
+            flags: KvmCpuidFlags(kvm_bindings::KVM_CPUID_FLAG_STATEFUL_FUNC),
+            modifiers: vec![CpuidRegisterModifier {
+                register: CpuidRegister::Eax,
+                bitmap: RegisterValueFilter {
+                    filter: 0b1100,
+                    value: 0b1000,
+                },
+                passthrough: false,
+            }],
+        });
+        template.msr_modifiers.push(RegisterModifier {
+            addr: 0x8000,
+            bitmap: RegisterValueFilter {
+                filter: 0b11,
+                value: 0b10,
+            },
+            source: None,
+        });
+
+        let effect_map = template.effect_map();
+        assert_eq!(effect_map.cpuid.len(), 4);
+        assert_eq!(effect_map.msrs.len(), 2);
+
+        let eax_key = CpuidEffectKey {
+            leaf: 0x3,
+            subleaf: 0x0,
+            flags: KvmCpuidFlags(kvm_bindings::KVM_CPUID_FLAG_STATEFUL_FUNC),
+            register: CpuidRegister::Eax,
+        };
+        assert_eq!(
+            effect_map.cpuid[&eax_key],
+            RegisterValueFilter {
+                filter: 0b1111,
+                value: 0b1001,
+            }
+        );
+        assert_eq!(
+            effect_map.msrs[&0x8000],
+            RegisterValueFilter {
+                filter: 0b11,
+                value: 0b10,
+            }
+        );
+    }
+
+    #[test]
+    fn test_effect_on_leaf() {
+        let mut template = build_test_template();
+        template.cpuid_modifiers[0].modifiers.push(CpuidRegisterModifier {
+            register: CpuidRegister::Eax,
+            bitmap: RegisterValueFilter {
+                filter: 0b1100,
+                value: 0b1000,
+            },
+            passthrough: false,
+        });
+        template.cpuid_modifiers[0].modifiers.remove(3);
+
+        let flags = KvmCpuidFlags(kvm_bindings::KVM_CPUID_FLAG_STATEFUL_FUNC);
+        assert_eq!(
+            template.effect_on_leaf(0x3, 0x0, flags),
+            [
+                Some(RegisterValueFilter {
+                    filter: 0b1111,
+                    value: 0b1001,
+                }),
+                Some(RegisterValueFilter {
+                    filter: 0b0111,
+                    value: 0b0100,
+                }),
+                Some(RegisterValueFilter {
+                    filter: 0b0111,
+                    value: 0b0111,
+                }),
+                None,
+            ]
+        );
+
+        // Only the exact entry is considered.
+        assert_eq!(
+            template.effect_on_leaf(0x3, 0x1, flags),
+            [None, None, None, None]
+        );
+        assert_eq!(
+            template.effect_on_leaf(0x3, 0x0, KvmCpuidFlags::EMPTY),
+            [None, None, None, None]
+        );
+    }
+
+    #[test]
+    fn test_get_cpu_template_with_fallback_for_t2cl() {
+        // T2CL should be downgraded to T2 on Intel CPUs older than Cascade Lake and returned as is
+        // on Cascade Lake or newer.
+        let cpu_template = Some(CpuTemplateType::Static(StaticCpuTemplate::T2CL));
+        let result = get_cpu_template_with_fallback(&cpu_template);
+        if &get_vendor_id_from_host().unwrap() == VENDOR_ID_INTEL {
+            let resolved = result.unwrap();
+            if CpuModel::get_cpu_model().is_at_least_cascade_lake() {
+                assert_eq!(resolved.template, Cow::Owned(t2cl::t2cl()));
+                assert!(!resolved.downgraded);
+            } else {
+                assert_eq!(resolved.template, Cow::Owned(t2::t2()));
+                assert!(resolved.downgraded);
+            }
+        } else {
+            assert_eq!(
+                result.unwrap_err(),
+                GetCpuTemplateError::CpuVendorMismatched
+            );
+        }
+    }
+
+    #[test]
+    fn test_get_cpu_template_with_fallback_without_downgrade() {
+        // Templates that can be applied as requested are never downgraded.
+        let inner_cpu_template = build_test_template();
+        let cpu_template = Some(CpuTemplateType::Custom(inner_cpu_template.clone()));
+        let resolved = get_cpu_template_with_fallback(&cpu_template).unwrap();
+        assert_eq!(resolved.template, Cow::Borrowed(&inner_cpu_template));
+        assert!(!resolved.downgraded);
+
+        let cpu_template = Some(CpuTemplateType::Static(StaticCpuTemplate::T2));
+        let result = get_cpu_template_with_fallback(&cpu_template);
+        if &get_vendor_id_from_host().unwrap() == VENDOR_ID_INTEL {
+            let resolved = result.unwrap();
+            assert_eq!(resolved.template, Cow::Owned(t2::t2()));
+            assert!(!resolved.downgraded);
+        } else {
+            assert_eq!(
+                result.unwrap_err(),
+                GetCpuTemplateError::CpuVendorMismatched
+            );
+        }
+
+        // The fallback only applies to CPU model mismatches.
+        let cpu_template = Some(CpuTemplateType::Static(StaticCpuTemplate::None));
+        assert_eq!(
+            get_cpu_template_with_fallback(&cpu_template).unwrap_err(),
+            GetCpuTemplateError::InvalidStaticCpuTemplate(StaticCpuTemplate::None)
+        );
+    }
+
+    #[test]
+    fn test_kvm_cpuid_flags_serde() {
+        let leaf_modifier_json = |flags: &str| {
+            format!(
+                r#"{{
+                    "leaf": "0x7",
+                    "subleaf": "0x0",
+                    "flags": {flags},
+                    "modifiers": []
+                }}"#
+            )
+        };
+
+        for flags in ["1", r#""0x1""#, r#""0b1""#] {
+            let leaf_modifier =
+                serde_json::from_str::<CpuidLeafModifier>(&leaf_modifier_json(flags)).unwrap();
+            assert_eq!(leaf_modifier.flags, KvmCpuidFlags::SIGNIFICANT_INDEX);
+        }
+
+        for flags in [r#""1""#, r#""0xz""#, "4294967296", "-1"] {
+            serde_json::from_str::<CpuidLeafModifier>(&leaf_modifier_json(flags)).unwrap_err();
+        }
+    }
+
+    #[test]
+    fn test_modifier_iter() {
+        let template = build_test_template();
+        let modifiers = (&template).into_iter().collect::<Vec<_>>();
+        assert_eq!(modifiers.len(), 6);
+        assert_eq!(
+            modifiers[0],
+            ModifierRef::Cpuid(
+                &template.cpuid_modifiers[0],
+                &template.cpuid_modifiers[0].modifiers[0]
+            )
+        );
+        assert_eq!(
+            modifiers[5],
+            ModifierRef::Msr(&template.msr_modifiers[1])
+        );
+
+        let template = serde_json::from_str::<CustomCpuTemplate>(TEST_TEMPLATE_JSON).unwrap();
+        let (mut cpuid_count, mut msr_count) = (0, 0);
+        for modifier in &template {
+            match modifier {
+                ModifierRef::Cpuid(..) => cpuid_count += 1,
+                ModifierRef::Msr(_) => msr_count += 1,
+            }
+        }
+        assert_eq!((cpuid_count, msr_count), (8, 4));
+
+        assert_eq!(CustomCpuTemplate::default().into_iter().count(), 0);
+    }
+
+    #[test]
+    fn test_coalesce() {
+        let mut template = serde_json::from_str::<CustomCpuTemplate>(
+            r#"{
+                "cpuid_modifiers": [
+                    {
+                        "leaf": "0x7",
+                        "subleaf": "0x0",
+                        "flags": 1,
+                        "modifiers": [
+                            {
+                                "register": "ebx",
+                                "bitmap": "0bxx10"
+                            },
+                            {
+                                "register": "ecx",
+                                "bitmap": "0bxxxx"
+                            }
+                        ]
+                    },
+                    {
+                        "leaf": "0x1",
+                        "subleaf": "0x0",
+                        "flags": 0,
+                        "modifiers": [
+                            {
+                                "register": "eax",
+                                "bitmap": "0b1xxx"
+                            }
+                        ]
+                    },
+                    {
+                        "leaf": "0x7",
+                        "subleaf": "0x0",
+                        "flags": 1,
+                        "modifiers": [
+                            {
+                                "register": "ebx",
+                                "bitmap": "0b0xxx"
+                            }
+                        ]
+                    }
+                ],
+                "msr_modifiers": [
+                    {
+                        "addr": "0x10a",
+                        "bitmap": "0bxxxx"
+                    },
+                    {
+                        "addr": "0x48",
+                        "bitmap": "0b1x"
+                    },
+                    {
+                        "addr": "0x48",
+                        "bitmap": "0bx0"
+                    }
+                ]
+            }"#,
+        )
+        .unwrap();
+        let effect_map = template.effect_map();
+
+        template.coalesce();
+        assert_eq!(
+            template.cpuid_modifiers,
+            vec![
+                CpuidLeafModifier {
+                    leaf: 0x1,
+                    subleaf: 0x0,
+                    flags: KvmCpuidFlags::EMPTY,
+                    modifiers: vec![CpuidRegisterModifier {
+                        register: CpuidRegister::Eax,
+                        bitmap: RegisterValueFilter {
+                            filter: 0b1000,
+                            value: 0b1000,
+                        },
+                        passthrough: false,
+                    }],
+                },
+                CpuidLeafModifier {
+                    leaf: 0x7,
+                    subleaf: 0x0,
+                    flags: KvmCpuidFlags::SIGNIFICANT_INDEX,
+                    modifiers: vec![CpuidRegisterModifier {
+                        register: CpuidRegister::Ebx,
+                        bitmap: RegisterValueFilter {
+                            filter: 0b1011,
+                            value: 0b0010,
+                        },
+                        passthrough: false,
+                    }],
+                },
+            ]
+        );
+        // The all don't-care modifier of MSR 0x10a is dropped.
+        assert_eq!(
+            template.msr_modifiers,
+            vec![RegisterModifier {
+                addr: 0x48,
+                bitmap: RegisterValueFilter {
+                    filter: 0b11,
+                    value: 0b10,
+                },
+                source: None,
+            }]
+        );
+
+        // Coalescing only drops modifiers without effect.
+        let mut coalesced_effect_map = effect_map.clone();
+        coalesced_effect_map.cpuid.retain(|_, bitmap| bitmap.filter != 0);
+        coalesced_effect_map.msrs.retain(|_, bitmap| bitmap.filter != 0);
+        assert_eq!(template.effect_map(), coalesced_effect_map);
+    }
+
+    #[test]
+    fn test_subleaf_wildcard_serde() {
+        let json = r#"{
+            "cpuid_modifiers": [
+                {
+                    "leaf": "0x7",
+                    "subleaf": "*",
+                    "flags": 0,
+                    "modifiers": [
+                        {
+                            "register": "ebx",
+                            "bitmap": "0bxx01"
+                        }
+                    ]
+                }
+            ]
+        }"#;
+        let template = CustomCpuTemplate::try_from(json).unwrap();
+        assert_eq!(template.cpuid_modifiers[0].subleaf, SUBLEAF_WILDCARD);
+
+        let serialized: Value = serde_json::to_value(&template).unwrap();
+        assert_eq!(serialized["cpuid_modifiers"][0]["subleaf"], "*");
+        let deserialized: CustomCpuTemplate = serde_json::from_value(serialized).unwrap();
+        assert_eq!(deserialized, template);
+
+        let error = CustomCpuTemplate::try_from(json.replace("\"*\"", "\"**\"").as_str())
+            .unwrap_err()
+            .to_string();
+        assert!(
+            error.contains("No supported number system prefix"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn test_cpuid_register_op() {
+        let deserialize = |op: &str| {
+            let json = format!(r#"{{"register": "ecx", {op}"bitmap": "0b0x10"}}"#);
+            serde_json::from_str::<CpuidRegisterModifier>(&json)
+                .unwrap()
+                .bitmap
+        };
+
+        let set = RegisterValueFilter {
+            filter: 0b1011,
+            value: 0b0010,
+        };
+        assert_eq!(deserialize(""), set);
+        assert_eq!(deserialize(r#""op": "set", "#), set);
+        assert_eq!(
+            deserialize(r#""op": "or", "#),
+            RegisterValueFilter {
+                filter: 0b0010,
+                value: 0b0010,
+            }
+        );
+        assert_eq!(
+            deserialize(r#""op": "and", "#),
+            RegisterValueFilter {
+                filter: !0b0010,
+                value: 0,
+            }
+        );
+
+        let json = r#"{"register": "ecx", "op": "xor", "bitmap": "0b1"}"#;
+        let error = serde_json::from_str::<CpuidRegisterModifier>(json).unwrap_err();
+        assert!(
+            error.to_string().contains("unknown variant `xor`"),
+            "{error}"
+        );
+
+        // The op is not serialized, only the bitmap it translates to.
+        let modifier = serde_json::from_str::<CpuidRegisterModifier>(
+            r#"{"register": "ecx", "op": "or", "bitmap": "0b10"}"#,
+        )
+        .unwrap();
+        let serialized = serde_json::to_value(&modifier).unwrap();
+        assert_eq!(serialized.get("op"), None);
+        assert_eq!(
+            serde_json::from_value::<CpuidRegisterModifier>(serialized).unwrap(),
+            modifier
+        );
+    }
+
+    #[test]
+    fn test_passthrough_modifier() {
+        let modifier = serde_json::from_str::<CpuidRegisterModifier>(
+            r#"{"register": "ecx", "passthrough": true}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            modifier,
+            CpuidRegisterModifier {
+                register: CpuidRegister::Ecx,
+                bitmap: RegisterValueFilter::default(),
+                passthrough: true,
+            }
+        );
+        assert_eq!(modifier.bitmap.apply(0x1234), 0x1234);
+
+        let serialized = serde_json::to_value(&modifier).unwrap();
+        assert_eq!(serialized.get("passthrough"), Some(&Value::Bool(true)));
+        assert_eq!(
+            serde_json::from_value::<CpuidRegisterModifier>(serialized).unwrap(),
+            modifier
+        );
+
+        // Other modifiers do not serialize the flag.
+        let modifier = serde_json::from_str::<CpuidRegisterModifier>(
+            r#"{"register": "ecx", "bitmap": "0b10"}"#,
+        )
+        .unwrap();
+        assert!(!modifier.passthrough);
+        let serialized = serde_json::to_value(&modifier).unwrap();
+        assert_eq!(serialized.get("passthrough"), None);
+
+        for json in [
+            r#"{"register": "ecx", "passthrough": true, "bitmap": "0b10"}"#,
+            r#"{"register": "ecx", "passthrough": true, "op": "and"}"#,
+            r#"{"register": "ecx", "passthrough": false}"#,
+            r#"{"register": "ecx"}"#,
+        ] {
+            serde_json::from_str::<CpuidRegisterModifier>(json).unwrap_err();
+        }
+    }
+
+    #[test]
+    fn test_passthrough_transforms() {
+        let passthrough_leaf = CpuidLeafModifier {
+            leaf: 0x4,
+            subleaf: 0x0,
+            flags: KvmCpuidFlags::EMPTY,
+            modifiers: vec![CpuidRegisterModifier {
+                register: CpuidRegister::Ecx,
+                bitmap: RegisterValueFilter::default(),
+                passthrough: true,
+            }],
+        };
+        let mut template = build_test_template();
+        template.cpuid_modifiers.push(passthrough_leaf.clone());
+        let passthrough_leaves = |template: &CustomCpuTemplate| -> Vec<CpuidLeafModifier> {
+            template
+                .cpuid_modifiers
+                .iter()
+                .filter(|leaf_modifier| {
+                    leaf_modifier
+                        .modifiers
+                        .iter()
+                        .any(|reg_modifier| reg_modifier.passthrough)
+                })
+                .cloned()
+                .collect()
+        };
+
+        let mut coalesced = template.clone();
+        coalesced.coalesce();
+        assert_eq!(
+            passthrough_leaves(&coalesced),
+            vec![passthrough_leaf.clone()]
+        );
+        let merged = build_test_template()
+            .merge(&template, MergeStrategy::LaterWins)
+            .unwrap();
+        assert_eq!(passthrough_leaves(&merged), vec![passthrough_leaf.clone()]);
+        assert_eq!(
+            passthrough_leaves(&template.intersect(&template)),
+            vec![passthrough_leaf.clone()]
+        );
+        assert_eq!(
+            passthrough_leaves(&template.intersect(&build_test_template())),
+            vec![]
+        );
+
+        // The mark is dropped once another modifier changes a bit of the register.
+        let mut set_bit = passthrough_leaf;
+        set_bit.modifiers[0] = CpuidRegisterModifier {
+            register: CpuidRegister::Ecx,
+            bitmap: RegisterValueFilter {
+                filter: 0b1,
+                value: 0b1,
+            },
+            passthrough: false,
+        };
+        let other = CustomCpuTemplate {
+            cpuid_modifiers: vec![set_bit.clone()],
+            ..Default::default()
+        };
+        let merged = template.merge(&other, MergeStrategy::LaterWins).unwrap();
+        assert_eq!(passthrough_leaves(&merged), vec![]);
+        assert!(merged.cpuid_modifiers.contains(&set_bit));
+    }
+
+    #[test]
+    fn test_grouped_subleaves() {
+        let json = r#"{
+            "cpuid_modifiers": [
+                {
+                    "leaf": "0x7",
+                    "flags": 1,
+                    "subleaves": {
+                        "0x1": {
+                            "flags": 0,
+                            "modifiers": [{"register": "eax", "bitmap": "0b1x"}]
+                        },
+                        "0x0": {
+                            "modifiers": [{"register": "ebx", "bitmap": "0bx0"}]
+                        }
+                    }
+                },
+                {
+                    "leaf": "0x1",
+                    "subleaf": "0x0",
+                    "flags": 0,
+                    "modifiers": []
+                }
+            ]
+        }"#;
+        let template = CustomCpuTemplate::try_from(json).unwrap();
+        let modifier = |register, filter, value| CpuidRegisterModifier {
+            register,
+            bitmap: RegisterValueFilter { filter, value },
+            passthrough: false,
+        };
+        assert_eq!(
+            template.cpuid_modifiers,
+            vec![
+                CpuidLeafModifier {
+                    leaf: 0x7,
+                    subleaf: 0x0,
+                    flags: KvmCpuidFlags::SIGNIFICANT_INDEX,
+                    modifiers: vec![modifier(CpuidRegister::Ebx, 0b01, 0b00)],
+                },
+                CpuidLeafModifier {
+                    leaf: 0x7,
+                    subleaf: 0x1,
+                    flags: KvmCpuidFlags::EMPTY,
+                    modifiers: vec![modifier(CpuidRegister::Eax, 0b10, 0b10)],
+                },
+                CpuidLeafModifier {
+                    leaf: 0x1,
+                    subleaf: 0x0,
+                    flags: KvmCpuidFlags::EMPTY,
+                    modifiers: vec![],
+                },
+            ]
+        );
+
+        // Templates are serialized with one modifier per sub-leaf.
+        let serialized = serde_json::to_value(&template).unwrap();
+        assert_eq!(serialized["cpuid_modifiers"][1]["subleaf"], "0x1");
+        let deserialized: CustomCpuTemplate = serde_json::from_value(serialized).unwrap();
+        assert_eq!(deserialized, template);
+
+        let json = r#"{
+            "cpuid_modifiers": [
+                {
+                    "leaf": "0x7",
+                    "subleaf": "0x0",
+                    "flags": 1,
+                    "subleaves": {}
+                }
+            ]
+        }"#;
+        let error = CustomCpuTemplate::try_from(json).unwrap_err().to_string();
+        assert!(
+            error.contains("CPUID leaf modifier 0x7 must have either `subleaf` and `modifiers`"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn test_retain_modifiers() {
+        let mut template = CustomCpuTemplate::try_from(TEST_TEMPLATE_JSON).unwrap();
+        template.cpuid_modifiers.push(build_test_template().cpuid_modifiers[0].clone());
+        template.retain_cpuid(|modifier| modifier.leaf < 0x8000_0000);
+        assert_eq!(
+            template.cpuid_modifiers,
+            build_test_template().cpuid_modifiers
+        );
+
+        template.retain_msrs(|modifier| modifier.addr <= 0x1);
+        assert_eq!(
+            template.msr_index_iter().collect::<Vec<_>>(),
+            vec![0x0, 0x1]
+        );
+        template.retain_msrs(|_| false);
+        assert!(template.msr_modifiers.is_empty());
+    }
+
+    #[test]
+    fn test_remove_shadowed() {
+        let mut template = build_test_template();
+        // Shadowed by the second EAX modifier below.
+        template.cpuid_modifiers[0].modifiers[0].bitmap = RegisterValueFilter {
+            filter: 0b0011,
+            value: 0b0001,
+        };
+        template.cpuid_modifiers.push(CpuidLeafModifier {
+            modifiers: vec![
+                CpuidRegisterModifier {
+                    register: CpuidRegister::Eax,
+                    bitmap: RegisterValueFilter {
+                        filter: 0b0111,
+                        value: 0b0010,
+                    },
+                    passthrough: false,
+                },
+                // Only partially overlaps the EBX modifier above.
+                CpuidRegisterModifier {
+                    register: CpuidRegister::Ebx,
+                    bitmap: RegisterValueFilter {
+                        filter: 0b1100,
+                        value: 0b1000,
+                    },
+                    passthrough: false,
+                },
+            ],
+            ..template.cpuid_modifiers[0].clone()
+        });
+        template.msr_modifiers.push(RegisterModifier {
+            addr: 0x9999,
+            bitmap: RegisterValueFilter {
+                filter: 0b1,
+                value: 0b1,
+            },
+            source: None,
+        });
+
+        let mut simplified = template.clone();
+        simplified.remove_shadowed();
+        assert_eq!(simplified.effect_map(), template.effect_map());
+
+        let registers = |leaf_modifier: &CpuidLeafModifier| {
+            leaf_modifier
+                .modifiers
+                .iter()
+                .map(|reg_modifier| reg_modifier.register.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(simplified.cpuid_modifiers.len(), 2);
+        assert_eq!(
+            registers(&simplified.cpuid_modifiers[0]),
+            vec![CpuidRegister::Ebx, CpuidRegister::Ecx, CpuidRegister::Edx]
+        );
+        assert_eq!(
+            registers(&simplified.cpuid_modifiers[1]),
+            vec![CpuidRegister::Eax, CpuidRegister::Ebx]
+        );
+        assert_eq!(
+            simplified.msr_index_iter().collect::<Vec<_>>(),
+            vec![0x8000, 0x9999]
+        );
+
+        // Without shadowed modifiers, the template is unchanged.
+        let mut template = build_test_template();
+        template.remove_shadowed();
+        assert_eq!(template, build_test_template());
+    }
+
+    #[test]
+    fn test_kvm_op_estimate() {
+        // The MSRs are set even if the template leaves all of them unchanged.
+        assert_eq!(
+            build_test_template().kvm_op_estimate(4),
+            OpEstimate {
+                cpuid_ops: 4,
+                msr_ops: 4,
+            }
+        );
+        assert_eq!(
+            CustomCpuTemplate::default().kvm_op_estimate(2),
+            OpEstimate {
+                cpuid_ops: 2,
+                msr_ops: 2,
+            }
+        );
+
+        // Five CPUID entries and four MSRs are still set with one call of each kind per vCPU.
+        let template = CustomCpuTemplate::try_from(TEST_TEMPLATE_JSON).unwrap();
+        assert_eq!(
+            template.kvm_op_estimate(3),
+            OpEstimate {
+                cpuid_ops: 3,
+                msr_ops: 3,
+            }
+        );
+        assert_eq!(template.kvm_op_estimate(0), OpEstimate::default());
+    }
+
+    #[test]
+    fn test_overlaps() {
+        let template = build_test_template();
+        let json_template = CustomCpuTemplate::try_from(TEST_TEMPLATE_JSON).unwrap();
+        assert!(template.overlaps(&template));
+        assert!(!template.overlaps(&json_template));
+        assert!(!template.overlaps(&CustomCpuTemplate::default()));
+
+        // Same MSR.
+        let mut other = json_template.clone();
+        other.msr_modifiers.push(template.msr_modifiers[1]);
+        assert!(template.overlaps(&other));
+        assert!(other.overlaps(&template));
+
+        // Same CPUID register of a sub-leaf matched by a wildcard.
+        let mut other = json_template;
+        let mut leaf_modifier = template.cpuid_modifiers[0].clone();
+        leaf_modifier.subleaf = SUBLEAF_WILDCARD;
+        leaf_modifier.modifiers.truncate(1);
+        other.cpuid_modifiers.push(leaf_modifier.clone());
+        assert!(template.overlaps(&other));
+        assert!(other.overlaps(&template));
+
+        // Different register of the same leaf.
+        leaf_modifier.modifiers[0].register = CpuidRegister::Ebx;
+        let mut template = template;
+        template.cpuid_modifiers[0].modifiers.remove(1);
+        template.msr_modifiers.clear();
+        let other = CustomCpuTemplate {
+            cpuid_modifiers: vec![leaf_modifier],
+            ..Default::default()
+        };
+        assert!(!template.overlaps(&other));
+    }
+
+    #[test]
+    fn test_is_effectively_empty() {
+        assert!(CustomCpuTemplate::default().is_effectively_empty());
+
+        let mut template = build_test_template();
+        assert!(!template.is_effectively_empty());
+        for reg_modifier in template.cpuid_modifiers[0].modifiers.iter_mut() {
+            reg_modifier.bitmap = RegisterValueFilter::default();
+        }
+        // Only modifiers with don't-care bits are left.
+        assert!(template.is_effectively_empty());
+
+        template.kvm_capabilities.push(KvmCapability::Add(1));
+        assert!(!template.is_effectively_empty());
+    }
+
+    #[test]
+    fn test_msr_index_set() {
+        let template = build_test_template();
+        let msr_index_set = template.msr_index_set();
+        assert!(msr_index_set.touches_msr(0x9999));
+        assert!(!msr_index_set.touches_msr(0x10));
+        for addr in template.msr_index_iter() {
+            assert!(msr_index_set.touches_msr(addr));
+        }
+        assert_eq!(msr_index_set, MsrIndexSet(HashSet::from([0x8000, 0x9999])));
+    }
+
+    #[test]
+    fn test_redacted() {
+        let template = CustomCpuTemplate::try_from(TEST_TEMPLATE_JSON).unwrap();
+        let redacted = template.redacted(&HashSet::from([0x1, 0xbbca]));
+
+        assert_eq!(
+            redacted.msr_index_iter().collect::<Vec<_>>(),
+            template.msr_index_iter().collect::<Vec<_>>()
+        );
+        for (original, redacted) in template.msr_modifiers.iter().zip(&redacted.msr_modifiers) {
+            if original.addr == 0x1 || original.addr == 0xbbca {
+                assert_eq!(redacted.bitmap, RegisterValueFilter::default());
             } else {
-                assert_eq!(
-                    cpu_template.get_cpu_template().unwrap_err(),
-                    GetCpuTemplateError::InvalidCpuModel,
-                )
+                assert_eq!(redacted.bitmap, original.bitmap);
             }
-        } else {
+        }
+        assert_eq!(redacted.cpuid_modifiers, template.cpuid_modifiers);
+        assert_eq!(
+            template,
+            CustomCpuTemplate::try_from(TEST_TEMPLATE_JSON).unwrap()
+        );
+
+        let json = serde_json::to_string(&redacted).unwrap();
+        assert!(
+            json.contains(&format!("\"0b{}\"", "x".repeat(64))),
+            "{json}"
+        );
+    }
+
+    #[test]
+    fn test_without_msrs_and_cpuid() {
+        let template = CustomCpuTemplate::try_from(TEST_TEMPLATE_JSON).unwrap();
+
+        let without_msrs = template.without_msrs();
+        assert!(without_msrs.msr_modifiers.is_empty());
+        assert_eq!(without_msrs.cpuid_modifiers, template.cpuid_modifiers);
+        assert_eq!(without_msrs.kvm_capabilities, template.kvm_capabilities);
+
+        let without_cpuid = template.without_cpuid();
+        assert!(without_cpuid.cpuid_modifiers.is_empty());
+        assert_eq!(without_cpuid.msr_modifiers, template.msr_modifiers);
+        assert_eq!(without_cpuid.kvm_capabilities, template.kvm_capabilities);
+
+        assert!(!template.msr_modifiers.is_empty());
+        assert!(!template.cpuid_modifiers.is_empty());
+    }
+
+    #[test]
+    fn test_remap_msr_addrs() {
+        let original = CustomCpuTemplate::try_from(TEST_TEMPLATE_JSON).unwrap();
+
+        // MSRs 0x0 and 0x1 swap addresses and 0xbbca moves to an unused one.
+        let mut template = original.clone();
+        let map = HashMap::from([(0x0, 0x1), (0x1, 0x0), (0xbbca, 0x4000_0000)]);
+        template.remap_msr_addrs(&map).unwrap();
+        assert_eq!(
+            template.msr_index_iter().collect::<Vec<_>>(),
+            vec![0x1, 0x0, 0x3, 0x4000_0000]
+        );
+        for (remapped, modifier) in template.msr_modifiers.iter().zip(&original.msr_modifiers) {
+            assert_eq!(remapped.bitmap, modifier.bitmap);
+        }
+        assert_eq!(template.cpuid_modifiers, original.cpuid_modifiers);
+
+        // Modifiers of the same MSR stay together.
+        let mut template = original.clone();
+        template.msr_modifiers.push(original.msr_modifiers[0]);
+        template
+            .remap_msr_addrs(&HashMap::from([(0x0, 0x10)]))
+            .unwrap();
+        assert_eq!(template.msr_modifiers[0].addr, 0x10);
+        assert_eq!(template.msr_modifiers[4].addr, 0x10);
+    }
+
+    #[test]
+    fn test_remap_msr_addrs_collision() {
+        let original = CustomCpuTemplate::try_from(TEST_TEMPLATE_JSON).unwrap();
+        let mut template = original.clone();
+
+        let error = template
+            .remap_msr_addrs(&HashMap::from([(0x1, 0x3)]))
+            .unwrap_err();
+        assert_eq!(
+            error,
+            MsrRemapError {
+                first: 0x1,
+                second: 0x3,
+                addr: 0x3,
+            }
+        );
+        assert_eq!(
+            error.to_string(),
+            "Remapping MSRs 0x1 and 0x3 would make both of them modify MSR 0x3."
+        );
+        assert_eq!(template, original);
+
+        let error = template
+            .remap_msr_addrs(&HashMap::from([(0x0, 0xbbcb), (0xbbca, 0xbbcb)]))
+            .unwrap_err();
+        assert_eq!(error.addr, 0xbbcb);
+        assert_eq!(template, original);
+    }
+
+    #[test]
+    fn test_cpuid_register_modifier_new_checked() {
+        let modifier = CpuidRegisterModifier::new_checked(
+            CpuidRegister::Ecx,
+            RegisterValueFilter {
+                filter: 0xffff_0000,
+                value: 0x8000_0000,
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            modifier,
+            CpuidRegisterModifier {
+                register: CpuidRegister::Ecx,
+                bitmap: RegisterValueFilter {
+                    filter: 0xffff_0000,
+                    value: 0x8000_0000,
+                },
+                passthrough: false,
+            }
+        );
+
+        let error = CpuidRegisterModifier::new_checked(
+            CpuidRegister::Ecx,
+            RegisterValueFilter {
+                filter: 0x1_0000_0001,
+                value: 0x1,
+            },
+        )
+        .unwrap_err();
+        assert_eq!(error, WidthError::Cpuid(CpuidRegister::Ecx, 0x1_0000_0000));
+        assert_eq!(
+            error.to_string(),
+            "Bitmap of CPUID register Ecx sets bits 0x100000000 beyond the 32 bits of the \
+             register."
+        );
+    }
+
+    fn build_msr_template(msrs: &[(u32, u64, u64)]) -> CustomCpuTemplate {
+        CustomCpuTemplate {
+            msr_modifiers: msrs
+                .iter()
+                .map(|&(addr, filter, value)| RegisterModifier {
+                    addr,
+                    bitmap: RegisterValueFilter { filter, value },
+                    source: None,
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_intersect_agreeing() {
+        let mut coalesced = build_test_template();
+        coalesced.coalesce();
+        assert_eq!(
+            build_test_template().intersect(&build_test_template()),
+            coalesced
+        );
+    }
+
+    #[test]
+    fn test_intersect_partially_agreeing() {
+        let template = build_msr_template(&[(0x10, 0b1111, 0b1010), (0x20, 0b1, 0b1)]);
+        let other = build_msr_template(&[(0x10, 0b0111, 0b0011), (0x30, 0b1, 0b1)]);
+
+        // Bit 3 is only covered by `template` and bit 0 is set to different values.
+        let expected = build_msr_template(&[(0x10, 0b0110, 0b0010)]);
+        assert_eq!(template.intersect(&other), expected);
+        assert_eq!(other.intersect(&template), expected);
+
+        let mut template = build_test_template();
+        template.cpuid_modifiers[0].modifiers[0].bitmap.value ^= 0b0001;
+        let intersection = template.intersect(&build_test_template());
+        assert_eq!(
+            intersection.cpuid_modifiers[0].modifiers[0],
+            CpuidRegisterModifier {
+                register: CpuidRegister::Eax,
+                bitmap: RegisterValueFilter {
+                    filter: 0b0110,
+                    value: 0b0100,
+                },
+                passthrough: false,
+            }
+        );
+        assert_eq!(intersection.cpuid_modifiers[0].modifiers.len(), 4);
+    }
+
+    #[test]
+    fn test_intersect_disjoint() {
+        let template = build_msr_template(&[(0x10, 0b1111, 0b1010)]);
+        let other = build_msr_template(&[(0x20, 0b1111, 0b1010)]);
+        assert_eq!(template.intersect(&other), CustomCpuTemplate::default());
+
+        let other = build_msr_template(&[(0x10, 0b1111, 0b0101)]);
+        assert_eq!(template.intersect(&other), CustomCpuTemplate::default());
+    }
+
+    #[test]
+    fn test_masked_to_range() {
+        let template = build_msr_template(&[(0x10, u64::MAX, 0xf0f0_0000_0000_f0f0)]);
+        let masked = template.masked_to_range(0, 31);
+        assert_eq!(masked, build_msr_template(&[(0x10, 0xffff_ffff, 0xf0f0)]));
+        assert_eq!(
+            serde_json::to_value(&masked.msr_modifiers[0]).unwrap()["bitmap"],
+            format!("0b{}{:032b}", "x".repeat(32), 0xf0f0)
+        );
+
+        let masked = template.masked_to_range(4, 11);
+        assert_eq!(masked, build_msr_template(&[(0x10, 0xff0, 0x0f0)]));
+        // The original template is left as is.
+        assert_eq!(template.msr_modifiers[0].bitmap.filter, u64::MAX);
+
+        // The window is capped at the width of CPUID registers.
+        let masked = build_test_template().masked_to_range(2, 63);
+        for reg_modifier in &masked.cpuid_modifiers[0].modifiers {
+            assert_eq!(reg_modifier.bitmap.filter, 0b0100);
+        }
+        let masked = build_test_template().masked_to_range(32, 63);
+        assert!(masked.is_effectively_empty());
+    }
+
+    #[test]
+    fn test_msr_field_source() {
+        let modifier = serde_json::from_str::<RegisterModifier>(
+            r#"{"addr": "0x48", "bitmap": "0b1111", "source": {"msr": "0x10a", "bits": [8, 11]}}"#,
+        )
+        .unwrap();
+        let source = MsrFieldSource {
+            msr: 0x10a,
+            bits: [8, 11],
+            default: None,
+        };
+        assert_eq!(modifier.source, Some(source));
+
+        let serialized = serde_json::to_value(modifier).unwrap();
+        assert_eq!(serialized["source"]["msr"], "0x10a");
+        assert_eq!(
+            serde_json::from_value::<RegisterModifier>(serialized).unwrap(),
+            modifier
+        );
+        // Other modifiers do not serialize the source.
+        let serialized = serde_json::to_value(RegisterModifier {
+            source: None,
+            ..modifier
+        })
+        .unwrap();
+        assert_eq!(serialized.get("source"), None);
+
+        let with_default = serde_json::from_str::<MsrFieldSource>(
+            r#"{"msr": "0x10a", "bits": [8, 11], "default": "0xa00"}"#,
+        )
+        .unwrap();
+        assert_eq!(with_default.default, Some(0xa00));
+        let serialized = serde_json::to_value(with_default).unwrap();
+        assert_eq!(serialized["default"], "0xa00");
+        assert_eq!(serde_json::to_value(source).unwrap().get("default"), None);
+
+        // The field is copied into the bits of the filter.
+        assert_eq!(source.copy_into(0b1111, 0xfa00), Some(0b1010));
+        assert_eq!(source.copy_into(0b1111_0000, 0xfa00), Some(0b1010_0000));
+        assert_eq!(
+            modifier.resolve_bitmap(|addr| (addr == 0x10a).then_some(0xfa00)),
+            Ok(RegisterValueFilter {
+                filter: 0b1111,
+                value: 0b1010,
+            })
+        );
+        assert_eq!(
+            modifier.resolve_bitmap(|_| None),
+            Err(MsrSourceError::SourceNotPresent(0x10a))
+        );
+        let full_width = MsrFieldSource {
+            msr: 0x10a,
+            bits: [0, 63],
+            default: None,
+        };
+        assert_eq!(full_width.copy_into(u64::MAX, 0x1234), Some(0x1234));
+
+        // Filters must be a contiguous range as wide as the field.
+        for filter in [0, 0b111, 0b1_1111, 0b1011_0000] {
+            assert_eq!(source.copy_into(filter, 0xfa00), None);
+        }
+        for bits in [[11, 8], [60, 64]] {
+            let source = MsrFieldSource {
+                msr: 0x10a,
+                bits,
+                default: None,
+            };
+            assert_eq!(source.copy_into(0b1111, 0xfa00), None);
+        }
+
+        let mut template = CustomCpuTemplate {
+            msr_modifiers: vec![modifier],
+            ..Default::default()
+        };
+        template.validate().unwrap();
+        template.msr_modifiers[0].bitmap = RegisterValueFilter {
+            filter: 0b111,
+            value: 0,
+        };
+        assert_eq!(
+            template.validate().unwrap_err().to_string(),
+            "Filter of the modifier of MSR 0x48 does not match the bits of its source field."
+        );
+    }
+
+    #[test]
+    fn test_msr_field_source_transforms() {
+        let source = MsrFieldSource {
+            msr: 0x10a,
+            bits: [8, 11],
+            default: None,
+        };
+        let mut template = build_msr_template(&[
+            (0x50, 0b1, 0b1),
+            (0x48, 0b1111, 0),
+            (0x10, 0b01, 0),
+            (0x48, 0b1_0000, 0b1_0000),
+            (0x10, 0b10, 0b10),
+        ]);
+        template.msr_modifiers[1].source = Some(source);
+        let mut expected = build_msr_template(&[
+            (0x10, 0b11, 0b10),
+            (0x48, 0b1_0000, 0b1_0000),
+            (0x48, 0b1111, 0),
+            (0x50, 0b1, 0b1),
+        ]);
+        expected.msr_modifiers[2].source = Some(source);
+
+        // The modifier with a source is kept after the modifier of the other bits of its MSR.
+        template.coalesce();
+        assert_eq!(template, expected);
+        assert_eq!(template.msr_source_iter().collect::<Vec<_>>(), vec![0x10a]);
+
+        // The source field is narrowed along with the filter.
+        let masked = template.masked_to_range(2, 63);
+        assert_eq!(masked.msr_modifiers[2].bitmap.filter, 0b1100);
+        assert_eq!(masked.msr_modifiers[2].source.unwrap().bits, [10, 11]);
+        masked.validate().unwrap();
+        let masked = template.masked_to_range(8, 63);
+        assert_eq!(masked.msr_modifiers[2].source, None);
+
+        // Source MSRs are remapped along with the modified MSRs.
+        template
+            .remap_msr_addrs(&HashMap::from([(0x10a, 0x10b)]))
+            .unwrap();
+        assert_eq!(template.msr_source_iter().collect::<Vec<_>>(), vec![0x10b]);
+    }
+
+    #[test]
+    fn test_msr_field_source_effect_map() {
+        let source = MsrFieldSource {
+            msr: 0x10a,
+            bits: [8, 11],
+            default: None,
+        };
+        let mut template = build_msr_template(&[(0x48, 0b1_0000, 0b1_0000), (0x48, 0b1111, 0)]);
+        template.msr_modifiers[1].source = Some(source);
+        template.validate().unwrap();
+
+        let effect_map = template.effect_map();
+        assert_eq!(
+            effect_map.msrs,
+            BTreeMap::from([(
+                0x48,
+                RegisterValueFilter {
+                    filter: 0b1_1111,
+                    value: 0b1_0000,
+                }
+            )])
+        );
+        assert_eq!(
+            effect_map.msr_sources,
+            BTreeMap::from([(0x48, template.msr_modifiers[1])])
+        );
+
+        // Later modifiers can neither change bits of the field nor copy another field.
+        for modifier in [
+            RegisterModifier {
+                addr: 0x48,
+                bitmap: RegisterValueFilter {
+                    filter: 0b1000,
+                    value: 0,
+                },
+                source: None,
+            },
+            template.msr_modifiers[1],
+        ] {
+            let mut template = template.clone();
+            template.msr_modifiers.push(modifier);
             assert_eq!(
-                cpu_template.get_cpu_template().unwrap_err(),
-                GetCpuTemplateError::CpuVendorMismatched,
+                template.validate().unwrap_err().to_string(),
+                "Modifiers of MSR 0x48 copy more than one field into it, or change bits of a \
+                 copied field."
             );
         }
     }
 
     #[test]
-    fn test_get_cpu_template_with_t2a_static_template() {
-        // Test `get_cpu_template()` when T2A static CPU template is specified. The owned
-        // `CustomCpuTemplate` should be returned if CPU vendor is AMD. Otherwise it should fail.
-        let cpu_template = Some(CpuTemplateType::Static(StaticCpuTemplate::T2A));
-        if &get_vendor_id_from_host().unwrap() == VENDOR_ID_AMD {
-            assert_eq!(
-                cpu_template.get_cpu_template().unwrap(),
-                Cow::Owned(t2a::t2a())
-            );
+    fn test_msr_field_source_merge() {
+        let source = MsrFieldSource {
+            msr: 0x10a,
+            bits: [8, 11],
+            default: None,
+        };
+        let mut sourced = build_msr_template(&[(0x48, 0b1111, 0)]);
+        sourced.msr_modifiers[0].source = Some(source);
+
+        // The field is kept if no other template sets any of its bits.
+        let literal = build_msr_template(&[(0x48, 0b1_0000, 0b1_0000)]);
+        let mut expected = build_msr_template(&[(0x48, 0b1_0000, 0b1_0000), (0x48, 0b1111, 0)]);
+        expected.msr_modifiers[1].source = Some(source);
+        for strategy in [
+            MergeStrategy::LaterWins,
+            MergeStrategy::EarlierWins,
+            MergeStrategy::ErrorOnConflict,
+        ] {
+            assert_eq!(sourced.merge(&literal, strategy).unwrap(), expected);
+            assert_eq!(literal.merge(&sourced, strategy).unwrap(), expected);
+        }
+
+        // Otherwise, the field is kept or overwritten as a whole.
+        let overlapping = build_msr_template(&[(0x48, 0b1111, 0b0101)]);
+        assert_eq!(
+            sourced
+                .merge(&overlapping, MergeStrategy::LaterWins)
+                .unwrap(),
+            overlapping
+        );
+        assert_eq!(
+            sourced
+                .merge(&overlapping, MergeStrategy::EarlierWins)
+                .unwrap(),
+            sourced
+        );
+        assert_eq!(
+            sourced.merge(&overlapping, MergeStrategy::ErrorOnConflict),
+            Err(MergeConflictError::MsrSource(0x48))
+        );
+        let partial = build_msr_template(&[(0x48, 0b11, 0b01)]);
+        assert_eq!(
+            partial.merge(&sourced, MergeStrategy::LaterWins).unwrap(),
+            sourced
+        );
+        assert_eq!(
+            sourced.merge(&partial, MergeStrategy::LaterWins),
+            Err(MergeConflictError::MsrSource(0x48))
+        );
+
+        // Only one field can be copied into an MSR.
+        let mut other_sourced = build_msr_template(&[(0x48, 0b1111_0000, 0)]);
+        other_sourced.msr_modifiers[0].source = Some(MsrFieldSource {
+            msr: 0x10b,
+            ..source
+        });
+        let error = sourced
+            .merge(&other_sourced, MergeStrategy::LaterWins)
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Templates copy different fields into MSR 0x48, or only part of a copied field is \
+             overwritten."
+        );
+    }
+
+    #[test]
+    fn test_msr_field_source_intersect() {
+        let source = MsrFieldSource {
+            msr: 0x10a,
+            bits: [8, 11],
+            default: None,
+        };
+        let mut sourced = build_msr_template(&[(0x48, 0b1111, 0)]);
+        sourced.msr_modifiers[0].source = Some(source);
+        let mut with_literal = build_msr_template(&[(0x48, 0b1_0000, 0b1_0000), (0x48, 0b1111, 0)]);
+        with_literal.msr_modifiers[1].source = Some(source);
+
+        assert_eq!(sourced.intersect(&sourced), sourced);
+        assert_eq!(with_literal.intersect(&sourced), sourced);
+        // The values of the field are not known, even if the bits happen to agree.
+        let zeroed = build_msr_template(&[(0x48, 0b1111, 0)]);
+        assert_eq!(sourced.intersect(&zeroed), CustomCpuTemplate::default());
+        assert_eq!(zeroed.intersect(&sourced), CustomCpuTemplate::default());
+    }
+
+    #[test]
+    fn test_resolve_all() {
+        let custom = CustomCpuTemplate::try_from(TEST_TEMPLATE_JSON).unwrap();
+        let (supported, mismatched) = if &get_vendor_id_from_host().unwrap() == VENDOR_ID_INTEL {
+            (StaticCpuTemplate::T2, StaticCpuTemplate::T2A)
         } else {
+            (StaticCpuTemplate::T2A, StaticCpuTemplate::T2)
+        };
+        let requests = [
+            None,
+            Some(CpuTemplateType::Static(supported)),
+            Some(CpuTemplateType::Static(mismatched)),
+            Some(CpuTemplateType::Custom(custom.clone())),
+            Some(CpuTemplateType::Static(StaticCpuTemplate::None)),
+        ];
+
+        let results = resolve_all(&requests);
+        assert_eq!(results.len(), requests.len());
+        assert_eq!(
+            results[0].as_ref().unwrap(),
+            &Cow::Owned(CustomCpuTemplate::default())
+        );
+        assert_eq!(
+            results[1].as_ref().unwrap(),
+            &requests[1].get_cpu_template().unwrap()
+        );
+        assert_eq!(
+            results[2].as_ref().unwrap_err(),
+            &GetCpuTemplateError::CpuVendorMismatched
+        );
+        assert_eq!(results[3].as_ref().unwrap(), &Cow::Borrowed(&custom));
+        assert_eq!(
+            results[4].as_ref().unwrap_err(),
+            &GetCpuTemplateError::InvalidStaticCpuTemplate(StaticCpuTemplate::None)
+        );
+    }
+
+    #[test]
+    fn test_static_templates_equal_on_host() {
+        use crate::cpu_config::x86_64::cpuid::{Cpuid, CpuidEntry, CpuidKey, IntelCpuid};
+
+        // Synthetic Cascade Lake host with every CPUID leaf T2 and T2S modify zeroed, on which
+        // T2S sets IA32_ARCH_CAPABILITIES to the value it already has.
+        let t2s_arch_capabilities = t2s::t2s().msr_modifiers[0].bitmap.value;
+        let entries = t2s::t2s()
+            .cpuid_modifiers
+            .iter()
+            .map(|leaf_modifier| {
+                let key = CpuidKey::subleaf(leaf_modifier.leaf, leaf_modifier.subleaf);
+                (key, CpuidEntry::default())
+            })
+            .collect();
+        let mut host = HostSnapshot {
+            vendor: *VENDOR_ID_INTEL,
+            model: CpuModel::from(&0x0005_0657),
+            cpuid: Cpuid::Intel(IntelCpuid(entries)),
+            msrs: HashMap::from([(0x10a, t2s_arch_capabilities)]),
+        };
+
+        assert!(static_templates_equal_on_host(
+            StaticCpuTemplate::T2,
+            StaticCpuTemplate::T2S,
+            &host
+        ));
+        assert!(!static_templates_equal_on_host(
+            StaticCpuTemplate::T2,
+            StaticCpuTemplate::None,
+            &host
+        ));
+        // T2A cannot be used on an Intel host.
+        assert!(!static_templates_equal_on_host(
+            StaticCpuTemplate::T2A,
+            StaticCpuTemplate::T2A,
+            &host
+        ));
+
+        host.msrs.insert(0x10a, 0x0);
+        assert!(!static_templates_equal_on_host(
+            StaticCpuTemplate::T2,
+            StaticCpuTemplate::T2S,
+            &host
+        ));
+        assert!(static_templates_equal_on_host(
+            StaticCpuTemplate::T2S,
+            StaticCpuTemplate::T2S,
+            &host
+        ));
+    }
+
+    #[test]
+    fn test_materialize_static() {
+        use crate::cpu_config::x86_64::cpuid::{Cpuid, CpuidEntry, CpuidKey, IntelCpuid};
+
+        // Synthetic Cascade Lake host with every CPUID leaf T2 and T2S modify zeroed.
+        let entries = t2s::t2s()
+            .cpuid_modifiers
+            .iter()
+            .map(|leaf_modifier| {
+                let key = CpuidKey::subleaf(leaf_modifier.leaf, leaf_modifier.subleaf);
+                (key, CpuidEntry::default())
+            })
+            .collect();
+        let host = HostSnapshot {
+            vendor: *VENDOR_ID_INTEL,
+            model: CpuModel::from(&0x0005_0657),
+            cpuid: Cpuid::Intel(IntelCpuid(entries)),
+            msrs: HashMap::from([(0x10a, 0xff)]),
+        };
+        let host_config = CpuConfiguration {
+            cpuid: host.cpuid.clone(),
+            msrs: BTreeMap::from([(0x10a, 0xff)]),
+        };
+
+        for template in [StaticCpuTemplate::T2, StaticCpuTemplate::T2S] {
+            let materialized = CustomCpuTemplate::materialize_static(template, &host).unwrap();
+            assert!(!materialized.cpuid_modifiers.is_empty());
+            // Every bit of the modified registers is set or cleared.
+            assert!(materialized.into_iter().all(|modifier| match modifier {
+                ModifierRef::Cpuid(_, reg_modifier) => reg_modifier.bitmap.filter == u32::MAX,
+                ModifierRef::Msr(modifier) => modifier.bitmap.filter == u64::MAX,
+            }));
+
+            let static_template =
+                static_cpu_template(template, &host.vendor, &host.model).unwrap();
             assert_eq!(
-                cpu_template.get_cpu_template().unwrap_err(),
-                GetCpuTemplateError::CpuVendorMismatched,
+                host_config.clone().apply_template(&materialized).unwrap(),
+                host_config.clone().apply_template(&static_template).unwrap()
             );
         }
+
+        // T2A cannot be used on an Intel host.
+        assert_eq!(
+            CustomCpuTemplate::materialize_static(StaticCpuTemplate::T2A, &host),
+            Err(MaterializeError::Static(
+                GetCpuTemplateError::CpuVendorMismatched
+            ))
+        );
     }
 
     #[test]
-    fn test_get_cpu_template_with_none_static_template() {
-        // Test `get_cpu_template()` when no static CPU template is provided.
-        // `InvalidStaticCpuTemplate` error should be returned because it is no longer valid and
-        // was replaced with `None` of `Option<CpuTemplateType>`.
-        let cpu_template = Some(CpuTemplateType::Static(StaticCpuTemplate::None));
+    fn test_prune_noop() {
+        let mut template = build_test_template();
+        template.cpuid_modifiers[0].modifiers[1].bitmap = RegisterValueFilter::default();
+        template.cpuid_modifiers.push(CpuidLeafModifier {
+            leaf: 0x4,
+            subleaf: 0x0,
+            flags: KvmCpuidFlags::EMPTY,
+            modifiers: vec![CpuidRegisterModifier {
+                register: CpuidRegister::Eax,
+                bitmap: RegisterValueFilter::default(),
+                passthrough: false,
+            }],
+        });
+
+        let mut expected = build_test_template();
+        expected.cpuid_modifiers[0].modifiers.remove(1);
+        // MSR modifiers of the test template are all don't-care.
+        expected.msr_modifiers.clear();
+
+        template.prune_noop();
+        assert_eq!(template, expected);
+    }
+
+    #[test]
+    fn test_describe() {
+        assert_eq!(build_test_template().describe(), "1 CPUID leaf, 2 MSRs");
         assert_eq!(
-            cpu_template.get_cpu_template().unwrap_err(),
-            GetCpuTemplateError::InvalidStaticCpuTemplate(StaticCpuTemplate::None)
+            CustomCpuTemplate::try_from(TEST_TEMPLATE_JSON)
+                .unwrap()
+                .describe(),
+            "5 CPUID leaves, 4 MSRs"
         );
 
-        // Test the Display for StaticCpuTemplate
-        assert_eq!(format!("{}", StaticCpuTemplate::None), "None");
+        let template = CustomCpuTemplate {
+            kvm_capabilities: vec![KvmCapability::Add(1)],
+            msr_modifiers: build_test_template().msr_modifiers[..1].to_vec(),
+            ..Default::default()
+        };
+        assert_eq!(
+            template.describe(),
+            "0 CPUID leaves, 1 MSR, 1 KVM capability"
+        );
+
+        // Sub-leaves of the same leaf are counted once.
+        let clear_bits = |register, bits: u32| CpuidRegisterModifier {
+            register,
+            bitmap: RegisterValueFilter {
+                filter: bits,
+                value: 0,
+            },
+            passthrough: false,
+        };
+        let template = CustomCpuTemplate {
+            cpuid_modifiers: vec![
+                CpuidLeafModifier {
+                    leaf: 0x1,
+                    subleaf: 0x0,
+                    flags: KvmCpuidFlags::EMPTY,
+                    modifiers: vec![clear_bits(CpuidRegister::Ecx, 1 << 20)],
+                },
+                CpuidLeafModifier {
+                    leaf: 0x7,
+                    subleaf: 0x0,
+                    flags: KvmCpuidFlags::SIGNIFICANT_INDEX,
+                    modifiers: vec![clear_bits(CpuidRegister::Ebx, 1 << 11)],
+                },
+                CpuidLeafModifier {
+                    leaf: 0x7,
+                    subleaf: 0x1,
+                    flags: KvmCpuidFlags::SIGNIFICANT_INDEX,
+                    modifiers: vec![clear_bits(CpuidRegister::Eax, 1 << 4)],
+                },
+            ],
+            ..Default::default()
+        };
+        assert_eq!(template.describe(), "2 CPUID leaves, 0 MSRs");
     }
 
     #[test]
-    fn test_get_cpu_template_with_custom_template() {
-        // Test `get_cpu_template()` when a custom CPU template is provided. The borrowed
-        // `CustomCpuTemplate` should be returned.
-        let inner_cpu_template = CustomCpuTemplate::default();
-        let cpu_template = Some(CpuTemplateType::Custom(inner_cpu_template.clone()));
+    fn test_distinct_flags() {
+        let mut template = CustomCpuTemplate::try_from(TEST_TEMPLATE_JSON).unwrap();
+        assert_eq!(template.distinct_flags(), BTreeSet::from([0]));
+
+        template.cpuid_modifiers[1].flags = KvmCpuidFlags::SIGNIFICANT_INDEX;
+        template.cpuid_modifiers[3].flags = KvmCpuidFlags::SIGNIFICANT_INDEX;
         assert_eq!(
-            cpu_template.get_cpu_template().unwrap(),
-            Cow::Borrowed(&inner_cpu_template)
+            template.distinct_flags(),
+            BTreeSet::from([0, KvmCpuidFlags::SIGNIFICANT_INDEX.0])
         );
+        assert_eq!(CustomCpuTemplate::default().distinct_flags(), BTreeSet::new());
     }
 
     #[test]
-    fn test_malformed_json() {
-        // Misspelled field name, register
-        let cpu_template_result = serde_json::from_str::<CustomCpuTemplate>(
-            r#"{
-                    "cpuid_modifiers": [
-                        {
-                            "leaf": "0x80000001",
-                            "subleaf": "0b000111",
-                            "flags": 0,
-                            "modifiers": [
-                                {
-                                    "register": "ekx",
-                                    "bitmap": "0bx00100xxx1xxxxxxxxxxxxxxxxxxxxx1"
-                                }
-                            ]
-                        },
-                    ],
-                }"#,
+    fn test_modifier_histogram() {
+        // 4 CPUID register modifiers filtering 3 bits and 2 MSR modifiers filtering none.
+        assert_eq!(
+            build_test_template().modifier_histogram(),
+            BTreeMap::from([(0, 2), (3, 4)])
         );
-        assert!(cpu_template_result
-            .unwrap_err()
-            .to_string()
-            .contains("Invalid CPUID register. Must be one of [eax, ebx, ecx, edx]"));
 
-        // Malformed MSR register address
-        let cpu_template_result = serde_json::from_str::<CustomCpuTemplate>(
-            r#"{
-                    "msr_modifiers":  [
-                        {
-                            "addr": "0jj0",
-                            "bitmap": "0bx00100xxx1xxxx00xxx1xxxxxxxxxxx1"
-                        },
-                    ]
-                }"#,
+        let mut template = build_msr_template(&[(0x10, u64::MAX, 0x0), (0x20, 0b1010, 0b1000)]);
+        template.cpuid_modifiers = build_test_template().cpuid_modifiers;
+        assert_eq!(
+            template.modifier_histogram(),
+            BTreeMap::from([(2, 1), (3, 4), (64, 1)])
         );
-        let error_msg: String = cpu_template_result.unwrap_err().to_string();
-        // Formatted error expected clarifying the number system prefix is missing
-        assert!(
-            error_msg.contains("No supported number system prefix found in value"),
-            "{}",
-            error_msg
+        assert_eq!(
+            CustomCpuTemplate::default().modifier_histogram(),
+            BTreeMap::new()
         );
+    }
 
-        // Malformed CPUID leaf address
-        let cpu_template_result = serde_json::from_str::<CustomCpuTemplate>(
-            r#"{
-                    "cpuid_modifiers": [
-                        {
-                            "leaf": "k",
-                            "subleaf": "0b000111",
-                            "flags": 0,
-                            "modifiers": [
-                                {
-                                    "register": "eax",
-                                    "bitmap": "0bx00100xxx1xxxxxxxxxxxxxxxxxxxxx1"
-                                }
-                            ]
-                        },
-                    ],
-                }"#,
+    #[test]
+    fn test_merge() {
+        let earlier = build_msr_template(&[(0x10, 0b0111, 0b0101), (0x20, 0b1, 0b1)]);
+        let later = build_msr_template(&[(0x10, 0b1110, 0b1000), (0x30, 0b1, 0b0)]);
+
+        // Bit 2 of MSR 0x10 is set by `earlier` and cleared by `later`.
+        assert_eq!(
+            earlier.merge(&later, MergeStrategy::default()).unwrap(),
+            build_msr_template(&[
+                (0x10, 0b1111, 0b1001),
+                (0x20, 0b1, 0b1),
+                (0x30, 0b1, 0b0),
+            ])
         );
-        let error_msg: String = cpu_template_result.unwrap_err().to_string();
-        // Formatted error expected clarifying the number system prefix is missing
-        assert!(
-            error_msg.contains("No supported number system prefix found in value"),
-            "{}",
-            error_msg
+        assert_eq!(
+            earlier.merge(&later, MergeStrategy::EarlierWins).unwrap(),
+            build_msr_template(&[
+                (0x10, 0b1111, 0b1101),
+                (0x20, 0b1, 0b1),
+                (0x30, 0b1, 0b0),
+            ])
         );
-        // Malformed 64-bit bitmap - filter failed
-        let cpu_template_result = serde_json::from_str::<CustomCpuTemplate>(
-            r#"{
-                    "msr_modifiers":  [
-                        {
-                            "addr": "0x200",
-                            "bitmap": "0bx0?1_0_0x_?x1xxxx00xxx1xxxxxxxxxxx1"
-                        },
-                    ]
-                }"#,
+        let error = earlier
+            .merge(&later, MergeStrategy::ErrorOnConflict)
+            .unwrap_err();
+        assert_eq!(
+            error,
+            MergeConflictError::Msr {
+                addr: 0x10,
+                bits: 0b0100,
+            }
         );
-        assert!(cpu_template_result.unwrap_err().to_string().contains(
-            "Failed to parse string [0bx0?1_0_0x_?x1xxxx00xxx1xxxxxxxxxxx1] as a bitmap"
-        ));
-        // Malformed 64-bit bitmap - value failed
-        let cpu_template_result = serde_json::from_str::<CustomCpuTemplate>(
-            r#"{
-                    "msr_modifiers":  [
-                        {
-                            "addr": "0x200",
-                            "bitmap": "0bx00100x0x1xxxx05xxx1xxxxxxxxxxx1"
-                        },
-                    ]
-                }"#,
+        assert_eq!(
+            error.to_string(),
+            "Templates set bits 0x4 of MSR 0x10 to different values."
         );
-        assert!(cpu_template_result
-            .unwrap_err()
-            .to_string()
-            .contains("Failed to parse string [0bx00100x0x1xxxx05xxx1xxxxxxxxxxx1] as a bitmap"));
+
+        // Without conflicting bits, all strategies give the same result.
+        let later = build_msr_template(&[(0x10, 0b1000, 0b1000)]);
+        let expected = build_msr_template(&[(0x10, 0b1111, 0b1101), (0x20, 0b1, 0b1)]);
+        for strategy in [
+            MergeStrategy::LaterWins,
+            MergeStrategy::EarlierWins,
+            MergeStrategy::ErrorOnConflict,
+        ] {
+            assert_eq!(earlier.merge(&later, strategy).unwrap(), expected);
+        }
     }
 
     #[test]
-    fn test_deserialization_lifecycle() {
-        let cpu_template = serde_json::from_str::<CustomCpuTemplate>(TEST_TEMPLATE_JSON)
-            .expect("Failed to deserialize custom CPU template.");
-        assert_eq!(5, cpu_template.cpuid_modifiers.len());
-        assert_eq!(4, cpu_template.msr_modifiers.len());
+    fn test_merge_operator() {
+        let earlier = build_msr_template(&[(0x10, 0b0111, 0b0101), (0x20, 0b1, 0b1)]);
+        let mut later = build_msr_template(&[(0x10, 0b1110, 0b1000), (0x30, 0b1, 0b0)]);
+        later.cpuid_modifiers = build_test_template().cpuid_modifiers;
+        let merged = earlier.merge(&later, MergeStrategy::LaterWins).unwrap();
+
+        assert_eq!(earlier.clone() + later.clone(), merged);
+        let mut template = earlier;
+        template += later;
+        assert_eq!(template, merged);
     }
 
     #[test]
-    fn test_serialization_lifecycle() {
-        let template = build_test_template();
-        let template_json_str_result = serde_json::to_string_pretty(&template);
-        let template_json = template_json_str_result.unwrap();
-
-        let deserialization_result = serde_json::from_str::<CustomCpuTemplate>(&template_json);
-        assert_eq!(template, deserialization_result.unwrap());
+    #[should_panic(expected = "Failed to merge CPU templates")]
+    fn test_merge_operator_conflict() {
+        let mut wildcard = build_test_template();
+        wildcard.cpuid_modifiers[0].subleaf = SUBLEAF_WILDCARD;
+        let _ = build_test_template() + wildcard;
     }
 
-    /// Test to confirm that templates for different CPU architectures have
-    /// a size bitmask that is supported by the architecture when serialized to JSON.
     #[test]
-    fn test_bitmap_width() {
-        let mut cpuid_checked = false;
-        let mut msr_checked = false;
+    fn test_merge_kvm_capabilities() {
+        let mut earlier = build_test_template();
+        earlier.kvm_capabilities = vec![KvmCapability::Add(1), KvmCapability::Remove(2)];
+        let mut later = build_msr_template(&[(0x10, 0b1, 0b1)]);
+        later.kvm_capabilities = vec![KvmCapability::Remove(2), KvmCapability::Add(3)];
 
-        let template = build_test_template();
+        let mut coalesced = earlier.clone();
+        coalesced.coalesce();
+        assert_eq!(coalesced.kvm_capabilities, earlier.kvm_capabilities);
+        assert_eq!(
+            earlier
+                .merge(&later, MergeStrategy::LaterWins)
+                .unwrap()
+                .kvm_capabilities,
+            vec![
+                KvmCapability::Add(1),
+                KvmCapability::Remove(2),
+                KvmCapability::Add(3),
+            ]
+        );
+    }
 
-        let x86_template_str =
-            serde_json::to_string(&template).expect("Error serializing x86 template");
-        let json_tree: Value = serde_json::from_str(&x86_template_str)
-            .expect("Error deserializing x86 template JSON string");
+    #[test]
+    fn test_merge_cpuid_conflict() {
+        let mut later = build_test_template();
+        later.cpuid_modifiers[0].modifiers[3].bitmap.value = 0b0011;
 
-        // Check that bitmaps for CPUID values are 32-bits in width
-        if let Some(cpuid_modifiers_root) = json_tree.get("cpuid_modifiers") {
-            let cpuid_mod_node = &cpuid_modifiers_root.as_array().unwrap()[0];
-            if let Some(modifiers_node) = cpuid_mod_node.get("modifiers") {
-                let mod_node = &modifiers_node.as_array().unwrap()[0];
-                if let Some(bit_map_str) = mod_node.get("bitmap") {
-                    // 32-bit width with a "0b" prefix for binary-formatted numbers
-                    assert_eq!(bit_map_str.as_str().unwrap().len(), 34);
-                    cpuid_checked = true;
-                }
+        let error = build_test_template()
+            .merge(&later, MergeStrategy::ErrorOnConflict)
+            .unwrap_err();
+        assert_eq!(
+            error,
+            MergeConflictError::Cpuid {
+                leaf: 0x3,
+                subleaf: 0x0,
+                register: CpuidRegister::Edx,
+                bits: 0b0010,
             }
-        }
+        );
 
-        // Check that bitmaps for MSRs are 64-bits in width
-        if let Some(msr_modifiers_root) = json_tree.get("msr_modifiers") {
-            let msr_mod_node = &msr_modifiers_root.as_array().unwrap()[0];
-            if let Some(bit_map_str) = msr_mod_node.get("bitmap") {
-                // 64-bit width with a "0b" prefix for binary-formatted numbers
-                assert_eq!(bit_map_str.as_str().unwrap().len(), 66);
-                assert!(bit_map_str.as_str().unwrap().starts_with("0b"));
-                msr_checked = true;
-            }
-        }
+        let merged = build_test_template()
+            .merge(&later, MergeStrategy::LaterWins)
+            .unwrap();
+        let mut expected = later.clone();
+        expected.coalesce();
+        assert_eq!(merged, expected);
+    }
 
-        assert!(
-            cpuid_checked,
-            "CPUID bitmap width in a x86_64 template was not tested."
+    #[test]
+    fn test_merge_subleaf_wildcard() {
+        let mut wildcard = build_test_template();
+        wildcard.cpuid_modifiers[0].subleaf = SUBLEAF_WILDCARD;
+
+        // Mixing both is rejected, even if the templates agree on every bit.
+        let error = build_test_template()
+            .merge(&wildcard, MergeStrategy::ErrorOnConflict)
+            .unwrap_err();
+        assert_eq!(
+            error,
+            MergeConflictError::SubleafWildcard {
+                leaf: 0x3,
+                register: CpuidRegister::Eax,
+            }
         );
-        assert!(
-            msr_checked,
-            "MSR bitmap width in a x86_64 template was not tested."
+        assert_eq!(
+            error.to_string(),
+            "Templates modify register Eax of CPUID leaf 0x3 both in all sub-leaves and in a \
+             single sub-leaf."
         );
+        for strategy in [MergeStrategy::LaterWins, MergeStrategy::EarlierWins] {
+            assert_eq!(
+                wildcard.merge(&build_test_template(), strategy).unwrap_err(),
+                MergeConflictError::SubleafWildcard {
+                    leaf: 0x3,
+                    register: CpuidRegister::Eax,
+                }
+            );
+        }
+
+        // Other leaves can be modified in single sub-leaves.
+        let mut other_leaf = build_test_template();
+        other_leaf.cpuid_modifiers[0].leaf = 0x4;
+        wildcard
+            .merge(&other_leaf, MergeStrategy::ErrorOnConflict)
+            .unwrap();
     }
 }
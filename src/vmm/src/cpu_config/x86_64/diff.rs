@@ -0,0 +1,266 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::collections::BTreeMap;
+
+use crate::arch::x86_64::cpu_model::CpuModel;
+use crate::cpu_config::templates::{CustomCpuTemplate, Numeric, RegisterValueFilter};
+use crate::cpu_config::x86_64::custom_cpu_template::{
+    static_cpu_template, CpuidEffectKey, CpuidRegister, SUBLEAF_WILDCARD,
+};
+use crate::cpu_config::x86_64::static_cpu_templates::StaticCpuTemplate;
+
+/// Filters applied to a register by the old and the new CPU template. `None` means the template
+/// does not modify the register.
+pub type FilterChange<V> = (Option<RegisterValueFilter<V>>, Option<RegisterValueFilter<V>>);
+
+/// Difference between the effects of two CPU templates.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct CpuTemplateDiff {
+    /// CPUID registers whose filter differs between the templates.
+    pub cpuid: BTreeMap<CpuidEffectKey, FilterChange<u32>>,
+    /// MSRs whose filter differs between the templates, keyed by MSR address.
+    pub msrs: BTreeMap<u32, FilterChange<u64>>,
+}
+
+fn diff_maps<K, V>(
+    old: &BTreeMap<K, RegisterValueFilter<V>>,
+    new: &BTreeMap<K, RegisterValueFilter<V>>,
+) -> BTreeMap<K, FilterChange<V>>
+where
+    K: Ord + Clone,
+    V: Numeric,
+{
+    old.keys()
+        .chain(new.keys())
+        .filter_map(|key| {
+            let (old_bitmap, new_bitmap) = (old.get(key).copied(), new.get(key).copied());
+            (old_bitmap != new_bitmap).then(|| (key.clone(), (old_bitmap, new_bitmap)))
+        })
+        .collect()
+}
+
+/// Get the name of a CPUID register as written in templates.
+fn register_name(register: &CpuidRegister) -> &'static str {
+    match register {
+        CpuidRegister::Eax => "eax",
+        CpuidRegister::Ebx => "ebx",
+        CpuidRegister::Ecx => "ecx",
+        CpuidRegister::Edx => "edx",
+    }
+}
+
+/// Get the character used in bitmap strings for a single bit of a filter.
+fn bit_char<V: Numeric>(bitmap: Option<&RegisterValueFilter<V>>, pos: u32) -> char {
+    match bitmap {
+        Some(bitmap) if bitmap.filter.bit(pos) => match bitmap.value.bit(pos) {
+            true => '1',
+            false => '0',
+        },
+        _ => 'x',
+    }
+}
+
+/// Get the number of bits whose state differs between the filters of `change`.
+fn num_changed_bits<V: Numeric>(change: &FilterChange<V>) -> usize {
+    (0..V::BITS)
+        .filter(|pos| bit_char(change.0.as_ref(), *pos) != bit_char(change.1.as_ref(), *pos))
+        .count()
+}
+
+fn push_unified_hunk<V: Numeric>(unified: &mut String, header: &str, change: &FilterChange<V>) {
+    unified.push_str(&format!("@@ {header} @@\n"));
+    for pos in (0..V::BITS).rev() {
+        let old_char = bit_char(change.0.as_ref(), pos);
+        let new_char = bit_char(change.1.as_ref(), pos);
+        if old_char != new_char {
+            unified.push_str(&format!("- bit {pos}: {old_char}\n"));
+            unified.push_str(&format!("+ bit {pos}: {new_char}\n"));
+        }
+    }
+}
+
+impl CpuTemplateDiff {
+    /// Check if the templates have the same effect.
+    pub fn is_empty(&self) -> bool {
+        self.cpuid.is_empty() && self.msrs.is_empty()
+    }
+
+    /// Get the number of CPUID register and MSR bits whose state differs between the templates.
+    pub fn num_changed_bits(&self) -> usize {
+        let cpuid_bits: usize = self.cpuid.values().map(num_changed_bits).sum();
+        let msr_bits: usize = self.msrs.values().map(num_changed_bits).sum();
+        cpuid_bits + msr_bits
+    }
+
+    /// Render the difference as text similar to a unified diff. Changes are grouped by CPUID
+    /// register and MSR, and every changed bit is listed with its old (`-`) and new (`+`) state,
+    /// where `x` means the bit is not modified by the template.
+    pub fn to_unified(&self) -> String {
+        let mut unified = String::new();
+        for (key, change) in &self.cpuid {
+            let subleaf = match key.subleaf {
+                SUBLEAF_WILDCARD => "*".to_string(),
+                subleaf => format!("{subleaf:#x}"),
+            };
+            let header = format!(
+                "cpuid leaf={:#x} subleaf={} flags={:#x} register={}",
+                key.leaf,
+                subleaf,
+                key.flags.0,
+                register_name(&key.register)
+            );
+            push_unified_hunk(&mut unified, &header, change);
+        }
+        for (addr, change) in &self.msrs {
+            push_unified_hunk(&mut unified, &format!("msr addr={addr:#x}"), change);
+        }
+        unified
+    }
+}
+
+impl CustomCpuTemplate {
+    /// Compare the effect of this template with the effect of `other`.
+    pub fn diff(&self, other: &CustomCpuTemplate) -> CpuTemplateDiff {
+        let (old, new) = (self.effect_map(), other.effect_map());
+        CpuTemplateDiff {
+            cpuid: diff_maps(&old.cpuid, &new.cpuid),
+            msrs: diff_maps(&old.msrs, &new.msrs),
+        }
+    }
+
+    /// Get the static CPU template usable on a CPU with the given vendor and model whose effect
+    /// is the nearest to the effect of this template, i.e. whose diff to this template changes
+    /// the fewest bits, along with that diff. Ties are resolved in favor of the template listed
+    /// first in [`StaticCpuTemplate`].
+    pub fn closest_static(
+        &self,
+        vendor: &[u8; 12],
+        model: &CpuModel,
+    ) -> Option<(StaticCpuTemplate, CpuTemplateDiff)> {
+        let candidates = [
+            StaticCpuTemplate::C3,
+            StaticCpuTemplate::T2,
+            StaticCpuTemplate::T2S,
+            StaticCpuTemplate::T2CL,
+            StaticCpuTemplate::T2A,
+        ];
+        let mut closest: Option<(StaticCpuTemplate, CpuTemplateDiff)> = None;
+        for candidate in candidates {
+            let Ok(static_template) = static_cpu_template(candidate, vendor, model) else {
+                continue;
+            };
+            let diff = static_template.diff(self);
+            let is_closer = closest
+                .as_ref()
+                .map_or(true, |(_, closest_diff)| {
+                    diff.num_changed_bits() < closest_diff.num_changed_bits()
+                });
+            if is_closer {
+                closest = Some((candidate, diff));
+            }
+        }
+        closest
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu_config::x86_64::custom_cpu_template::RegisterModifier;
+    use crate::cpu_config::x86_64::test_utils::build_test_template;
+
+    #[test]
+    fn test_diff_same_template() {
+        let template = build_test_template();
+        assert!(template.diff(&template).is_empty());
+        assert_eq!(template.diff(&template).to_unified(), "");
+    }
+
+    #[test]
+    fn test_diff_to_unified() {
+        let old = build_test_template();
+        let mut new = old.clone();
+        new.msr_modifiers[1].bitmap = RegisterValueFilter {
+            filter: 0b100,
+            value: 0b100,
+        };
+        new.msr_modifiers.push(RegisterModifier {
+            addr: 0x10,
+            bitmap: RegisterValueFilter {
+                filter: 0b1,
+                value: 0b0,
+            },
+            source: None,
+        });
+
+        let diff = old.diff(&new);
+        assert!(diff.cpuid.is_empty());
+        assert_eq!(diff.msrs.len(), 2);
+        assert_eq!(
+            diff.to_unified(),
+            "@@ msr addr=0x10 @@\n\
+             - bit 0: x\n\
+             + bit 0: 0\n\
+             @@ msr addr=0x8000 @@\n\
+             - bit 2: x\n\
+             + bit 2: 1\n"
+        );
+    }
+
+    #[test]
+    fn test_closest_static() {
+        use crate::cpu_config::x86_64::cpuid::{VENDOR_ID_AMD, VENDOR_ID_INTEL};
+        use crate::cpu_config::x86_64::static_cpu_templates::{t2, t2a};
+
+        let cascade_lake = CpuModel::from(&0x0005_0657);
+        let mut template = t2::t2();
+        template.cpuid_modifiers[0].modifiers[0].bitmap.filter |= 1 << 31;
+        template.cpuid_modifiers[0].modifiers[0].bitmap.value |= 1 << 31;
+        template.msr_modifiers.push(RegisterModifier {
+            addr: 0x10,
+            bitmap: RegisterValueFilter {
+                filter: 0b1,
+                value: 0b0,
+            },
+            source: None,
+        });
+
+        let (closest, diff) = template
+            .closest_static(VENDOR_ID_INTEL, &cascade_lake)
+            .unwrap();
+        assert_eq!(closest, StaticCpuTemplate::T2);
+        assert_eq!(diff.cpuid.len(), 1);
+        assert_eq!(diff.msrs.len(), 1);
+        assert_eq!(diff.num_changed_bits(), 2);
+
+        let (closest, diff) = t2::t2()
+            .closest_static(VENDOR_ID_INTEL, &cascade_lake)
+            .unwrap();
+        assert_eq!(closest, StaticCpuTemplate::T2);
+        assert!(diff.is_empty());
+
+        // T2A is the only static template usable on AMD CPUs.
+        let (closest, _) = template
+            .closest_static(VENDOR_ID_AMD, &cascade_lake)
+            .unwrap();
+        assert_eq!(closest, StaticCpuTemplate::T2A);
+        assert!(t2a::t2a()
+            .closest_static(VENDOR_ID_AMD, &cascade_lake)
+            .unwrap()
+            .1
+            .is_empty());
+    }
+
+    #[test]
+    fn test_diff_subleaf_wildcard() {
+        let old = build_test_template();
+        let mut new = old.clone();
+        new.cpuid_modifiers[0].subleaf = SUBLEAF_WILDCARD;
+        new.cpuid_modifiers[0].modifiers.truncate(1);
+
+        let unified = old.diff(&new).to_unified();
+        assert!(unified.contains("@@ cpuid leaf=0x3 subleaf=* flags=0x2 register=eax @@\n"));
+        assert!(unified.contains("@@ cpuid leaf=0x3 subleaf=0x0 flags=0x2 register=eax @@\n"));
+    }
+}
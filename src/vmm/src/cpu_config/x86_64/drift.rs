@@ -0,0 +1,311 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::collections::HashMap;
+
+use crate::cpu_config::templates::CustomCpuTemplate;
+use crate::cpu_config::x86_64::cpuid::{Cpuid, CpuidKey, CpuidRegisters};
+use crate::cpu_config::x86_64::custom_cpu_template::{CpuidRegister, SUBLEAF_WILDCARD};
+
+/// Register whose current value no longer matches the value intended by a CPU template.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Drift {
+    /// Bits of a CPUID register that differ from the template.
+    Cpuid {
+        /// Leaf value.
+        leaf: u32,
+        /// Sub-Leaf value.
+        subleaf: u32,
+        /// CPUID register.
+        register: CpuidRegister,
+        /// Mask of the bits that differ from the template.
+        drifted_bits: u32,
+    },
+    /// CPUID leaf modified by the template is not present.
+    MissingCpuid {
+        /// Leaf value.
+        leaf: u32,
+        /// Sub-Leaf value.
+        subleaf: u32,
+    },
+    /// Bits of an MSR that differ from the template.
+    Msr {
+        /// MSR address.
+        addr: u32,
+        /// Mask of the bits that differ from the template.
+        drifted_bits: u64,
+    },
+    /// MSR modified by the template is not present.
+    MissingMsr(u32),
+}
+
+/// Read access to the CPUID and MSRs of a vCPU, see [`CustomCpuTemplate::audit_live`].
+pub trait VcpuCpuidMsrAccess {
+    /// Error returned when reading fails.
+    type Error;
+
+    /// Read the CPUID of the vCPU.
+    fn read_cpuid(&self) -> Result<Cpuid, Self::Error>;
+
+    /// Read the values of the MSRs at `addrs` from the vCPU.
+    fn read_msrs(&self, addrs: &[u32]) -> Result<HashMap<u32, u64>, Self::Error>;
+}
+
+/// Get the value of a CPUID register.
+pub(crate) fn cpuid_register_value(registers: &CpuidRegisters, register: &CpuidRegister) -> u32 {
+    match register {
+        CpuidRegister::Eax => registers.eax,
+        CpuidRegister::Ebx => registers.ebx,
+        CpuidRegister::Ecx => registers.ecx,
+        CpuidRegister::Edx => registers.edx,
+    }
+}
+
+impl CustomCpuTemplate {
+    /// Check that the template is still in effect on the given CPUID and MSR values, i.e. that
+    /// applying the template again would not change any bit. Every register with bits that
+    /// differ from the template is reported, including every sub-leaf matched by a
+    /// [`SUBLEAF_WILDCARD`] modifier. Bits of fields copied from other MSRs are not checked, as
+    /// their value depends on the source MSR at the time the template was applied.
+    pub fn verify_applied(
+        &self,
+        current_cpuid: &Cpuid,
+        current_msrs: &HashMap<u32, u64>,
+    ) -> Vec<Drift> {
+        let effect_map = self.effect_map();
+        let mut drifts = Vec::new();
+
+        for (key, bitmap) in &effect_map.cpuid {
+            let entries: Vec<_> = if key.subleaf == SUBLEAF_WILDCARD {
+                current_cpuid
+                    .inner()
+                    .iter()
+                    .filter(|(cpuid_key, _)| cpuid_key.leaf == key.leaf)
+                    .collect()
+            } else {
+                let cpuid_key = CpuidKey::subleaf(key.leaf, key.subleaf);
+                current_cpuid.inner().get_key_value(&cpuid_key).into_iter().collect()
+            };
+
+            if entries.is_empty() {
+                let drift = Drift::MissingCpuid {
+                    leaf: key.leaf,
+                    subleaf: key.subleaf,
+                };
+                // Report a missing leaf once, not once per register.
+                if drifts.last() != Some(&drift) {
+                    drifts.push(drift);
+                }
+            }
+
+            for (cpuid_key, entry) in entries {
+                let value = cpuid_register_value(&entry.result, &key.register);
+                let drifted_bits = bitmap.apply(value) ^ value;
+                if drifted_bits != 0 {
+                    drifts.push(Drift::Cpuid {
+                        leaf: cpuid_key.leaf,
+                        subleaf: cpuid_key.subleaf,
+                        register: key.register.clone(),
+                        drifted_bits,
+                    });
+                }
+            }
+        }
+
+        for (addr, bitmap) in &effect_map.msrs {
+            match current_msrs.get(addr) {
+                Some(value) => {
+                    let field = effect_map
+                        .msr_sources
+                        .get(addr)
+                        .map_or(0, |source| source.bitmap.filter);
+                    let drifted_bits = (bitmap.apply(*value) ^ value) & !field;
+                    if drifted_bits != 0 {
+                        drifts.push(Drift::Msr {
+                            addr: *addr,
+                            drifted_bits,
+                        });
+                    }
+                }
+                None => drifts.push(Drift::MissingMsr(*addr)),
+            }
+        }
+
+        drifts
+    }
+
+    /// Read the CPUID and the MSRs modified by the template from a running vCPU and check that
+    /// the template is still in effect on them, see [`CustomCpuTemplate::verify_applied`].
+    pub fn audit_live<V: VcpuCpuidMsrAccess>(&self, vcpu: &V) -> Result<Vec<Drift>, V::Error> {
+        let current_cpuid = vcpu.read_cpuid()?;
+        let msr_addrs: Vec<u32> = self.effect_map().msrs.into_keys().collect();
+        let current_msrs = vcpu.read_msrs(&msr_addrs)?;
+        Ok(self.verify_applied(&current_cpuid, &current_msrs))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::cpu_config::templates::RegisterValueFilter;
+    use crate::cpu_config::x86_64::cpuid::{CpuidEntry, IntelCpuid, KvmCpuidFlags};
+    use crate::cpu_config::x86_64::custom_cpu_template::{MsrFieldSource, RegisterModifier};
+    use crate::cpu_config::x86_64::test_utils::build_test_template;
+    use crate::cpu_config::x86_64::CpuConfiguration;
+
+    fn build_host_config() -> CpuConfiguration {
+        CpuConfiguration {
+            cpuid: Cpuid::Intel(IntelCpuid(BTreeMap::from([(
+                CpuidKey::leaf(0x3),
+                CpuidEntry {
+                    flags: KvmCpuidFlags::EMPTY,
+                    result: CpuidRegisters {
+                        eax: 0xffff_fff0,
+                        ebx: 0,
+                        ecx: 0xf,
+                        edx: 0x8,
+                    },
+                },
+            )]))),
+            msrs: BTreeMap::from([(0x8000, 0b1000), (0x9999, 0b1010)]),
+        }
+    }
+
+    fn applied_state() -> (Cpuid, HashMap<u32, u64>) {
+        let config = build_host_config()
+            .apply_template(&build_test_template())
+            .unwrap();
+        (config.cpuid, config.msrs.into_iter().collect())
+    }
+
+    /// vCPU reading CPUID and MSRs from memory.
+    struct MockVcpu {
+        cpuid: Cpuid,
+        msrs: HashMap<u32, u64>,
+    }
+
+    impl VcpuCpuidMsrAccess for MockVcpu {
+        type Error = String;
+
+        fn read_cpuid(&self) -> Result<Cpuid, String> {
+            Ok(self.cpuid.clone())
+        }
+
+        fn read_msrs(&self, addrs: &[u32]) -> Result<HashMap<u32, u64>, String> {
+            addrs
+                .iter()
+                .map(|addr| match self.msrs.get(addr) {
+                    Some(value) => Ok((*addr, *value)),
+                    None => Err(format!("Failed to read MSR {addr:#x}")),
+                })
+                .collect()
+        }
+    }
+
+    #[test]
+    fn test_audit_live() {
+        let (cpuid, msrs) = applied_state();
+        let mut vcpu = MockVcpu { cpuid, msrs };
+        let template = CustomCpuTemplate {
+            msr_modifiers: vec![RegisterModifier {
+                addr: 0x8000,
+                bitmap: RegisterValueFilter {
+                    filter: 0b1100,
+                    value: 0b0100,
+                },
+                source: None,
+            }],
+            ..build_test_template()
+        };
+        vcpu.msrs.insert(0x8000, 0b0100);
+        assert_eq!(template.audit_live(&vcpu).unwrap(), vec![]);
+
+        // The guest set bit 3 of MSR 0x8000, which the template clears.
+        vcpu.msrs.insert(0x8000, 0b1100);
+        assert_eq!(
+            template.audit_live(&vcpu).unwrap(),
+            vec![Drift::Msr {
+                addr: 0x8000,
+                drifted_bits: 0b1000,
+            }]
+        );
+
+        vcpu.msrs.remove(&0x8000);
+        assert_eq!(
+            template.audit_live(&vcpu).unwrap_err(),
+            "Failed to read MSR 0x8000"
+        );
+    }
+
+    #[test]
+    fn test_verify_applied_without_drift() {
+        let (cpuid, msrs) = applied_state();
+        assert_eq!(build_test_template().verify_applied(&cpuid, &msrs), vec![]);
+    }
+
+    #[test]
+    fn test_verify_applied_msr_source() {
+        let template = CustomCpuTemplate {
+            msr_modifiers: vec![RegisterModifier {
+                addr: 0x8000,
+                bitmap: RegisterValueFilter {
+                    filter: 0b1110,
+                    value: 0,
+                },
+                source: Some(MsrFieldSource {
+                    msr: 0x9999,
+                    bits: [0, 2],
+                    default: None,
+                }),
+            }],
+            ..Default::default()
+        };
+        let (cpuid, mut msrs) = applied_state();
+
+        // The copied field depends on MSR 0x9999 when the template was applied.
+        msrs.insert(0x8000, 0b0101);
+        assert_eq!(template.verify_applied(&cpuid, &msrs), vec![]);
+        msrs.insert(0x8000, 0b1011);
+        assert_eq!(template.verify_applied(&cpuid, &msrs), vec![]);
+    }
+
+    #[test]
+    fn test_verify_applied_with_drift() {
+        let (mut cpuid, mut msrs) = applied_state();
+        // Flip a bit pinned by the template and a bit that is not.
+        cpuid
+            .inner_mut()
+            .get_mut(&CpuidKey::leaf(0x3))
+            .unwrap()
+            .result
+            .ebx ^= 0b1100;
+        msrs.remove(&0x9999);
+
+        assert_eq!(
+            build_test_template().verify_applied(&cpuid, &msrs),
+            vec![
+                Drift::Cpuid {
+                    leaf: 0x3,
+                    subleaf: 0x0,
+                    register: CpuidRegister::Ebx,
+                    drifted_bits: 0b0100,
+                },
+                Drift::MissingMsr(0x9999),
+            ]
+        );
+
+        let empty_cpuid = Cpuid::Intel(IntelCpuid(BTreeMap::new()));
+        assert_eq!(
+            build_test_template().verify_applied(&empty_cpuid, &msrs),
+            vec![
+                Drift::MissingCpuid {
+                    leaf: 0x3,
+                    subleaf: 0x0,
+                },
+                Drift::MissingMsr(0x9999),
+            ]
+        );
+    }
+}
@@ -0,0 +1,558 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use serde::de::Error as SerdeError;
+use serde::{Deserialize, Serialize};
+
+use crate::cpu_config::templates::{CustomCpuTemplate, KvmCapability, Numeric, RegisterValueFilter};
+use crate::cpu_config::x86_64::cpuid::KvmCpuidFlags;
+use crate::cpu_config::x86_64::custom_cpu_template::{
+    CpuidLeafModifier, CpuidRegister, CpuidRegisterModifier, MsrFieldSource, RegisterModifier,
+};
+
+const C_HEADER_PREAMBLE: &str = "\
+/* Generated from a Firecracker custom CPU template. Do not edit. */
+#ifndef FC_CPU_TEMPLATE_H
+#define FC_CPU_TEMPLATE_H
+
+#include <stdint.h>
+
+#define FC_CPUID_EAX 0
+#define FC_CPUID_EBX 1
+#define FC_CPUID_ECX 2
+#define FC_CPUID_EDX 3
+
+struct fc_cpuid_write {
+    uint32_t leaf;
+    uint32_t subleaf;
+    uint32_t flags;
+    uint32_t reg;
+    uint32_t mask;
+    uint32_t value;
+};
+
+struct fc_msr_write {
+    uint32_t msr;
+    uint64_t mask;
+    uint64_t value;
+};
+
+struct fc_msr_copy {
+    uint32_t msr;
+    uint64_t mask;
+    uint32_t src_msr;
+    uint32_t src_lo;
+    uint32_t has_default;
+    uint64_t src_default;
+};
+";
+
+const C_HEADER_EPILOGUE: &str = "
+#endif /* FC_CPU_TEMPLATE_H */
+";
+
+fn c_register_name(register: &CpuidRegister) -> &'static str {
+    match register {
+        CpuidRegister::Eax => "FC_CPUID_EAX",
+        CpuidRegister::Ebx => "FC_CPUID_EBX",
+        CpuidRegister::Ecx => "FC_CPUID_ECX",
+        CpuidRegister::Edx => "FC_CPUID_EDX",
+    }
+}
+
+/// Action applied to a single bit in the explicit JSON format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum BitAction {
+    Set,
+    Clear,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ExplicitBit {
+    bit: u32,
+    action: BitAction,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ExplicitCpuidRegisterModifier {
+    register: CpuidRegister,
+    bits: Vec<ExplicitBit>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    passthrough: bool,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ExplicitCpuidLeafModifier {
+    leaf: u32,
+    subleaf: u32,
+    flags: u32,
+    modifiers: Vec<ExplicitCpuidRegisterModifier>,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ExplicitRegisterModifier {
+    addr: u32,
+    bits: Vec<ExplicitBit>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    source: Option<ExplicitMsrFieldSource>,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ExplicitMsrFieldSource {
+    msr: u32,
+    bits: [u32; 2],
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    default: Option<u64>,
+}
+
+/// Custom CPU template in the explicit JSON format, where every bit modified by a modifier is
+/// listed individually. Numbers are plain JSON numbers.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ExplicitTemplate {
+    kvm_capabilities: Vec<KvmCapability>,
+    cpuid_modifiers: Vec<ExplicitCpuidLeafModifier>,
+    msr_modifiers: Vec<ExplicitRegisterModifier>,
+}
+
+fn to_explicit_bits<V: Numeric>(bitmap: &RegisterValueFilter<V>) -> Vec<ExplicitBit> {
+    (0..V::BITS)
+        .filter(|pos| bitmap.filter.bit(*pos))
+        .map(|pos| ExplicitBit {
+            bit: pos,
+            action: match bitmap.value.bit(pos) {
+                true => BitAction::Set,
+                false => BitAction::Clear,
+            },
+        })
+        .collect()
+}
+
+fn from_explicit_bits<V: Numeric>(
+    bits: &[ExplicitBit],
+) -> Result<RegisterValueFilter<V>, serde_json::Error> {
+    let (mut filter, mut value) = (V::zero(), V::zero());
+    for explicit_bit in bits {
+        if explicit_bit.bit >= V::BITS {
+            return Err(serde_json::Error::custom(format!(
+                "Bit {} is out of range for a {}-bit register",
+                explicit_bit.bit,
+                V::BITS
+            )));
+        }
+        let mask = V::one() << explicit_bit.bit;
+        if filter & mask != V::zero() {
+            return Err(serde_json::Error::custom(format!(
+                "Bit {} is listed more than once",
+                explicit_bit.bit
+            )));
+        }
+        filter |= mask;
+        if explicit_bit.action == BitAction::Set {
+            value |= mask;
+        }
+    }
+    Ok(RegisterValueFilter { filter, value })
+}
+
+impl CustomCpuTemplate {
+    /// Export the template as JSON listing every bit modified by each modifier individually,
+    /// together with whether the bit is set or cleared. Unlike the bitmap strings of the
+    /// template format, this representation does not need any special parsing.
+    ///
+    /// Modifiers are kept as they are and in their original order, so
+    /// [`CustomCpuTemplate::from_explicit_json`] reconstructs the same template.
+    pub fn to_explicit_json(&self) -> String {
+        let explicit = ExplicitTemplate {
+            kvm_capabilities: self.kvm_capabilities.clone(),
+            cpuid_modifiers: self
+                .cpuid_modifiers
+                .iter()
+                .map(|leaf_modifier| ExplicitCpuidLeafModifier {
+                    leaf: leaf_modifier.leaf,
+                    subleaf: leaf_modifier.subleaf,
+                    flags: leaf_modifier.flags.0,
+                    modifiers: leaf_modifier
+                        .modifiers
+                        .iter()
+                        .map(|reg_modifier| ExplicitCpuidRegisterModifier {
+                            register: reg_modifier.register.clone(),
+                            bits: to_explicit_bits(&reg_modifier.bitmap),
+                            passthrough: reg_modifier.passthrough,
+                        })
+                        .collect(),
+                })
+                .collect(),
+            msr_modifiers: self
+                .msr_modifiers
+                .iter()
+                .map(|modifier| ExplicitRegisterModifier {
+                    addr: modifier.addr,
+                    bits: to_explicit_bits(&modifier.bitmap),
+                    source: modifier.source.map(|source| ExplicitMsrFieldSource {
+                        msr: source.msr,
+                        bits: source.bits,
+                        default: source.default,
+                    }),
+                })
+                .collect(),
+        };
+        serde_json::to_string_pretty(&explicit).expect("Explicit templates are always serializable")
+    }
+
+    /// Export the template as JSON without modifiers that consist only of don't-care bits, see
+    /// [`CustomCpuTemplate::prune_noop`].
+    pub fn to_pruned_json(&self) -> String {
+        let mut template = self.clone();
+        template.prune_noop();
+        serde_json::to_string_pretty(&template)
+            .expect("Custom CPU templates are always serializable")
+    }
+
+    /// Import a template exported with [`CustomCpuTemplate::to_explicit_json`].
+    pub fn from_explicit_json(json: &str) -> Result<Self, serde_json::Error> {
+        let explicit: ExplicitTemplate = serde_json::from_str(json)?;
+
+        let mut cpuid_modifiers = Vec::with_capacity(explicit.cpuid_modifiers.len());
+        for leaf_modifier in explicit.cpuid_modifiers {
+            let modifiers = leaf_modifier
+                .modifiers
+                .into_iter()
+                .map(|reg_modifier| {
+                    if reg_modifier.passthrough && !reg_modifier.bits.is_empty() {
+                        return Err(serde_json::Error::custom(format!(
+                            "Passthrough modifier of CPUID register {:?} must not change any bit",
+                            reg_modifier.register
+                        )));
+                    }
+                    Ok(CpuidRegisterModifier {
+                        register: reg_modifier.register,
+                        bitmap: from_explicit_bits(&reg_modifier.bits)?,
+                        passthrough: reg_modifier.passthrough,
+                    })
+                })
+                .collect::<Result<_, serde_json::Error>>()?;
+            cpuid_modifiers.push(CpuidLeafModifier {
+                leaf: leaf_modifier.leaf,
+                subleaf: leaf_modifier.subleaf,
+                flags: KvmCpuidFlags(leaf_modifier.flags),
+                modifiers,
+            });
+        }
+
+        let msr_modifiers = explicit
+            .msr_modifiers
+            .into_iter()
+            .map(|modifier| {
+                Ok(RegisterModifier {
+                    addr: modifier.addr,
+                    bitmap: from_explicit_bits(&modifier.bits)?,
+                    source: modifier.source.map(|source| MsrFieldSource {
+                        msr: source.msr,
+                        bits: source.bits,
+                        default: source.default,
+                    }),
+                })
+            })
+            .collect::<Result<_, serde_json::Error>>()?;
+
+        Ok(CustomCpuTemplate {
+            kvm_capabilities: explicit.kvm_capabilities,
+            cpuid_modifiers,
+            msr_modifiers,
+        })
+    }
+
+    /// Export the effect of the template as a C header containing arrays of register writes.
+    ///
+    /// Every write is described by the mask of bits to be modified and the value of those bits.
+    /// Fields copied from other MSRs, see [`RegisterModifier::source`], are described by the mask
+    /// of bits to copy the field into, the source MSR, the lowest bit of the field and the value
+    /// to use if the source MSR can not be read.
+    /// Entries are sorted by register location, so the output is deterministic.
+    pub fn to_c_header(&self) -> String {
+        let effect_map = self.effect_map();
+        let mut header = String::from(C_HEADER_PREAMBLE);
+
+        header.push_str(&format!(
+            "\n#define FC_CPUID_WRITES_COUNT {}\n",
+            effect_map.cpuid.len()
+        ));
+        if !effect_map.cpuid.is_empty() {
+            header.push_str("static const struct fc_cpuid_write fc_cpuid_writes[] = {\n");
+            for (key, bitmap) in &effect_map.cpuid {
+                header.push_str(&format!(
+                    "    {{ {:#x}, {:#x}, {:#x}, {}, {:#010x}, {:#010x} }},\n",
+                    key.leaf,
+                    key.subleaf,
+                    key.flags.0,
+                    c_register_name(&key.register),
+                    bitmap.filter,
+                    bitmap.value,
+                ));
+            }
+            header.push_str("};\n");
+        }
+
+        header.push_str(&format!(
+            "\n#define FC_MSR_WRITES_COUNT {}\n",
+            effect_map.msrs.len()
+        ));
+        if !effect_map.msrs.is_empty() {
+            header.push_str("static const struct fc_msr_write fc_msr_writes[] = {\n");
+            for (addr, bitmap) in &effect_map.msrs {
+                // Bits of a copied field are written by the copy.
+                let field = effect_map
+                    .msr_sources
+                    .get(addr)
+                    .map_or(0, |modifier| modifier.bitmap.filter);
+                header.push_str(&format!(
+                    "    {{ {:#x}, {:#018x}ULL, {:#018x}ULL }},\n",
+                    addr,
+                    bitmap.filter & !field,
+                    bitmap.value & !field,
+                ));
+            }
+            header.push_str("};\n");
+        }
+
+        header.push_str(&format!(
+            "\n#define FC_MSR_COPIES_COUNT {}\n",
+            effect_map.msr_sources.len()
+        ));
+        if !effect_map.msr_sources.is_empty() {
+            header.push_str("static const struct fc_msr_copy fc_msr_copies[] = {\n");
+            for (addr, modifier) in &effect_map.msr_sources {
+                let Some(source) = modifier.source else {
+                    continue;
+                };
+                header.push_str(&format!(
+                    "    {{ {:#x}, {:#018x}ULL, {:#x}, {}, {}, {:#018x}ULL }},\n",
+                    addr,
+                    modifier.bitmap.filter,
+                    source.msr,
+                    source.bits[0],
+                    u32::from(source.default.is_some()),
+                    source.default.unwrap_or(0),
+                ));
+            }
+            header.push_str("};\n");
+        }
+
+        header.push_str(C_HEADER_EPILOGUE);
+        header
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu_config::x86_64::test_utils::{build_test_template, TEST_TEMPLATE_JSON};
+
+    #[test]
+    fn test_to_c_header() {
+        let template = CustomCpuTemplate {
+            cpuid_modifiers: vec![CpuidLeafModifier {
+                leaf: 0x7,
+                subleaf: 0x0,
+                flags: KvmCpuidFlags::SIGNIFICANT_INDEX,
+                modifiers: vec![
+                    CpuidRegisterModifier {
+                        register: CpuidRegister::Ecx,
+                        bitmap: RegisterValueFilter {
+                            filter: 0b1000,
+                            value: 0b0000,
+                        },
+                        passthrough: false,
+                    },
+                    CpuidRegisterModifier {
+                        register: CpuidRegister::Ebx,
+                        bitmap: RegisterValueFilter {
+                            filter: 0b0011,
+                            value: 0b0001,
+                        },
+                        passthrough: false,
+                    },
+                ],
+            }],
+            msr_modifiers: vec![
+                RegisterModifier {
+                    addr: 0x10a,
+                    bitmap: RegisterValueFilter {
+                        filter: 0xff,
+                        value: 0x0f,
+                    },
+                    source: None,
+                },
+                RegisterModifier {
+                    addr: 0x10a,
+                    bitmap: RegisterValueFilter {
+                        filter: 0xf00,
+                        value: 0x0,
+                    },
+                    source: Some(MsrFieldSource {
+                        msr: 0xce,
+                        bits: [8, 11],
+                        default: Some(0x300),
+                    }),
+                },
+            ],
+            ..Default::default()
+        };
+
+        let expected = format!(
+            "{C_HEADER_PREAMBLE}
+#define FC_CPUID_WRITES_COUNT 2
+static const struct fc_cpuid_write fc_cpuid_writes[] = {{
+    {{ 0x7, 0x0, 0x1, FC_CPUID_EBX, 0x00000003, 0x00000001 }},
+    {{ 0x7, 0x0, 0x1, FC_CPUID_ECX, 0x00000008, 0x00000000 }},
+}};
+
+#define FC_MSR_WRITES_COUNT 1
+static const struct fc_msr_write fc_msr_writes[] = {{
+    {{ 0x10a, 0x00000000000000ffULL, 0x000000000000000fULL }},
+}};
+
+#define FC_MSR_COPIES_COUNT 1
+static const struct fc_msr_copy fc_msr_copies[] = {{
+    {{ 0x10a, 0x0000000000000f00ULL, 0xce, 8, 1, 0x0000000000000300ULL }},
+}};
+{C_HEADER_EPILOGUE}"
+        );
+        assert_eq!(template.to_c_header(), expected);
+    }
+
+    #[test]
+    fn test_to_c_header_empty_template() {
+        let expected = format!(
+            "{C_HEADER_PREAMBLE}
+#define FC_CPUID_WRITES_COUNT 0
+
+#define FC_MSR_WRITES_COUNT 0
+
+#define FC_MSR_COPIES_COUNT 0
+{C_HEADER_EPILOGUE}"
+        );
+        assert_eq!(CustomCpuTemplate::default().to_c_header(), expected);
+    }
+
+    #[test]
+    fn test_explicit_json_round_trip() {
+        let mut template = CustomCpuTemplate::try_from(TEST_TEMPLATE_JSON).unwrap();
+        template.kvm_capabilities.push(KvmCapability::Remove(5));
+        let json = template.to_explicit_json();
+        assert_eq!(
+            CustomCpuTemplate::from_explicit_json(&json).unwrap(),
+            template
+        );
+
+        let template = build_test_template();
+        let json = template.to_explicit_json();
+        assert_eq!(
+            CustomCpuTemplate::from_explicit_json(&json).unwrap(),
+            template
+        );
+
+        let explicit: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            explicit["cpuid_modifiers"][0]["modifiers"][0]["bits"],
+            serde_json::json!([
+                { "bit": 0, "action": "set" },
+                { "bit": 1, "action": "clear" },
+                { "bit": 2, "action": "set" },
+            ])
+        );
+        assert_eq!(explicit["msr_modifiers"][0]["bits"], serde_json::json!([]));
+    }
+
+    #[test]
+    fn test_explicit_json_msr_source() {
+        let mut template = build_test_template();
+        template.msr_modifiers[1].bitmap = RegisterValueFilter {
+            filter: 0b0110,
+            value: 0,
+        };
+        template.msr_modifiers[1].source = Some(MsrFieldSource {
+            msr: 0x10a,
+            bits: [4, 5],
+            default: Some(0x30),
+        });
+        let json = template.to_explicit_json();
+        assert_eq!(
+            CustomCpuTemplate::from_explicit_json(&json).unwrap(),
+            template
+        );
+
+        let explicit: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            explicit["msr_modifiers"][1]["source"],
+            serde_json::json!({ "msr": 0x10a, "bits": [4, 5], "default": 0x30 })
+        );
+        assert_eq!(explicit["msr_modifiers"][0].get("source"), None);
+    }
+
+    #[test]
+    fn test_explicit_json_passthrough() {
+        let mut template = build_test_template();
+        template.cpuid_modifiers[0].modifiers[1] = CpuidRegisterModifier {
+            register: CpuidRegister::Ebx,
+            bitmap: RegisterValueFilter::default(),
+            passthrough: true,
+        };
+        let json = template.to_explicit_json();
+        assert_eq!(
+            CustomCpuTemplate::from_explicit_json(&json).unwrap(),
+            template
+        );
+
+        let explicit: serde_json::Value = serde_json::from_str(&json).unwrap();
+        let modifiers = &explicit["cpuid_modifiers"][0]["modifiers"];
+        assert_eq!(modifiers[1]["passthrough"], serde_json::json!(true));
+        assert_eq!(modifiers[0].get("passthrough"), None);
+
+        // The first modifier without bits is the passthrough one.
+        let json = json.replacen(
+            r#""bits": []"#,
+            r#""bits": [{ "bit": 0, "action": "set" }]"#,
+            1,
+        );
+        let error = CustomCpuTemplate::from_explicit_json(&json).unwrap_err();
+        assert!(
+            error.to_string().contains("must not change any bit"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn test_from_explicit_json_invalid_bits() {
+        let json = r#"{
+            "kvm_capabilities": [],
+            "cpuid_modifiers": [],
+            "msr_modifiers": [{ "addr": 16, "bits": [{ "bit": 64, "action": "set" }] }]
+        }"#;
+        CustomCpuTemplate::from_explicit_json(json).unwrap_err();
+
+        let json = json.replace("64", "3").replace(
+            r#"{ "bit": 3, "action": "set" }"#,
+            r#"{ "bit": 3, "action": "set" }, { "bit": 3, "action": "clear" }"#,
+        );
+        CustomCpuTemplate::from_explicit_json(&json).unwrap_err();
+    }
+
+    #[test]
+    fn test_to_pruned_json() {
+        let json = build_test_template().to_pruned_json();
+        let template = CustomCpuTemplate::try_from(json.as_str()).unwrap();
+        assert_eq!(
+            template.cpuid_modifiers,
+            build_test_template().cpuid_modifiers
+        );
+        assert_eq!(template.msr_modifiers, vec![]);
+    }
+}
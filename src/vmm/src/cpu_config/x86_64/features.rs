@@ -0,0 +1,81 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::cpu_config::x86_64::loader::TemplateFormat;
+use crate::cpu_config::x86_64::static_cpu_templates::StaticCpuTemplate;
+
+/// Features of custom CPU templates supported by this build, e.g. to list them in the help of
+/// a CLI.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TemplateFeatures {
+    /// Forms of bitmaps accepted in templates.
+    pub bitmap_forms: Vec<&'static str>,
+    /// Shorthands accepted in templates, named by the key or value they are written with.
+    pub shorthands: Vec<&'static str>,
+    /// Formats templates can be loaded from.
+    pub formats: Vec<TemplateFormat>,
+    /// Static CPU templates, regardless of whether they can be used on the host CPU.
+    pub static_templates: Vec<StaticCpuTemplate>,
+    /// Whether metrics are recorded when applying templates, see the `cpu-template-metrics`
+    /// feature.
+    pub metrics: bool,
+    /// Whether templates can be converted to a compact in-memory form, see the
+    /// `compact-cpu-templates` feature.
+    pub compact: bool,
+    /// Whether tests applying templates to vCPUs of a real KVM VM are built, see the
+    /// `kvm-integration` feature.
+    pub kvm_integration: bool,
+}
+
+/// Get the features of custom CPU templates supported by this build.
+pub fn supported_template_features() -> TemplateFeatures {
+    TemplateFeatures {
+        bitmap_forms: vec![
+            "0b-prefixed",
+            "unprefixed",
+            "x for bits left as is",
+            "_ or single space between bits",
+            "{\"filter\", \"value\"} object",
+        ],
+        shorthands: vec!["base", "op", "subleaves", "passthrough", "*"],
+        formats: vec![TemplateFormat::Json, TemplateFormat::Jsonc],
+        static_templates: vec![
+            StaticCpuTemplate::C3,
+            StaticCpuTemplate::T2,
+            StaticCpuTemplate::T2S,
+            StaticCpuTemplate::T2CL,
+            StaticCpuTemplate::T2A,
+        ],
+        metrics: cfg!(feature = "cpu-template-metrics"),
+        compact: cfg!(feature = "compact-cpu-templates"),
+        kvm_integration: cfg!(feature = "kvm-integration"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu_config::x86_64::static_cpu_templates::dump_all_static_templates;
+
+    #[test]
+    fn test_supported_template_features() {
+        let features = supported_template_features();
+        assert!(features.formats.contains(&TemplateFormat::Json));
+        assert!(features
+            .bitmap_forms
+            .contains(&"{\"filter\", \"value\"} object"));
+        assert!(features.shorthands.contains(&"passthrough"));
+        // Sub-leaf wildcard, see `SUBLEAF_WILDCARD`.
+        assert!(features.shorthands.contains(&"*"));
+        assert_eq!(features.metrics, cfg!(feature = "cpu-template-metrics"));
+        assert_eq!(features.compact, cfg!(feature = "compact-cpu-templates"));
+        assert_eq!(features.kvm_integration, cfg!(feature = "kvm-integration"));
+
+        let dumped: Vec<_> = dump_all_static_templates()
+            .into_iter()
+            .map(|(template, _)| template)
+            .collect();
+        assert_eq!(features.static_templates, dumped);
+        assert!(!features.static_templates.contains(&StaticCpuTemplate::None));
+    }
+}
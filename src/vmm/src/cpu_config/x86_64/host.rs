@@ -0,0 +1,349 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::collections::{BTreeMap, HashMap};
+
+use serde::de::Error as SerdeError;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::arch::x86_64::cpu_model::CpuModel;
+use crate::cpu_config::templates::{CustomCpuTemplate, Numeric};
+use crate::cpu_config::x86_64::cpuid::common::{get_vendor_id_from_host, GetCpuidError};
+use crate::cpu_config::x86_64::cpuid::{
+    AmdCpuid, Cpuid, CpuidEntry, CpuidKey, IntelCpuid, VENDOR_ID_AMD, VENDOR_ID_INTEL,
+};
+use crate::cpu_config::x86_64::custom_cpu_template::CpuidRegister;
+use crate::vstate::vcpu::{KvmVcpu, KvmVcpuError};
+use crate::vstate::vm::{Vm, VmError};
+
+/// Errors associated with capturing a [`HostSnapshot`].
+#[derive(Debug, thiserror::Error, displaydoc::Display)]
+pub enum HostSnapshotError {
+    /// Failed to get CPU vendor ID of the host: {0}
+    Vendor(GetCpuidError),
+    /// Failed to create VM: {0}
+    Vm(VmError),
+    /// Failed to dump CPU configuration of vCPU: {0}
+    Vcpu(KvmVcpuError),
+}
+
+/// CPU vendor, model, CPUID and MSR values of a host, as seen by a vCPU with the CPUID supported
+/// by KVM.
+///
+/// Snapshots can be (de)serialized to compare hosts or to work on the CPU configuration of a
+/// host on another machine.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HostSnapshot {
+    /// CPU vendor ID.
+    pub vendor: [u8; 12],
+    /// CPU model.
+    pub model: CpuModel,
+    /// CPUID supported by KVM.
+    pub cpuid: Cpuid,
+    /// MSR values of a vCPU, keyed by MSR address.
+    pub msrs: HashMap<u32, u64>,
+}
+
+impl HostSnapshot {
+    /// Capture a snapshot of the current host. This creates a VM with a single vCPU to read the
+    /// MSR values from.
+    pub fn capture() -> Result<Self, HostSnapshotError> {
+        let vendor = get_vendor_id_from_host().map_err(HostSnapshotError::Vendor)?;
+        let vm = Vm::new(vec![]).map_err(HostSnapshotError::Vm)?;
+        let vcpu = KvmVcpu::new(0, &vm).map_err(HostSnapshotError::Vcpu)?;
+        vcpu.fd
+            .set_cpuid2(vm.supported_cpuid())
+            .map_err(|err| HostSnapshotError::Vcpu(KvmVcpuError::VcpuSetCpuid(err)))?;
+        let cpu_config = vcpu.dump_cpu_config().map_err(HostSnapshotError::Vcpu)?;
+
+        Ok(HostSnapshot {
+            vendor,
+            model: CpuModel::get_cpu_model(),
+            cpuid: cpu_config.cpuid,
+            msrs: cpu_config.msrs.into_iter().collect(),
+        })
+    }
+}
+
+/// Serialized form of a [`HostSnapshot`].
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SerializedHostSnapshot {
+    vendor: String,
+    model: u32,
+    cpuid: Vec<SerializedCpuidEntry>,
+    msrs: BTreeMap<u32, u64>,
+}
+
+/// Serialized form of a CPUID entry of a [`HostSnapshot`].
+#[derive(Debug, Serialize, Deserialize)]
+struct SerializedCpuidEntry {
+    leaf: u32,
+    subleaf: u32,
+    #[serde(flatten)]
+    entry: CpuidEntry,
+}
+
+impl Serialize for HostSnapshot {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        SerializedHostSnapshot {
+            vendor: String::from_utf8_lossy(&self.vendor).into_owned(),
+            model: u32::from(&self.model),
+            cpuid: self
+                .cpuid
+                .inner()
+                .iter()
+                .map(|(key, entry)| SerializedCpuidEntry {
+                    leaf: key.leaf,
+                    subleaf: key.subleaf,
+                    entry: entry.clone(),
+                })
+                .collect(),
+            msrs: self.msrs.iter().map(|(addr, value)| (*addr, *value)).collect(),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for HostSnapshot {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let snapshot = SerializedHostSnapshot::deserialize(deserializer)?;
+
+        let entries = snapshot
+            .cpuid
+            .into_iter()
+            .map(|entry| (CpuidKey::subleaf(entry.leaf, entry.subleaf), entry.entry))
+            .collect();
+        let (vendor, cpuid) = match snapshot.vendor.as_bytes() {
+            vendor if vendor == VENDOR_ID_INTEL => {
+                (*VENDOR_ID_INTEL, Cpuid::Intel(IntelCpuid(entries)))
+            }
+            vendor if vendor == VENDOR_ID_AMD => (*VENDOR_ID_AMD, Cpuid::Amd(AmdCpuid(entries))),
+            _ => {
+                return Err(D::Error::custom(format!(
+                    "Unsupported CPU vendor: {}",
+                    snapshot.vendor
+                )))
+            }
+        };
+
+        Ok(HostSnapshot {
+            vendor,
+            model: CpuModel::from(&snapshot.model),
+            cpuid,
+            msrs: snapshot.msrs.into_iter().collect(),
+        })
+    }
+}
+
+/// Bit whose value changes when a custom CPU template is applied to a host, see
+/// [`CustomCpuTemplate::changed_bits_vs_host`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChangedBit {
+    /// Bit of a CPUID register.
+    Cpuid {
+        /// Leaf value.
+        leaf: u32,
+        /// Sub-Leaf value.
+        subleaf: u32,
+        /// CPUID register.
+        register: CpuidRegister,
+        /// Index of the bit.
+        bit: u32,
+        /// Value of the bit once the template is applied.
+        value: bool,
+    },
+    /// Bit of an MSR.
+    Msr {
+        /// MSR address.
+        addr: u32,
+        /// Index of the bit.
+        bit: u32,
+        /// Value of the bit once the template is applied.
+        value: bool,
+    },
+}
+
+/// Get the index and the value in `after` of every bit that differs between `before` and
+/// `after`.
+fn changed_bits(before: u64, after: u64) -> impl Iterator<Item = (u32, bool)> {
+    let changed = before ^ after;
+    (0..u64::BITS)
+        .filter(move |bit| changed.bit(*bit))
+        .map(move |bit| (bit, after.bit(bit)))
+}
+
+impl CustomCpuTemplate {
+    /// Get the bits whose value changes when the template is applied to `host`, leaving out the
+    /// bits the template sets to the value they already have. CPUID bits come first, ordered by
+    /// entry, followed by MSR bits, ordered by address.
+    ///
+    /// Like with [`CustomCpuTemplate::lint_against_host`], modifiers of CPUID entries or MSRs
+    /// the host does not have are skipped, as are modifiers copying a field from an MSR the host
+    /// does not have and without a default value.
+    pub fn changed_bits_vs_host(&self, host: &HostSnapshot) -> Vec<ChangedBit> {
+        let mut cpuid = host.cpuid.clone();
+        let mut bits: Vec<_> = self
+            .apply_cpuid_audited(&mut cpuid)
+            .into_iter()
+            .flat_map(|change| {
+                changed_bits(u64::from(change.before), u64::from(change.after)).map(
+                    move |(bit, value)| ChangedBit::Cpuid {
+                        leaf: change.leaf,
+                        subleaf: change.subleaf,
+                        register: change.register.clone(),
+                        bit,
+                        value,
+                    },
+                )
+            })
+            .collect();
+
+        let mut msrs: BTreeMap<u32, u64> = self
+            .msr_index_iter()
+            .filter_map(|addr| Some((addr, *host.msrs.get(&addr)?)))
+            .collect();
+        for modifier in &self.msr_modifiers {
+            let bitmap = modifier.resolve_bitmap(|addr| host.msrs.get(&addr).copied());
+            if let (Ok(bitmap), Some(value)) = (bitmap, msrs.get_mut(&modifier.addr)) {
+                *value = bitmap.apply(*value);
+            }
+        }
+        for (addr, value) in msrs {
+            bits.extend(
+                changed_bits(host.msrs[&addr], value)
+                    .map(|(bit, value)| ChangedBit::Msr { addr, bit, value }),
+            );
+        }
+        bits
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu_config::templates::RegisterValueFilter;
+    use crate::cpu_config::x86_64::cpuid::{CpuidRegisters, KvmCpuidFlags};
+    use crate::cpu_config::x86_64::custom_cpu_template::{
+        CpuidLeafModifier, CpuidRegisterModifier, RegisterModifier,
+    };
+
+    fn build_snapshot() -> HostSnapshot {
+        HostSnapshot {
+            vendor: *VENDOR_ID_INTEL,
+            model: CpuModel::from(&0x0005_0657),
+            cpuid: Cpuid::Intel(IntelCpuid(BTreeMap::from([
+                (
+                    CpuidKey::leaf(0x1),
+                    CpuidEntry {
+                        flags: KvmCpuidFlags::EMPTY,
+                        result: CpuidRegisters {
+                            eax: 0x0005_0657,
+                            ebx: 0x1,
+                            ecx: 0x2,
+                            edx: 0x3,
+                        },
+                    },
+                ),
+                (
+                    CpuidKey::subleaf(0x7, 0x1),
+                    CpuidEntry {
+                        flags: KvmCpuidFlags::SIGNIFICANT_INDEX,
+                        result: CpuidRegisters::default(),
+                    },
+                ),
+            ]))),
+            msrs: HashMap::from([(0x10, 0x1234), (0x10a, 0xff)]),
+        }
+    }
+
+    #[test]
+    fn test_host_snapshot_serde() {
+        let snapshot = build_snapshot();
+        let json = serde_json::to_string(&snapshot).unwrap();
+        assert_eq!(
+            serde_json::from_str::<HostSnapshot>(&json).unwrap(),
+            snapshot
+        );
+
+        let json = json.replace("GenuineIntel", "GenuineIotel");
+        let error = serde_json::from_str::<HostSnapshot>(&json).unwrap_err();
+        assert!(
+            error.to_string().contains("Unsupported CPU vendor: GenuineIotel"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn test_host_snapshot_capture() {
+        let snapshot = HostSnapshot::capture().unwrap();
+        assert_ne!(snapshot.vendor, [0; 12]);
+        assert_eq!(snapshot.vendor, get_vendor_id_from_host().unwrap());
+        assert!(!snapshot.cpuid.inner().is_empty());
+    }
+
+    #[test]
+    fn test_changed_bits_vs_host() {
+        let msr_modifier = |addr, filter, value| RegisterModifier {
+            addr,
+            bitmap: RegisterValueFilter { filter, value },
+            source: None,
+        };
+        let template = CustomCpuTemplate {
+            cpuid_modifiers: vec![CpuidLeafModifier {
+                leaf: 0x1,
+                subleaf: 0x0,
+                flags: KvmCpuidFlags::EMPTY,
+                modifiers: vec![CpuidRegisterModifier {
+                    register: CpuidRegister::Ebx,
+                    bitmap: RegisterValueFilter {
+                        filter: 0b11,
+                        value: 0b11,
+                    },
+                    passthrough: false,
+                }],
+            }],
+            msr_modifiers: vec![
+                msr_modifier(0x10, 0b100, 0b100),
+                msr_modifier(0x10a, 0x101, 0x100),
+                // The host does not have MSR 0x48.
+                msr_modifier(0x48, 0b1, 0b1),
+            ],
+            ..Default::default()
+        };
+
+        // Bit 0 of EBX and bit 2 of MSR 0x10 are already set on the host.
+        assert_eq!(
+            template.changed_bits_vs_host(&build_snapshot()),
+            vec![
+                ChangedBit::Cpuid {
+                    leaf: 0x1,
+                    subleaf: 0x0,
+                    register: CpuidRegister::Ebx,
+                    bit: 1,
+                    value: true,
+                },
+                ChangedBit::Msr {
+                    addr: 0x10a,
+                    bit: 0,
+                    value: false,
+                },
+                ChangedBit::Msr {
+                    addr: 0x10a,
+                    bit: 8,
+                    value: true,
+                },
+            ]
+        );
+        assert_eq!(
+            CustomCpuTemplate::default().changed_bits_vs_host(&build_snapshot()),
+            vec![]
+        );
+    }
+}
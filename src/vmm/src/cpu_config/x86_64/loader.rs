@@ -0,0 +1,888 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::io::Read;
+use std::path::Path;
+
+use serde_json::Value;
+
+use crate::cpu_config::templates::{
+    CpuTemplateType, CustomCpuTemplate, GetCpuTemplate, GetCpuTemplateError, StaticCpuTemplate,
+};
+use crate::cpu_config::x86_64::custom_cpu_template::{
+    MergeConflictError, MergeStrategy, RegisterModifier,
+};
+
+/// Key of a custom CPU template naming the static CPU template it is based on.
+const BASE_KEY: &str = "base";
+
+/// Key of the bitmap of CPUID register and MSR modifiers.
+const BITMAP_KEY: &str = "bitmap";
+
+/// Errors associated with loading custom CPU templates.
+#[derive(Debug, thiserror::Error, displaydoc::Display)]
+pub enum LoadError {
+    /// Failed to parse CPU template: {0}
+    Parse(#[from] serde_json::Error),
+    /// Failed to resolve base static CPU template {0}: {1}
+    Base(StaticCpuTemplate, GetCpuTemplateError),
+    /// Failed to read CPU template file {0}: {1}
+    Read(String, std::io::Error),
+    /// {0}:{1}:{2}: Failed to parse CPU template: {3}
+    ParseFile(String, usize, usize, serde_json::Error),
+    /// Invalid CPU template: {0}
+    Validate(serde_json::Error),
+    /// {0}: Invalid CPU template: {1}
+    ValidateFile(String, serde_json::Error),
+    /// Failed to read CPU template: {0}
+    ReadInput(std::io::Error),
+    /// CPU template is empty.
+    Empty,
+    /// Unsupported CPU template format, only JSON with or without comments is supported.
+    UnsupportedFormat,
+    /// MSR {0:#x} is modified more than once.
+    DuplicateMsr(u32),
+    /// CPU template file {0} conflicts with the files before it: {1}
+    MergeConflict(String, MergeConflictError),
+    /// Bitmap {0} is a string, write it as an object with a filter and a value instead.
+    BitmapString(String),
+    /// Bitmap {0} is missing the 0b prefix.
+    BitmapPrefix(String),
+}
+
+/// How to handle multiple modifiers of the same MSR in a custom CPU template.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum LayeringMode {
+    /// Reject the template.
+    #[default]
+    Strict,
+    /// Only keep the bitmap and source of the last modifier.
+    LastWins,
+    /// Combine the bitmaps of all modifiers in order, see
+    /// [`crate::cpu_config::templates::RegisterValueFilter::compose`]. MSRs with a modifier
+    /// that has a [`RegisterModifier::source`] are rejected like with [`LayeringMode::Strict`].
+    Compose,
+}
+
+/// Forms of bitmaps accepted in a custom CPU template.
+///
+/// Bitmap strings are always serialized with a `0b` prefix, but by default it is optional when
+/// parsing them, as some tools producing templates omit it.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum BitmapSyntax {
+    /// Accept both bitmap strings, with or without the `0b` prefix, and
+    /// `{"filter": .., "value": ..}` objects.
+    #[default]
+    Any,
+    /// Accept bitmap objects and bitmap strings with the `0b` prefix.
+    RequirePrefix,
+    /// Only accept bitmap objects, whose bits are easier to audit than the `x` don't-care bits
+    /// of bitmap strings.
+    DisallowBitmapStrings,
+}
+
+/// Format of a custom CPU template.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TemplateFormat {
+    /// Plain JSON.
+    Json,
+    /// JSON with `//` and `/* */` comments.
+    Jsonc,
+}
+
+impl TemplateFormat {
+    /// Detect the format of a custom CPU template from its content.
+    pub fn detect(content: &str) -> Result<Self, LoadError> {
+        let trimmed = content.trim_start();
+        if trimmed.is_empty() {
+            return Err(LoadError::Empty);
+        }
+        if !["{", "//", "/*"].iter().any(|prefix| trimmed.starts_with(prefix)) {
+            return Err(LoadError::UnsupportedFormat);
+        }
+        if strip_json_comments(content) == content {
+            Ok(TemplateFormat::Json)
+        } else {
+            Ok(TemplateFormat::Jsonc)
+        }
+    }
+}
+
+/// Check that all bitmaps in the JSON `value` of a custom CPU template use `syntax`.
+fn check_bitmap_syntax(value: &Value, syntax: BitmapSyntax) -> Result<(), LoadError> {
+    match value {
+        Value::Object(map) => map.iter().try_for_each(|(key, value)| match value {
+            Value::String(bitmap) if key == BITMAP_KEY => match syntax {
+                BitmapSyntax::Any => Ok(()),
+                BitmapSyntax::RequirePrefix if bitmap.starts_with("0b") => Ok(()),
+                BitmapSyntax::RequirePrefix => Err(LoadError::BitmapPrefix(bitmap.clone())),
+                BitmapSyntax::DisallowBitmapStrings => Err(LoadError::BitmapString(bitmap.clone())),
+            },
+            value => check_bitmap_syntax(value, syntax),
+        }),
+        Value::Array(values) => values
+            .iter()
+            .try_for_each(|value| check_bitmap_syntax(value, syntax)),
+        _ => Ok(()),
+    }
+}
+
+/// Remove `//` and `/* */` comments outside of strings from JSON. Newlines within comments are
+/// kept, so line numbers in parse errors stay correct.
+fn strip_json_comments(jsonc: &str) -> String {
+    let mut json = String::with_capacity(jsonc.len());
+    let mut chars = jsonc.chars().peekable();
+    let mut in_string = false;
+    while let Some(c) = chars.next() {
+        if in_string {
+            json.push(c);
+            match c {
+                '\\' => json.extend(chars.next()),
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match (c, chars.peek()) {
+            ('"', _) => {
+                in_string = true;
+                json.push(c);
+            }
+            ('/', Some('/')) => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        json.push(c);
+                        break;
+                    }
+                }
+            }
+            ('/', Some('*')) => {
+                chars.next();
+                let mut prev = None;
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        json.push(c);
+                    }
+                    if prev == Some('*') && c == '/' {
+                        break;
+                    }
+                    prev = Some(c);
+                }
+            }
+            _ => json.push(c),
+        }
+    }
+    json
+}
+
+/// Remove commas outside of strings that are only followed by whitespace before the end of an
+/// object or array.
+fn strip_trailing_commas(json: &str) -> String {
+    let mut stripped = String::with_capacity(json.len());
+    let mut chars = json.chars();
+    let mut in_string = false;
+    while let Some(c) = chars.next() {
+        if in_string {
+            stripped.push(c);
+            match c {
+                '\\' => stripped.extend(chars.next()),
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '"' => in_string = true,
+            '}' | ']' => {
+                let end = stripped.trim_end().len();
+                if stripped[..end].ends_with(',') {
+                    stripped.remove(end - 1);
+                }
+            }
+            _ => {}
+        }
+        stripped.push(c);
+    }
+    stripped
+}
+
+/// Remove the `base` key from the JSON `value` of a custom CPU template and parse it.
+fn take_base(value: &mut Value) -> Result<Option<StaticCpuTemplate>, LoadError> {
+    match value.as_object_mut().and_then(|map| map.remove(BASE_KEY)) {
+        Some(base) => Ok(Some(serde_json::from_value(base)?)),
+        None => Ok(None),
+    }
+}
+
+impl CustomCpuTemplate {
+    /// Load a custom CPU template from JSON that may name a static CPU template in a `base` key.
+    ///
+    /// The base is resolved for the current host and its modifiers are followed by the modifiers
+    /// of the template, so the latter take precedence. Without a `base` key, this behaves like
+    /// parsing the template with `CustomCpuTemplate::try_from`.
+    pub fn from_json_with_base(json: &str) -> Result<Self, LoadError> {
+        Self::from_value_with_base(serde_json::from_str(json)?)
+    }
+
+    /// Load a custom CPU template like [`CustomCpuTemplate::from_json_with_base`], rejecting
+    /// bitmaps that are not written in the given `syntax`.
+    pub fn from_json_with_bitmap_syntax(
+        json: &str,
+        syntax: BitmapSyntax,
+    ) -> Result<Self, LoadError> {
+        let value: Value = serde_json::from_str(json)?;
+        check_bitmap_syntax(&value, syntax)?;
+        Self::from_value_with_base(value)
+    }
+
+    /// Load a custom CPU template from its JSON `value`, see
+    /// [`CustomCpuTemplate::from_json_with_base`].
+    fn from_value_with_base(mut value: Value) -> Result<Self, LoadError> {
+        let base = take_base(&mut value)?;
+        Self::with_base(base, serde_json::from_value(value)?)
+    }
+
+    /// Get `overlay` preceded by the modifiers of `base`, if any, and validate it.
+    fn with_base(
+        base: Option<StaticCpuTemplate>,
+        overlay: CustomCpuTemplate,
+    ) -> Result<Self, LoadError> {
+        let template = match base {
+            Some(base) => {
+                let mut template = Some(CpuTemplateType::Static(base))
+                    .get_cpu_template()
+                    .map_err(|err| LoadError::Base(base, err))?
+                    .into_owned();
+                template.kvm_capabilities.extend(overlay.kvm_capabilities);
+                template.cpuid_modifiers.extend(overlay.cpuid_modifiers);
+                template.msr_modifiers.extend(overlay.msr_modifiers);
+                template
+            }
+            None => overlay,
+        };
+        template.validate().map_err(LoadError::Validate)?;
+        Ok(template)
+    }
+
+    /// Load a custom CPU template like [`CustomCpuTemplate::from_json_with_base`], handling
+    /// multiple modifiers of the same MSR according to `mode`, see
+    /// [`CustomCpuTemplate::layer_msr_modifiers`]. With [`LayeringMode::Strict`], only the
+    /// modifiers of the template itself are checked, as they may override those of its base.
+    pub fn from_json_with_layering(json: &str, mode: LayeringMode) -> Result<Self, LoadError> {
+        let mut value: Value = serde_json::from_str(json)?;
+        let base = take_base(&mut value)?;
+        let mut overlay: CustomCpuTemplate = serde_json::from_value(value)?;
+        if mode == LayeringMode::Strict {
+            overlay.layer_msr_modifiers(mode)?;
+            return Self::with_base(base, overlay);
+        }
+
+        let mut template = Self::with_base(base, overlay)?;
+        template.layer_msr_modifiers(mode)?;
+        Ok(template)
+    }
+
+    /// Merge multiple modifiers of the same MSR into a single modifier, at the position of the
+    /// first one, according to `mode`. With [`LayeringMode::Strict`], the template is left
+    /// unchanged and an error is returned if any MSR is modified more than once.
+    pub fn layer_msr_modifiers(&mut self, mode: LayeringMode) -> Result<(), LoadError> {
+        let mut layered: Vec<RegisterModifier> = Vec::with_capacity(self.msr_modifiers.len());
+        for modifier in &self.msr_modifiers {
+            match layered.iter_mut().find(|layer| layer.addr == modifier.addr) {
+                Some(layer) => match mode {
+                    LayeringMode::Strict => return Err(LoadError::DuplicateMsr(modifier.addr)),
+                    LayeringMode::LastWins => {
+                        layer.bitmap = modifier.bitmap;
+                        layer.source = modifier.source;
+                    }
+                    LayeringMode::Compose
+                        if layer.source.is_none() && modifier.source.is_none() =>
+                    {
+                        layer.bitmap = layer.bitmap.compose(&modifier.bitmap)
+                    }
+                    LayeringMode::Compose => return Err(LoadError::DuplicateMsr(modifier.addr)),
+                },
+                None => layered.push(*modifier),
+            }
+        }
+        self.msr_modifiers = layered;
+        Ok(())
+    }
+
+    /// Load a custom CPU template from a JSON file, see
+    /// [`CustomCpuTemplate::from_json_with_base`].
+    ///
+    /// Parse errors are prefixed with the path of the file and the line and column of the error,
+    /// validation errors with the path of the file.
+    pub fn from_path(path: &Path) -> Result<Self, LoadError> {
+        let path_str = path.display().to_string();
+        let json = std::fs::read_to_string(path)
+            .map_err(|err| LoadError::Read(path_str.clone(), err))?;
+        Self::from_json_with_base(&json).map_err(|err| match err {
+            LoadError::Parse(err) => LoadError::ParseFile(path_str, err.line(), err.column(), err),
+            LoadError::Validate(err) => LoadError::ValidateFile(path_str, err),
+            err => err,
+        })
+    }
+
+    /// Load a custom CPU template split into the `*.json` files of a directory. The files are
+    /// loaded with [`CustomCpuTemplate::from_path`] and merged in the lexical order of their
+    /// names, failing on the first file that sets bits to different values than the files before
+    /// it, see [`CustomCpuTemplate::merge`].
+    pub fn from_dir(path: &Path) -> Result<Self, LoadError> {
+        let path_str = path.display().to_string();
+        let entries =
+            std::fs::read_dir(path).map_err(|err| LoadError::Read(path_str.clone(), err))?;
+        let mut file_paths = Vec::new();
+        for entry in entries {
+            let file_path = entry
+                .map_err(|err| LoadError::Read(path_str.clone(), err))?
+                .path();
+            if file_path.extension().is_some_and(|extension| extension == "json") {
+                file_paths.push(file_path);
+            }
+        }
+        if file_paths.is_empty() {
+            return Err(LoadError::Empty);
+        }
+        file_paths.sort();
+
+        let mut template = CustomCpuTemplate::default();
+        for file_path in file_paths {
+            let file_template = Self::from_path(&file_path)?;
+            template = template
+                .merge(&file_template, MergeStrategy::ErrorOnConflict)
+                .map_err(|err| LoadError::MergeConflict(file_path.display().to_string(), err))?;
+        }
+        Ok(template)
+    }
+
+    /// Load a custom CPU template from a reader, see [`CustomCpuTemplate::from_json_with_base`].
+    /// The format of the template is detected from its content.
+    pub fn from_reader(mut reader: impl Read) -> Result<Self, LoadError> {
+        let mut content = String::new();
+        reader.read_to_string(&mut content).map_err(LoadError::ReadInput)?;
+        match TemplateFormat::detect(&content)? {
+            TemplateFormat::Json => Self::from_json_with_base(&content),
+            TemplateFormat::Jsonc => Self::from_json_with_base(&strip_json_comments(&content)),
+        }
+    }
+
+    /// Load a custom CPU template like [`CustomCpuTemplate::from_json_with_base`], tolerating
+    /// comments and trailing commas in objects and arrays. Prefer the strict loaders for
+    /// templates that are not written by hand.
+    pub fn from_json_relaxed(json: &str) -> Result<Self, LoadError> {
+        Self::from_json_with_base(&strip_trailing_commas(&strip_json_comments(json)))
+    }
+
+    /// Load a custom CPU template from the standard input, see
+    /// [`CustomCpuTemplate::from_reader`].
+    pub fn from_stdin() -> Result<Self, LoadError> {
+        Self::from_reader(std::io::stdin().lock())
+    }
+
+    /// Load a JSON array of custom CPU templates. Each template is parsed and validated like
+    /// with `CustomCpuTemplate::try_from`.
+    pub fn vec_from_str(json: &str) -> Result<Vec<Self>, LoadError> {
+        let templates: Vec<Self> = serde_json::from_str(json)?;
+        for template in &templates {
+            template.validate().map_err(LoadError::Validate)?;
+        }
+        Ok(templates)
+    }
+
+    /// Serialize custom CPU templates to a JSON array, see [`CustomCpuTemplate::vec_from_str`].
+    pub fn vec_to_string(templates: &[Self]) -> String {
+        serde_json::to_string(templates).expect("Custom CPU templates are always serializable")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use vmm_sys_util::tempdir::TempDir;
+    use vmm_sys_util::tempfile::TempFile;
+
+    use super::*;
+    use crate::cpu_config::templates::RegisterValueFilter;
+    use crate::cpu_config::x86_64::cpuid::common::get_vendor_id_from_host;
+    use crate::cpu_config::x86_64::cpuid::VENDOR_ID_INTEL;
+    use crate::cpu_config::x86_64::custom_cpu_template::MsrFieldSource;
+    use crate::cpu_config::x86_64::static_cpu_templates::t2;
+    use crate::cpu_config::x86_64::test_utils::{build_test_template, TEST_TEMPLATE_JSON};
+
+    const OVERLAY_MSR_JSON: &str = r#"{
+        "msr_modifiers": [
+            {
+                "addr": "0x10a",
+                "bitmap": "0b0xxxxxxx"
+            }
+        ]
+    }"#;
+
+    fn with_base(json: &str, base: &str) -> String {
+        format!("{{ \"base\": \"{base}\", {}", json.trim_start().strip_prefix('{').unwrap())
+    }
+
+    #[test]
+    fn test_from_json_with_bitmap_syntax() {
+        let object_json = r#"{
+            "msr_modifiers": [
+                {
+                    "addr": "0x10a",
+                    "bitmap": {"filter": "0x80", "value": "0x0"}
+                }
+            ]
+        }"#;
+        let object_template =
+            CustomCpuTemplate::from_json_with_bitmap_syntax(object_json, BitmapSyntax::Any)
+                .unwrap();
+        assert_eq!(
+            CustomCpuTemplate::from_json_with_bitmap_syntax(
+                object_json,
+                BitmapSyntax::DisallowBitmapStrings,
+            )
+            .unwrap(),
+            object_template
+        );
+        assert_eq!(
+            object_template,
+            CustomCpuTemplate::try_from(OVERLAY_MSR_JSON).unwrap()
+        );
+
+        assert_eq!(
+            CustomCpuTemplate::from_json_with_bitmap_syntax(OVERLAY_MSR_JSON, BitmapSyntax::Any)
+                .unwrap(),
+            object_template
+        );
+        let error = CustomCpuTemplate::from_json_with_bitmap_syntax(
+            OVERLAY_MSR_JSON,
+            BitmapSyntax::DisallowBitmapStrings,
+        )
+        .unwrap_err();
+        assert!(matches!(error, LoadError::BitmapString(_)), "{error}");
+        assert_eq!(
+            error.to_string(),
+            "Bitmap 0b0xxxxxxx is a string, write it as an object with a filter and a value \
+             instead."
+        );
+        let error = CustomCpuTemplate::from_json_with_bitmap_syntax(
+            TEST_TEMPLATE_JSON,
+            BitmapSyntax::DisallowBitmapStrings,
+        )
+        .unwrap_err();
+        assert!(matches!(error, LoadError::BitmapString(_)), "{error}");
+    }
+
+    #[test]
+    fn test_from_json_with_bitmap_prefix() {
+        let prefixed_template = CustomCpuTemplate::from_json_with_bitmap_syntax(
+            OVERLAY_MSR_JSON,
+            BitmapSyntax::RequirePrefix,
+        )
+        .unwrap();
+        assert_eq!(
+            prefixed_template,
+            CustomCpuTemplate::try_from(OVERLAY_MSR_JSON).unwrap()
+        );
+
+        let unprefixed_json = OVERLAY_MSR_JSON.replace("0b0xxxxxxx", "0xxxxxxx");
+        assert_eq!(
+            CustomCpuTemplate::from_json_with_bitmap_syntax(&unprefixed_json, BitmapSyntax::Any)
+                .unwrap(),
+            prefixed_template
+        );
+        let error = CustomCpuTemplate::from_json_with_bitmap_syntax(
+            &unprefixed_json,
+            BitmapSyntax::RequirePrefix,
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Bitmap 0xxxxxxx is missing the 0b prefix."
+        );
+    }
+
+    #[test]
+    fn test_from_json_without_base() {
+        let template = CustomCpuTemplate::from_json_with_base(TEST_TEMPLATE_JSON).unwrap();
+        assert_eq!(
+            template,
+            CustomCpuTemplate::try_from(TEST_TEMPLATE_JSON).unwrap()
+        );
+
+        let json = serde_json::to_string(&build_test_template()).unwrap();
+        let template = CustomCpuTemplate::from_json_with_base(&json).unwrap();
+        assert_eq!(template, build_test_template());
+    }
+
+    #[test]
+    fn test_from_json_with_t2_base() {
+        let result = CustomCpuTemplate::from_json_with_base(&with_base(OVERLAY_MSR_JSON, "T2"));
+        if &get_vendor_id_from_host().unwrap() == VENDOR_ID_INTEL {
+            let template = result.unwrap();
+            let base = t2::t2();
+            assert_eq!(template.cpuid_modifiers, base.cpuid_modifiers);
+            assert_eq!(template.msr_modifiers.len(), base.msr_modifiers.len() + 1);
+            assert_eq!(template.msr_modifiers.last().unwrap().addr, 0x10a);
+        } else {
+            assert!(matches!(
+                result.unwrap_err(),
+                LoadError::Base(
+                    StaticCpuTemplate::T2,
+                    GetCpuTemplateError::CpuVendorMismatched
+                )
+            ));
+        }
+    }
+
+    #[test]
+    fn test_from_json_with_mismatched_base() {
+        // T2 is Intel only and T2A is AMD only, so one of them always mismatches the host.
+        let base = if &get_vendor_id_from_host().unwrap() == VENDOR_ID_INTEL {
+            StaticCpuTemplate::T2A
+        } else {
+            StaticCpuTemplate::T2
+        };
+        let error = CustomCpuTemplate::from_json_with_base(&with_base(
+            OVERLAY_MSR_JSON,
+            &base.to_string(),
+        ))
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "Failed to resolve base static CPU template {base}: CPU vendor mismatched between \
+                 actual CPU and CPU template."
+            )
+        );
+    }
+
+    #[test]
+    fn test_from_json_with_unknown_base() {
+        let error =
+            CustomCpuTemplate::from_json_with_base(&with_base(OVERLAY_MSR_JSON, "T3")).unwrap_err();
+        assert!(matches!(error, LoadError::Parse(_)), "{error}");
+    }
+
+    #[test]
+    fn test_from_path() {
+        let file = TempFile::new().unwrap();
+        std::fs::write(file.as_path(), TEST_TEMPLATE_JSON).unwrap();
+        assert_eq!(
+            CustomCpuTemplate::from_path(file.as_path()).unwrap(),
+            CustomCpuTemplate::try_from(TEST_TEMPLATE_JSON).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_from_path_malformed() {
+        let file = TempFile::new().unwrap();
+        let path = file.as_path().display().to_string();
+        let json = "{\n    \"msr_modifiers\": [\n        {,\n    ]\n}";
+        std::fs::write(file.as_path(), json).unwrap();
+
+        let error = CustomCpuTemplate::from_path(file.as_path()).unwrap_err();
+        assert!(matches!(error, LoadError::ParseFile(_, 3, _, _)), "{error}");
+        assert!(
+            error.to_string().starts_with(&format!("{path}:3:")),
+            "{error}"
+        );
+
+        let error = CustomCpuTemplate::from_path(Path::new("/no/such/template.json")).unwrap_err();
+        assert!(matches!(error, LoadError::Read(_, _)), "{error}");
+    }
+
+    #[test]
+    fn test_from_dir() {
+        let dir = TempDir::new().unwrap();
+        let write = |name: &str, content: &str| {
+            std::fs::write(dir.as_path().join(name), content).unwrap();
+        };
+        write("10-security.json", OVERLAY_MSR_JSON);
+        write("00-base.json", TEST_TEMPLATE_JSON);
+        write("README.md", "Not a template.");
+
+        let base = CustomCpuTemplate::try_from(TEST_TEMPLATE_JSON).unwrap();
+        let security = CustomCpuTemplate::try_from(OVERLAY_MSR_JSON).unwrap();
+        assert_eq!(
+            CustomCpuTemplate::from_dir(dir.as_path()).unwrap(),
+            base.merge(&security, MergeStrategy::ErrorOnConflict)
+                .unwrap()
+        );
+
+        // Bit 7 of MSR 0x10a is cleared by 10-security.json.
+        write(
+            "20-conflict.json",
+            r#"{"msr_modifiers": [{"addr": "0x10a", "bitmap": "0b1xxxxxxx"}]}"#,
+        );
+        let error = CustomCpuTemplate::from_dir(dir.as_path()).unwrap_err();
+        match error {
+            LoadError::MergeConflict(path, MergeConflictError::Msr { addr, bits }) => {
+                assert!(path.ends_with("20-conflict.json"), "{path}");
+                assert_eq!((addr, bits), (0x10a, 0x80));
+            }
+            error => panic!("Unexpected error: {error}"),
+        }
+
+        let empty_dir = TempDir::new().unwrap();
+        assert!(matches!(
+            CustomCpuTemplate::from_dir(empty_dir.as_path()).unwrap_err(),
+            LoadError::Empty
+        ));
+    }
+
+    #[test]
+    fn test_from_dir_msr_source() {
+        let dir = TempDir::new().unwrap();
+        let write = |name: &str, content: &str| {
+            std::fs::write(dir.as_path().join(name), content).unwrap();
+        };
+        write("00-security.json", OVERLAY_MSR_JSON);
+        write(
+            "10-source.json",
+            r#"{
+                "msr_modifiers": [
+                    {
+                        "addr": "0x10a",
+                        "bitmap": "0bxxxx0000",
+                        "source": {"msr": "0x48", "bits": [0, 3]}
+                    }
+                ]
+            }"#,
+        );
+        let template = CustomCpuTemplate::from_dir(dir.as_path()).unwrap();
+        assert_eq!(template.msr_source_iter().collect::<Vec<_>>(), vec![0x48]);
+
+        // Bit 7 of MSR 0x10a is part of the field copied by 10-source.json.
+        write(
+            "10-source.json",
+            r#"{
+                "msr_modifiers": [
+                    {
+                        "addr": "0x10a",
+                        "bitmap": "0b0000xxxx",
+                        "source": {"msr": "0x48", "bits": [0, 3]}
+                    }
+                ]
+            }"#,
+        );
+        let error = CustomCpuTemplate::from_dir(dir.as_path()).unwrap_err();
+        match error {
+            LoadError::MergeConflict(path, MergeConflictError::MsrSource(addr)) => {
+                assert!(path.ends_with("10-source.json"), "{path}");
+                assert_eq!(addr, 0x10a);
+            }
+            error => panic!("Unexpected error: {error}"),
+        }
+    }
+
+    #[test]
+    fn test_detect_format() {
+        assert_eq!(
+            TemplateFormat::detect(TEST_TEMPLATE_JSON).unwrap(),
+            TemplateFormat::Json
+        );
+        // Comment markers within strings are not comments.
+        assert_eq!(
+            TemplateFormat::detect(r#"{"kvm_capabilities": ["/*", "//\\"]}"#).unwrap(),
+            TemplateFormat::Json
+        );
+        assert_eq!(
+            TemplateFormat::detect("// T2 with PDPE1GB\n{}").unwrap(),
+            TemplateFormat::Jsonc
+        );
+        assert!(matches!(TemplateFormat::detect(" \n\t").unwrap_err(), LoadError::Empty));
+        assert!(matches!(
+            TemplateFormat::detect("msr_modifiers: []").unwrap_err(),
+            LoadError::UnsupportedFormat
+        ));
+    }
+
+    #[test]
+    fn test_from_reader() {
+        let template = CustomCpuTemplate::from_reader(TEST_TEMPLATE_JSON.as_bytes()).unwrap();
+        assert_eq!(
+            template,
+            CustomCpuTemplate::try_from(TEST_TEMPLATE_JSON).unwrap()
+        );
+
+        let jsonc = r#"// Clears bit 7.
+            {
+                "msr_modifiers": [
+                    {
+                        "addr": "0x10a", /* IA32_ARCH_CAPABILITIES */
+                        "bitmap": "0b0xxxxxxx"
+                    }
+                ]
+            }"#;
+        let template = CustomCpuTemplate::from_reader(jsonc.as_bytes()).unwrap();
+        assert_eq!(
+            template,
+            CustomCpuTemplate::from_json_with_base(OVERLAY_MSR_JSON).unwrap()
+        );
+
+        let error = CustomCpuTemplate::from_reader("".as_bytes()).unwrap_err();
+        assert_eq!(error.to_string(), "CPU template is empty.");
+    }
+
+    #[test]
+    fn test_from_json_relaxed() {
+        let json = r#"{
+            "msr_modifiers": [
+                {
+                    "addr": "0x10a", // IA32_ARCH_CAPABILITIES
+                    "bitmap": "0b0xxxxxxx",
+                },
+            ],
+        }"#;
+        CustomCpuTemplate::from_json_with_base(json).unwrap_err();
+        assert_eq!(
+            CustomCpuTemplate::from_json_relaxed(json).unwrap(),
+            CustomCpuTemplate::from_json_with_base(OVERLAY_MSR_JSON).unwrap()
+        );
+        assert_eq!(
+            CustomCpuTemplate::from_json_relaxed(TEST_TEMPLATE_JSON).unwrap(),
+            CustomCpuTemplate::try_from(TEST_TEMPLATE_JSON).unwrap()
+        );
+
+        // Commas within strings are kept.
+        assert_eq!(
+            strip_trailing_commas(r#"{"a": [",]", ",}" , ] , }"#),
+            r#"{"a": [",]", ",}"  ]  }"#
+        );
+        // Only a single trailing comma is tolerated.
+        CustomCpuTemplate::from_json_relaxed(r#"{"kvm_capabilities": ["1",,]}"#).unwrap_err();
+    }
+
+    #[test]
+    fn test_vec_from_str() {
+        let templates = vec![
+            build_test_template(),
+            CustomCpuTemplate::try_from(TEST_TEMPLATE_JSON).unwrap(),
+        ];
+        let json = CustomCpuTemplate::vec_to_string(&templates);
+        assert!(json.starts_with('['), "{json}");
+        assert_eq!(CustomCpuTemplate::vec_from_str(&json).unwrap(), templates);
+
+        assert_eq!(
+            CustomCpuTemplate::vec_from_str("[]").unwrap(),
+            Vec::<CustomCpuTemplate>::new()
+        );
+        let error = CustomCpuTemplate::vec_from_str(TEST_TEMPLATE_JSON).unwrap_err();
+        assert!(matches!(error, LoadError::Parse(_)), "{error}");
+        let json = format!("[{OVERLAY_MSR_JSON}, {{\"unknown\": []}}]");
+        CustomCpuTemplate::vec_from_str(&json).unwrap_err();
+    }
+
+    const LAYERED_MSR_JSON: &str = r#"{
+        "msr_modifiers": [
+            {
+                "addr": "0x10a",
+                "bitmap": "0b0011xxxx"
+            },
+            {
+                "addr": "0x10",
+                "bitmap": "0b1"
+            },
+            {
+                "addr": "0x10a",
+                "bitmap": "0bxx1100xx"
+            }
+        ]
+    }"#;
+
+    fn msr_bitmaps(template: &CustomCpuTemplate) -> Vec<(u32, RegisterValueFilter<u64>)> {
+        template
+            .msr_modifiers
+            .iter()
+            .map(|modifier| (modifier.addr, modifier.bitmap))
+            .collect()
+    }
+
+    #[test]
+    fn test_layering_strict() {
+        let error =
+            CustomCpuTemplate::from_json_with_layering(LAYERED_MSR_JSON, LayeringMode::default())
+                .unwrap_err();
+        assert_eq!(error.to_string(), "MSR 0x10a is modified more than once.");
+
+        CustomCpuTemplate::from_json_with_layering(OVERLAY_MSR_JSON, LayeringMode::Strict)
+            .unwrap();
+
+        // T2S modifies MSR 0x10a too, which the template may override.
+        let json = with_base(OVERLAY_MSR_JSON, "T2S");
+        match CustomCpuTemplate::from_json_with_layering(&json, LayeringMode::Strict) {
+            Ok(template) => {
+                assert_eq!(
+                    template,
+                    CustomCpuTemplate::from_json_with_base(&json).unwrap()
+                );
+            }
+            Err(error) => assert!(matches!(error, LoadError::Base(..)), "{error}"),
+        }
+        let error = CustomCpuTemplate::from_json_with_layering(
+            &with_base(LAYERED_MSR_JSON, "T2S"),
+            LayeringMode::Strict,
+        )
+        .unwrap_err();
+        assert_eq!(error.to_string(), "MSR 0x10a is modified more than once.");
+    }
+
+    #[test]
+    fn test_layering_last_wins() {
+        let template =
+            CustomCpuTemplate::from_json_with_layering(LAYERED_MSR_JSON, LayeringMode::LastWins)
+                .unwrap();
+        assert_eq!(
+            msr_bitmaps(&template),
+            vec![
+                (0x10a, RegisterValueFilter::<u64>::from((0b0011_1100, 0b0011_0000))),
+                (0x10, RegisterValueFilter::<u64>::from((0b1, 0b1))),
+            ]
+        );
+
+        // The source of the last modifier is kept too.
+        let source = MsrFieldSource {
+            msr: 0x10,
+            bits: [0, 3],
+            default: None,
+        };
+        let mut template = CustomCpuTemplate::try_from(LAYERED_MSR_JSON).unwrap();
+        template.msr_modifiers[2].source = Some(source);
+        template.layer_msr_modifiers(LayeringMode::LastWins).unwrap();
+        assert_eq!(template.msr_modifiers[0].source, Some(source));
+    }
+
+    #[test]
+    fn test_layering_compose() {
+        let template =
+            CustomCpuTemplate::from_json_with_layering(LAYERED_MSR_JSON, LayeringMode::Compose)
+                .unwrap();
+        assert_eq!(
+            msr_bitmaps(&template),
+            vec![
+                (0x10a, RegisterValueFilter::<u64>::from((0b1111_1100, 0b0011_0000))),
+                (0x10, RegisterValueFilter::<u64>::from((0b1, 0b1))),
+            ]
+        );
+
+        // Composing the modifiers does not change the effect of the template.
+        let unlayered = CustomCpuTemplate::try_from(LAYERED_MSR_JSON).unwrap();
+        assert_eq!(template.effect_map(), unlayered.effect_map());
+
+        // Modifiers copying a field from another MSR are not composed.
+        let mut template = unlayered;
+        template.msr_modifiers[2].source = Some(MsrFieldSource {
+            msr: 0x10,
+            bits: [0, 3],
+            default: None,
+        });
+        assert_eq!(
+            template
+                .layer_msr_modifiers(LayeringMode::Compose)
+                .unwrap_err()
+                .to_string(),
+            "MSR 0x10a is modified more than once."
+        );
+    }
+}
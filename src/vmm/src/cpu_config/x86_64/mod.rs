@@ -1,20 +1,61 @@
 // Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
+/// Module for building custom CPU templates
+pub mod builder;
+/// Module for checksums of custom CPU templates
+pub mod checksum;
+/// Module for compact in-memory storage of custom CPU templates
+#[cfg(feature = "compact-cpu-templates")]
+pub mod compact;
 /// Module for CPUID instruction related content
 pub mod cpuid;
 /// Module for custom CPU templates
 pub mod custom_cpu_template;
+/// Module for comparing custom CPU templates
+pub mod diff;
+/// Module for detecting drift from applied custom CPU templates
+pub mod drift;
+/// Module with exporters of custom CPU templates to other formats
+pub mod export;
+/// Module for querying the custom CPU template features supported by this build
+pub mod features;
+/// Module for capturing the CPU configuration of the host
+pub mod host;
+/// Module for loading custom CPU templates
+pub mod loader;
+/// Module for custom CPU templates that vary per vCPU
+pub mod per_vcpu;
+/// Module for planning the KVM operations implied by custom CPU templates
+pub mod plan;
+/// Module for selecting custom CPU templates by microVM role
+pub mod registry;
 /// Module for static CPU templates
 pub mod static_cpu_templates;
 /// Module with test utils for custom CPU templates
 pub mod test_utils;
+/// Module with validations for custom CPU templates
+pub mod validation;
 
 use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
 
-use self::custom_cpu_template::CpuidRegister;
+use kvm_bindings::kvm_msr_entry;
+
+use self::custom_cpu_template::{
+    CpuidRegister, MaterializeError, MergeConflictError, MsrRemapError, MsrSourceError,
+    WidthError, SUBLEAF_WILDCARD,
+};
+use self::host::HostSnapshotError;
+use self::loader::LoadError;
+use self::validation::{
+    HostMsrError, HypervisorSignatureError, KvmCpuidFlagsError, NoopModifierError,
+    TemplatePolicyError, UndefinedRegisterError, VendorMismatchError,
+};
 use super::templates::CustomCpuTemplate;
-use crate::cpu_config::x86_64::cpuid::{Cpuid, CpuidKey};
+use crate::cpu_config::x86_64::cpuid::{Cpuid, CpuidKey, CpuidRegisters};
+#[cfg(feature = "cpu-template-metrics")]
+use crate::logger::{CpuTemplateMetrics, IncMetric};
 
 /// Errors thrown while configuring templates.
 #[derive(Debug, PartialEq, Eq, thiserror::Error, displaydoc::Display)]
@@ -27,6 +68,57 @@ pub enum CpuConfigurationError {
     CpuidFromKvmCpuid(crate::cpu_config::x86_64::cpuid::CpuidTryFromKvmCpuid),
     /// KVM vcpu ioctl failed: {0}
     VcpuIoctl(crate::vstate::vcpu::KvmVcpuError),
+    /// Applying the template was aborted.
+    Aborted,
+    /// {0}
+    MsrSource(#[from] MsrSourceError),
+}
+
+/// Errors thrown while applying templates to KVM entries.
+#[derive(Debug, PartialEq, Eq, thiserror::Error, displaydoc::Display)]
+pub enum ApplyError {
+    /// Template changes an MSR entry that is not present: Register Address: {0:#x}
+    MsrNotPresent(u32),
+    /// {0}
+    MsrSource(#[from] MsrSourceError),
+}
+
+/// Errors associated with custom CPU templates, wrapping the errors of the individual template
+/// operations so callers can handle all of them as one type.
+#[derive(Debug, thiserror::Error, displaydoc::Display)]
+pub enum TemplateError {
+    /// {0}
+    Load(#[from] LoadError),
+    /// {0}
+    Width(#[from] WidthError),
+    /// {0}
+    KvmCpuidFlags(#[from] KvmCpuidFlagsError),
+    /// {0}
+    VendorMismatch(#[from] VendorMismatchError),
+    /// {0}
+    NoopModifier(#[from] NoopModifierError),
+    /// {0}
+    Policy(#[from] TemplatePolicyError),
+    /// {0}
+    HostMsr(#[from] HostMsrError),
+    /// {0}
+    HostSnapshot(#[from] HostSnapshotError),
+    /// {0}
+    UndefinedRegister(#[from] UndefinedRegisterError),
+    /// {0}
+    HypervisorSignature(#[from] HypervisorSignatureError),
+    /// {0}
+    Apply(#[from] ApplyError),
+    /// {0}
+    Configuration(#[from] CpuConfigurationError),
+    /// {0}
+    MergeConflict(#[from] MergeConflictError),
+    /// {0}
+    MsrRemap(#[from] MsrRemapError),
+    /// {0}
+    Materialize(#[from] MaterializeError),
+    /// Template modifies MSR {0:#x}, which is not supported by KVM on the host.
+    UnsupportedMsr(u32),
 }
 
 /// CPU configuration for x86_64 CPUs
@@ -51,51 +143,289 @@ impl CpuConfiguration {
             mut msrs,
         } = self;
 
-        let guest_cpuid = cpuid.inner_mut();
+        apply_cpuid_modifiers(&mut cpuid, template, None)?;
+        apply_msr_modifiers(&mut msrs, template, None)?;
 
-        // Apply CPUID modifiers
-        for mod_leaf in template.cpuid_modifiers.iter() {
-            let cpuid_key = CpuidKey {
-                leaf: mod_leaf.leaf,
-                subleaf: mod_leaf.subleaf,
-            };
-            if let Some(entry) = guest_cpuid.get_mut(&cpuid_key) {
-                entry.flags = mod_leaf.flags;
+        Ok(Self { cpuid, msrs })
+    }
+
+    /// Modifies provided config with changes from template like
+    /// [`CpuConfiguration::apply_template`], checking `cancel` before applying each modifier.
+    ///
+    /// If `cancel` is set, [`CpuConfigurationError::Aborted`] is returned and the partially
+    /// modified configuration is dropped, so callers never observe a configuration with only
+    /// some of the modifiers applied.
+    pub fn apply_template_cancellable(
+        self,
+        template: &CustomCpuTemplate,
+        cancel: &AtomicBool,
+    ) -> Result<Self, CpuConfigurationError> {
+        let Self {
+            mut cpuid,
+            mut msrs,
+        } = self;
+
+        apply_cpuid_modifiers(&mut cpuid, template, Some(cancel))?;
+        apply_msr_modifiers(&mut msrs, template, Some(cancel))?;
+
+        Ok(Self { cpuid, msrs })
+    }
+
+    /// Modifies provided config with changes from template like
+    /// [`CpuConfiguration::apply_template`], recording the number of registers whose value
+    /// changed and the time spent applying the CPUID and MSR modifiers in `metrics`.
+    #[cfg(feature = "cpu-template-metrics")]
+    pub fn apply_template_with_metrics(
+        self,
+        template: &CustomCpuTemplate,
+        metrics: &CpuTemplateMetrics,
+    ) -> Result<Self, CpuConfigurationError> {
+        let Self {
+            mut cpuid,
+            mut msrs,
+        } = self;
+
+        let host_cpuid = cpuid.clone();
+        {
+            let _metric = metrics.apply_cpuid_agg.record_latency_metrics();
+            apply_cpuid_modifiers(&mut cpuid, template, None)?;
+        }
+        let cpuid_registers_modified = changed_cpuid_registers(&host_cpuid, &cpuid);
+        metrics.cpuid_registers_modified.add(cpuid_registers_modified as u64);
+
+        let host_msrs = msrs.clone();
+        {
+            let _metric = metrics.apply_msrs_agg.record_latency_metrics();
+            apply_msr_modifiers(&mut msrs, template, None)?;
+        }
+        let msrs_modified = msrs
+            .iter()
+            .filter(|(addr, value)| host_msrs.get(addr) != Some(value))
+            .count();
+        metrics.msrs_modified.add(msrs_modified as u64);
+
+        Ok(Self { cpuid, msrs })
+    }
+}
+
+/// Fail with [`CpuConfigurationError::Aborted`] if `cancel` is set.
+fn check_cancel(cancel: Option<&AtomicBool>) -> Result<(), CpuConfigurationError> {
+    match cancel {
+        Some(cancel) if cancel.load(Ordering::Acquire) => Err(CpuConfigurationError::Aborted),
+        _ => Ok(()),
+    }
+}
+
+/// Number of CPUID registers in `after` whose value differs from the same register in `before`.
+#[cfg(feature = "cpu-template-metrics")]
+fn changed_cpuid_registers(before: &Cpuid, after: &Cpuid) -> usize {
+    after
+        .inner()
+        .iter()
+        .filter_map(|(key, entry)| Some((&before.inner().get(key)?.result, &entry.result)))
+        .map(|(old, new)| {
+            [
+                (old.eax, new.eax),
+                (old.ebx, new.ebx),
+                (old.ecx, new.ecx),
+                (old.edx, new.edx),
+            ]
+            .iter()
+            .filter(|(old, new)| old != new)
+            .count()
+        })
+        .sum()
+}
+
+/// Apply the CPUID modifiers of `template` to `cpuid`. `cancel` is checked before applying
+/// each leaf modifier.
+fn apply_cpuid_modifiers(
+    cpuid: &mut Cpuid,
+    template: &CustomCpuTemplate,
+    cancel: Option<&AtomicBool>,
+) -> Result<(), CpuConfigurationError> {
+    let guest_cpuid = cpuid.inner_mut();
+
+    for mod_leaf in template.cpuid_modifiers.iter() {
+        check_cancel(cancel)?;
+        let mut entries: Vec<_> = if mod_leaf.subleaf == SUBLEAF_WILDCARD {
+            guest_cpuid
+                .iter_mut()
+                .filter(|(key, _)| key.leaf == mod_leaf.leaf)
+                .map(|(_, entry)| entry)
+                .collect()
+        } else {
+            guest_cpuid
+                .get_mut(&CpuidKey {
+                    leaf: mod_leaf.leaf,
+                    subleaf: mod_leaf.subleaf,
+                })
+                .into_iter()
+                .collect()
+        };
+        if entries.is_empty() {
+            return Err(CpuConfigurationError::CpuidFeatureNotSupported(
+                mod_leaf.leaf,
+                mod_leaf.subleaf,
+            ));
+        }
+
+        for entry in entries.iter_mut() {
+            entry.flags = mod_leaf.flags;
+
+            // Can we modify one reg multiple times????
+            for mod_reg in &mod_leaf.modifiers {
+                let register = cpuid_register_mut(&mut entry.result, &mod_reg.register);
+                *register = mod_reg.bitmap.apply(*register);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Apply the MSR modifiers of `template` to `msrs`. `cancel` is checked before applying each
+/// modifier. Fields are copied from the values of the source MSRs before any modifier is
+/// applied.
+fn apply_msr_modifiers(
+    msrs: &mut BTreeMap<u32, u64>,
+    template: &CustomCpuTemplate,
+    cancel: Option<&AtomicBool>,
+) -> Result<(), CpuConfigurationError> {
+    let host_msrs = msrs.clone();
+    for modifier in &template.msr_modifiers {
+        check_cancel(cancel)?;
+        let bitmap = modifier.resolve_bitmap(|addr| host_msrs.get(&addr).copied())?;
+        if let Some(reg_value) = msrs.get_mut(&modifier.addr) {
+            *reg_value = bitmap.apply(*reg_value);
+        } else {
+            return Err(CpuConfigurationError::MsrNotSupported(modifier.addr));
+        }
+    }
 
-                // Can we modify one reg multiple times????
+    Ok(())
+}
+
+/// Value of a CPUID register before and after applying a template.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegisterChange {
+    /// Leaf value.
+    pub leaf: u32,
+    /// Sub-Leaf value.
+    pub subleaf: u32,
+    /// CPUID register.
+    pub register: CpuidRegister,
+    /// Value before applying the template.
+    pub before: u32,
+    /// Value after applying the template.
+    pub after: u32,
+}
+
+fn cpuid_register_mut<'a>(
+    registers: &'a mut CpuidRegisters,
+    register: &CpuidRegister,
+) -> &'a mut u32 {
+    match register {
+        CpuidRegister::Eax => &mut registers.eax,
+        CpuidRegister::Ebx => &mut registers.ebx,
+        CpuidRegister::Ecx => &mut registers.ecx,
+        CpuidRegister::Edx => &mut registers.edx,
+    }
+}
+
+impl CustomCpuTemplate {
+    /// Apply the CPUID modifiers of the template and report the original and the new value of
+    /// every register whose value changed.
+    ///
+    /// Unlike [`CpuConfiguration::apply_template`], modifiers of leaves that are not present are
+    /// skipped instead of failing.
+    pub fn apply_cpuid_audited(&self, cpuid: &mut Cpuid) -> Vec<RegisterChange> {
+        let mut values = BTreeMap::<(CpuidKey, CpuidRegister), (u32, u32)>::new();
+        for mod_leaf in &self.cpuid_modifiers {
+            let entries = cpuid.inner_mut().iter_mut().filter(|(key, _)| {
+                key.leaf == mod_leaf.leaf
+                    && (mod_leaf.subleaf == SUBLEAF_WILDCARD || key.subleaf == mod_leaf.subleaf)
+            });
+            for (key, entry) in entries {
+                entry.flags = mod_leaf.flags;
                 for mod_reg in &mod_leaf.modifiers {
-                    match mod_reg.register {
-                        CpuidRegister::Eax => {
-                            entry.result.eax = mod_reg.bitmap.apply(entry.result.eax)
-                        }
-                        CpuidRegister::Ebx => {
-                            entry.result.ebx = mod_reg.bitmap.apply(entry.result.ebx)
-                        }
-                        CpuidRegister::Ecx => {
-                            entry.result.ecx = mod_reg.bitmap.apply(entry.result.ecx)
-                        }
-                        CpuidRegister::Edx => {
-                            entry.result.edx = mod_reg.bitmap.apply(entry.result.edx)
-                        }
-                    }
+                    let value = cpuid_register_mut(&mut entry.result, &mod_reg.register);
+                    let before = *value;
+                    *value = mod_reg.bitmap.apply(before);
+                    values
+                        .entry((key.clone(), mod_reg.register.clone()))
+                        .or_insert((before, before))
+                        .1 = *value;
                 }
-            } else {
-                return Err(CpuConfigurationError::CpuidFeatureNotSupported(
-                    cpuid_key.leaf,
-                    cpuid_key.subleaf,
-                ));
             }
         }
 
-        for modifier in &template.msr_modifiers {
-            if let Some(reg_value) = msrs.get_mut(&modifier.addr) {
-                *reg_value = modifier.bitmap.apply(*reg_value);
-            } else {
-                return Err(CpuConfigurationError::MsrNotSupported(modifier.addr));
+        values
+            .into_iter()
+            .filter(|(_, (before, after))| before != after)
+            .map(|((key, register), (before, after))| RegisterChange {
+                leaf: key.leaf,
+                subleaf: key.subleaf,
+                register,
+                before,
+                after,
+            })
+            .collect()
+    }
+
+    /// Read the values of the MSRs modified by the template with `read_msrs`, together with the
+    /// source MSRs it copies fields from. Source MSRs are read one by one. If reading one fails
+    /// and its source has a default value, the MSR is left out so that the default is used when
+    /// applying the template, otherwise the error is returned.
+    pub fn read_msrs<E>(
+        &self,
+        read_msrs: impl Fn(&[u32]) -> Result<BTreeMap<u32, u64>, E>,
+    ) -> Result<BTreeMap<u32, u64>, E> {
+        let addrs: Vec<u32> = self.msr_index_iter().collect();
+        let mut msrs = read_msrs(&addrs)?;
+        for source in self.msr_modifiers.iter().filter_map(|modifier| modifier.source) {
+            if msrs.contains_key(&source.msr) {
+                continue;
+            }
+            match read_msrs(&[source.msr]) {
+                Ok(source_msrs) => msrs.extend(source_msrs),
+                Err(_) if source.default.is_some() => {}
+                Err(err) => return Err(err),
             }
         }
+        Ok(msrs)
+    }
 
-        Ok(Self { cpuid, msrs })
+    /// Apply the MSR modifiers of the template to the matching KVM MSR entries in place.
+    ///
+    /// Modifiers are applied in order. All modifiers targeting an MSR without a matching entry
+    /// are reported, while the remaining modifiers are still applied. Fields are copied from the
+    /// entries of the source MSRs before any modifier is applied.
+    pub fn apply_msr_entries(&self, entries: &mut [kvm_msr_entry]) -> Result<(), Vec<ApplyError>> {
+        let host_msrs: BTreeMap<u32, u64> = entries
+            .iter()
+            .map(|entry| (entry.index, entry.data))
+            .collect();
+        let mut errors = Vec::new();
+        for modifier in &self.msr_modifiers {
+            let bitmap = match modifier.resolve_bitmap(|addr| host_msrs.get(&addr).copied()) {
+                Ok(bitmap) => bitmap,
+                Err(err) => {
+                    errors.push(ApplyError::MsrSource(err));
+                    continue;
+                }
+            };
+            match entries.iter_mut().find(|entry| entry.index == modifier.addr) {
+                Some(entry) => entry.data = bitmap.apply(entry.data),
+                None => errors.push(ApplyError::MsrNotPresent(modifier.addr)),
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
     }
 }
 
@@ -105,7 +435,9 @@ mod tests {
 
     use kvm_bindings::KVM_CPUID_FLAG_STATEFUL_FUNC;
 
-    use super::custom_cpu_template::{CpuidLeafModifier, CpuidRegisterModifier, RegisterModifier};
+    use super::custom_cpu_template::{
+        CpuidLeafModifier, CpuidRegisterModifier, MsrFieldSource, RegisterModifier,
+    };
     use super::*;
     use crate::cpu_config::templates::RegisterValueFilter;
     use crate::cpu_config::x86_64::cpuid::{CpuidEntry, IntelCpuid, KvmCpuidFlags};
@@ -123,6 +455,7 @@ mod tests {
                             filter: 0b0111,
                             value: 0b0101,
                         },
+                        passthrough: false,
                     },
                     CpuidRegisterModifier {
                         register: CpuidRegister::Ebx,
@@ -130,6 +463,7 @@ mod tests {
                             filter: 0b0111,
                             value: 0b0100,
                         },
+                        passthrough: false,
                     },
                     CpuidRegisterModifier {
                         register: CpuidRegister::Ecx,
@@ -137,6 +471,7 @@ mod tests {
                             filter: 0b0111,
                             value: 0b0111,
                         },
+                        passthrough: false,
                     },
                     CpuidRegisterModifier {
                         register: CpuidRegister::Edx,
@@ -144,6 +479,7 @@ mod tests {
                             filter: 0b0111,
                             value: 0b0001,
                         },
+                        passthrough: false,
                     },
                 ],
             }],
@@ -154,6 +490,7 @@ mod tests {
                         filter: 0,
                         value: 0,
                     },
+                    source: None,
                 },
                 RegisterModifier {
                     addr: 0x8000,
@@ -161,6 +498,7 @@ mod tests {
                         filter: 0,
                         value: 0,
                     },
+                    source: None,
                 },
             ],
             ..Default::default()
@@ -264,4 +602,359 @@ mod tests {
             CpuConfigurationError::MsrNotSupported(guest_template.msr_modifiers[0].addr)
         )
     }
+
+    #[test]
+    fn test_apply_template_subleaf_wildcard() {
+        let host_configuration = CpuConfiguration {
+            cpuid: Cpuid::Intel(IntelCpuid(BTreeMap::from([
+                (CpuidKey::subleaf(0x7, 0x0), CpuidEntry::default()),
+                (CpuidKey::subleaf(0x7, 0x1), CpuidEntry::default()),
+                (CpuidKey::subleaf(0x8, 0x0), CpuidEntry::default()),
+            ]))),
+            msrs: Default::default(),
+        };
+        let template = CustomCpuTemplate {
+            cpuid_modifiers: vec![CpuidLeafModifier {
+                leaf: 0x7,
+                subleaf: SUBLEAF_WILDCARD,
+                flags: KvmCpuidFlags::EMPTY,
+                modifiers: vec![CpuidRegisterModifier {
+                    register: CpuidRegister::Ebx,
+                    bitmap: RegisterValueFilter {
+                        filter: 0b11,
+                        value: 0b01,
+                    },
+                    passthrough: false,
+                }],
+            }],
+            ..Default::default()
+        };
+
+        let cpu_config = host_configuration.apply_template(&template).unwrap();
+        let cpuid = cpu_config.cpuid.inner();
+        assert_eq!(cpuid[&CpuidKey::subleaf(0x7, 0x0)].result.ebx, 0b01);
+        assert_eq!(cpuid[&CpuidKey::subleaf(0x7, 0x1)].result.ebx, 0b01);
+        assert_eq!(cpuid[&CpuidKey::subleaf(0x8, 0x0)].result.ebx, 0);
+
+        // A wildcard still requires the leaf to be present.
+        assert_eq!(
+            empty_cpu_config().apply_template(&template).unwrap_err(),
+            CpuConfigurationError::CpuidFeatureNotSupported(0x7, SUBLEAF_WILDCARD)
+        );
+    }
+
+    #[test]
+    fn test_apply_msr_entries() {
+        let msr_entry = |index, data| kvm_msr_entry {
+            index,
+            data,
+            ..Default::default()
+        };
+        let mut template = build_test_template();
+        template.msr_modifiers[0].bitmap = RegisterValueFilter {
+            filter: 0b0110,
+            value: 0b0100,
+        };
+
+        let mut entries = vec![msr_entry(0x9999, 0b1010), msr_entry(0x8000, 0b1000)];
+        template.apply_msr_entries(&mut entries).unwrap();
+        assert_eq!(entries[0].data, 0b1100);
+        assert_eq!(entries[1].data, 0b1000);
+
+        let mut entries = vec![msr_entry(0x9999, 0b1010)];
+        assert_eq!(
+            template.apply_msr_entries(&mut entries).unwrap_err(),
+            vec![ApplyError::MsrNotPresent(0x8000)]
+        );
+        // Modifiers with a matching entry are still applied.
+        assert_eq!(entries[0].data, 0b1100);
+    }
+
+    #[test]
+    fn test_apply_msr_source() {
+        // Bits 4 to 7 of MSR 0x9999 are copied into bits 1 to 4 of MSR 0x8000.
+        let mut template = build_test_template();
+        template.msr_modifiers[1] = RegisterModifier {
+            addr: 0x8000,
+            bitmap: RegisterValueFilter {
+                filter: 0b1_1110,
+                value: 0,
+            },
+            source: Some(MsrFieldSource {
+                msr: 0x9999,
+                bits: [4, 7],
+                default: None,
+            }),
+        };
+        // The source MSR is modified before the target, but the field is copied from the value
+        // of the host.
+        template.msr_modifiers[0].bitmap = RegisterValueFilter {
+            filter: 0xf0,
+            value: 0,
+        };
+        let host_cpu_config = CpuConfiguration {
+            cpuid: build_supported_cpuid(),
+            msrs: BTreeMap::from([(0x8000, 0b1_0000_0001), (0x9999, 0b1010_0011)]),
+        };
+
+        let guest_cpu_config = host_cpu_config.clone().apply_template(&template).unwrap();
+        assert_eq!(guest_cpu_config.msrs[&0x8000], 0b1_0001_0101);
+        assert_eq!(guest_cpu_config.msrs[&0x9999], 0b0000_0011);
+
+        let msr_entry = |index, data| kvm_msr_entry {
+            index,
+            data,
+            ..Default::default()
+        };
+        let mut entries = vec![
+            msr_entry(0x9999, 0b1010_0011),
+            msr_entry(0x8000, 0b1_0000_0001),
+        ];
+        template.apply_msr_entries(&mut entries).unwrap();
+        assert_eq!(entries[1].data, 0b1_0001_0101);
+
+        // The source MSR must be present.
+        let mut host_cpu_config = host_cpu_config;
+        host_cpu_config.msrs.remove(&0x9999);
+        template.msr_modifiers.remove(0);
+        assert_eq!(
+            host_cpu_config.apply_template(&template).unwrap_err(),
+            CpuConfigurationError::MsrSource(MsrSourceError::SourceNotPresent(0x9999))
+        );
+        let mut entries = vec![msr_entry(0x8000, 0b1_0000_0001)];
+        assert_eq!(
+            template.apply_msr_entries(&mut entries).unwrap_err(),
+            vec![ApplyError::MsrSource(
+                MsrSourceError::SourceNotPresent(0x9999)
+            )]
+        );
+    }
+
+    #[test]
+    fn test_read_msrs_with_source_default() {
+        let host_msrs = BTreeMap::from([(0x8000, 0b1_0000_0001), (0x9999, 0b1010_0011)]);
+        // Reading fails with the first MSR that is not on the host.
+        let read_msrs = |addrs: &[u32]| {
+            addrs
+                .iter()
+                .map(|addr| Ok((*addr, *host_msrs.get(addr).ok_or(*addr)?)))
+                .collect::<Result<BTreeMap<_, _>, u32>>()
+        };
+        let source = MsrFieldSource {
+            msr: 0x9999,
+            bits: [4, 7],
+            default: Some(0b0110_0000),
+        };
+        let template = CustomCpuTemplate {
+            msr_modifiers: vec![RegisterModifier {
+                addr: 0x8000,
+                bitmap: RegisterValueFilter {
+                    filter: 0b1_1110,
+                    value: 0,
+                },
+                source: Some(source),
+            }],
+            ..Default::default()
+        };
+        let apply = |template: &CustomCpuTemplate| {
+            let cpu_config = CpuConfiguration {
+                cpuid: build_supported_cpuid(),
+                msrs: template.read_msrs(read_msrs).unwrap(),
+            };
+            cpu_config.apply_template(template).unwrap().msrs[&0x8000]
+        };
+
+        // The field is copied from the value of the host if the source MSR can be read.
+        assert_eq!(template.read_msrs(read_msrs).unwrap(), host_msrs);
+        assert_eq!(apply(&template), 0b1_0001_0101);
+
+        // The default is used otherwise.
+        let mut template = template;
+        template.msr_modifiers[0].source = Some(MsrFieldSource {
+            msr: 0xabcd,
+            ..source
+        });
+        assert_eq!(
+            template.read_msrs(read_msrs).unwrap(),
+            BTreeMap::from([(0x8000, 0b1_0000_0001)])
+        );
+        assert_eq!(apply(&template), 0b1_0000_1101);
+
+        // Without a default, the read error is returned.
+        template.msr_modifiers[0].source = Some(MsrFieldSource {
+            msr: 0xabcd,
+            default: None,
+            ..source
+        });
+        assert_eq!(template.read_msrs(read_msrs).unwrap_err(), 0xabcd);
+        // Errors reading the modified MSRs are always returned.
+        template.msr_modifiers[0].addr = 0xdcba;
+        template.msr_modifiers[0].source = Some(source);
+        assert_eq!(template.read_msrs(read_msrs).unwrap_err(), 0xdcba);
+    }
+
+    #[test]
+    fn test_apply_template_cancellable() {
+        let template = build_test_template();
+        let cancel = AtomicBool::new(false);
+        assert_eq!(
+            supported_cpu_config()
+                .apply_template_cancellable(&template, &cancel)
+                .unwrap(),
+            supported_cpu_config().apply_template(&template).unwrap()
+        );
+
+        // The flag is checked before the first modifier, so nothing is applied. The partially
+        // modified configuration is dropped either way.
+        cancel.store(true, Ordering::Release);
+        assert_eq!(
+            supported_cpu_config()
+                .apply_template_cancellable(&template, &cancel)
+                .unwrap_err(),
+            CpuConfigurationError::Aborted
+        );
+        // Templates without modifiers never check the flag.
+        assert_eq!(
+            supported_cpu_config()
+                .apply_template_cancellable(&CustomCpuTemplate::default(), &cancel)
+                .unwrap(),
+            supported_cpu_config()
+        );
+    }
+
+    #[cfg(feature = "cpu-template-metrics")]
+    #[test]
+    fn test_apply_template_with_metrics() {
+        use crate::logger::StoreMetric;
+
+        let metrics = CpuTemplateMetrics::new();
+        let mut template = build_test_template();
+        // Only MSR 0x8000 changes value, MSR 0x9999 is left as is by the template.
+        template.msr_modifiers[1].bitmap = RegisterValueFilter {
+            filter: 0b1111,
+            value: 0b0001,
+        };
+        let guest_configuration = supported_cpu_config()
+            .apply_template_with_metrics(&template, &metrics)
+            .unwrap();
+        assert_eq!(
+            guest_configuration,
+            supported_cpu_config().apply_template(&template).unwrap()
+        );
+        assert_eq!(metrics.cpuid_registers_modified.count(), 4);
+        assert_eq!(metrics.msrs_modified.count(), 1);
+        assert!(metrics.apply_cpuid_agg.min_us.fetch() <= metrics.apply_cpuid_agg.max_us.fetch());
+        assert!(metrics.apply_msrs_agg.min_us.fetch() <= metrics.apply_msrs_agg.max_us.fetch());
+
+        supported_cpu_config()
+            .apply_template_with_metrics(&template, &metrics)
+            .unwrap();
+        assert_eq!(metrics.cpuid_registers_modified.count(), 8);
+        assert_eq!(metrics.msrs_modified.count(), 2);
+
+        // MSRs are not counted if they are not all supported.
+        unsupported_cpu_config()
+            .apply_template_with_metrics(&template, &metrics)
+            .unwrap_err();
+        assert_eq!(metrics.msrs_modified.count(), 2);
+    }
+
+    #[test]
+    fn test_apply_cpuid_audited() {
+        let mut cpuid = build_supported_cpuid();
+        cpuid.inner_mut().get_mut(&CpuidKey::leaf(0x3)).unwrap().result = CpuidRegisters {
+            eax: 0b0101,
+            ebx: 0b1000,
+            ecx: 0,
+            edx: 0b0001,
+        };
+        let mut template = build_test_template();
+        // Modifiers of missing leaves are skipped.
+        template.cpuid_modifiers.push(CpuidLeafModifier {
+            leaf: 0x4,
+            ..template.cpuid_modifiers[0].clone()
+        });
+
+        let changes = template.apply_cpuid_audited(&mut cpuid);
+        // EAX and EDX already have the values set by the template.
+        assert_eq!(
+            changes,
+            vec![
+                RegisterChange {
+                    leaf: 0x3,
+                    subleaf: 0x0,
+                    register: CpuidRegister::Ebx,
+                    before: 0b1000,
+                    after: 0b1100,
+                },
+                RegisterChange {
+                    leaf: 0x3,
+                    subleaf: 0x0,
+                    register: CpuidRegister::Ecx,
+                    before: 0,
+                    after: 0b0111,
+                },
+            ]
+        );
+
+        // Applying the template again changes nothing.
+        assert_eq!(template.apply_cpuid_audited(&mut cpuid), vec![]);
+    }
+
+    #[test]
+    fn test_template_error_display() {
+        let errors = [
+            (TemplateError::from(LoadError::DuplicateMsr(0x10a)), "0x10a"),
+            (
+                TemplateError::from(WidthError::Cpuid(CpuidRegister::Ecx, 0x1_0000_0000)),
+                "0x100000000",
+            ),
+            (
+                TemplateError::from(KvmCpuidFlagsError::MissingSignificantIndex(0x7)),
+                "0x7",
+            ),
+            (
+                TemplateError::from(VendorMismatchError {
+                    template_vendor: "GenuineIntel",
+                    leaf: 0x8000001e,
+                    leaf_vendor: "AuthenticAMD",
+                }),
+                "0x8000001e",
+            ),
+            (
+                TemplateError::from(NoopModifierError::Msr(0x9999)),
+                "0x9999",
+            ),
+            (
+                TemplateError::from(TemplatePolicyError::MsrNotAllowed(0x48)),
+                "0x48",
+            ),
+            (TemplateError::from(ApplyError::MsrNotPresent(0x3a)), "0x3a"),
+            (TemplateError::from(CpuConfigurationError::Aborted), "aborted"),
+            (
+                TemplateError::from(MergeConflictError::Msr {
+                    addr: 0x10a,
+                    bits: 0x1,
+                }),
+                "0x10a",
+            ),
+            (
+                TemplateError::from(MsrRemapError {
+                    first: 0x1,
+                    second: 0x3,
+                    addr: 0x3,
+                }),
+                "0x1",
+            ),
+        ];
+        for (error, identifier) in errors {
+            assert!(error.to_string().contains(identifier), "{error}");
+        }
+
+        // Wrapping does not change the message.
+        assert_eq!(
+            TemplateError::from(ApplyError::MsrNotPresent(0x3a)).to_string(),
+            ApplyError::MsrNotPresent(0x3a).to_string()
+        );
+    }
 }
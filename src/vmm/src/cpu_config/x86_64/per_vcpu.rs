@@ -0,0 +1,130 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::cpu_config::templates::CustomCpuTemplate;
+use crate::cpu_config::x86_64::custom_cpu_template::{MergeConflictError, MergeStrategy};
+
+/// Custom CPU template that varies per vCPU, e.g. to make the boot vCPU advertise different
+/// topology leaves than the others.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PerVcpuTemplate {
+    /// Template of every vCPU.
+    pub default: CustomCpuTemplate,
+    /// Templates merged into the default one for some vCPUs, keyed by vCPU index.
+    #[serde(default)]
+    pub overrides: HashMap<u32, CustomCpuTemplate>,
+}
+
+impl PerVcpuTemplate {
+    /// Get the template of the vCPU at `index`, i.e. the default template merged with the
+    /// override of the vCPU, if any. Bits set by both take the value of the override.
+    pub fn resolve(&self, index: u32) -> Result<CustomCpuTemplate, MergeConflictError> {
+        match self.overrides.get(&index) {
+            Some(template) => self.default.merge(template, MergeStrategy::LaterWins),
+            None => Ok(self.default.clone()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu_config::templates::RegisterValueFilter;
+    use crate::cpu_config::x86_64::cpuid::KvmCpuidFlags;
+    use crate::cpu_config::x86_64::custom_cpu_template::{
+        CpuidLeafModifier, CpuidRegister, CpuidRegisterModifier, MsrFieldSource, RegisterModifier,
+    };
+    use crate::cpu_config::x86_64::test_utils::build_test_template;
+
+    fn build_per_vcpu_template() -> PerVcpuTemplate {
+        let boot_vcpu = CustomCpuTemplate {
+            cpuid_modifiers: vec![CpuidLeafModifier {
+                leaf: 0x3,
+                subleaf: 0x0,
+                flags: KvmCpuidFlags::STATEFUL_FUNC,
+                modifiers: vec![CpuidRegisterModifier {
+                    register: CpuidRegister::Eax,
+                    bitmap: RegisterValueFilter {
+                        filter: 0b1000,
+                        value: 0b1000,
+                    },
+                    passthrough: false,
+                }],
+            }],
+            ..Default::default()
+        };
+        PerVcpuTemplate {
+            default: build_test_template(),
+            overrides: HashMap::from([(0, boot_vcpu)]),
+        }
+    }
+
+    #[test]
+    fn test_resolve() {
+        let template = build_per_vcpu_template();
+
+        let boot_vcpu = template.resolve(0).unwrap();
+        let eax = boot_vcpu.cpuid_modifiers[0]
+            .modifiers
+            .iter()
+            .find(|reg_modifier| reg_modifier.register == CpuidRegister::Eax)
+            .unwrap();
+        assert_eq!(
+            eax.bitmap,
+            RegisterValueFilter {
+                filter: 0b1111,
+                value: 0b1101,
+            }
+        );
+
+        assert_eq!(template.resolve(1).unwrap(), build_test_template());
+        assert_eq!(template.resolve(7).unwrap(), build_test_template());
+    }
+
+    #[test]
+    fn test_resolve_msr_source() {
+        let source = RegisterModifier {
+            addr: 0x8000,
+            bitmap: RegisterValueFilter {
+                filter: 0b1111,
+                value: 0,
+            },
+            source: Some(MsrFieldSource {
+                msr: 0x10a,
+                bits: [4, 7],
+                default: None,
+            }),
+        };
+        let template = PerVcpuTemplate {
+            default: build_test_template(),
+            overrides: HashMap::from([(
+                0,
+                CustomCpuTemplate {
+                    msr_modifiers: vec![source],
+                    ..Default::default()
+                },
+            )]),
+        };
+
+        let boot_vcpu = template.resolve(0).unwrap();
+        assert_eq!(boot_vcpu.msr_modifiers, vec![source]);
+    }
+
+    #[test]
+    fn test_serde() {
+        let template = build_per_vcpu_template();
+        let json = serde_json::to_string(&template).unwrap();
+        assert_eq!(
+            serde_json::from_str::<PerVcpuTemplate>(&json).unwrap(),
+            template
+        );
+
+        let template = serde_json::from_str::<PerVcpuTemplate>(r#"{"default": {}}"#).unwrap();
+        assert_eq!(template, PerVcpuTemplate::default());
+    }
+}
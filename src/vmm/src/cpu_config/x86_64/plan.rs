@@ -0,0 +1,145 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use serde::{Deserialize, Serialize};
+
+use crate::cpu_config::templates::CustomCpuTemplate;
+use crate::cpu_config::x86_64::cpuid::{CpuidRegisters, KvmCpuidFlags};
+use crate::cpu_config::x86_64::custom_cpu_template::SUBLEAF_WILDCARD;
+use crate::cpu_config::x86_64::host::HostSnapshot;
+use crate::cpu_config::x86_64::{CpuConfiguration, CpuConfigurationError};
+
+/// KVM operation implied by a custom CPU template.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum KvmOp {
+    /// Set a CPUID entry.
+    SetCpuidEntry {
+        /// Leaf value.
+        leaf: u32,
+        /// Sub-Leaf value.
+        subleaf: u32,
+        /// KVM CPUID flags of the entry.
+        flags: KvmCpuidFlags,
+        /// Register values of the entry.
+        register_values: CpuidRegisters,
+    },
+    /// Set an MSR.
+    SetMsr {
+        /// MSR address.
+        addr: u32,
+        /// Value of the MSR after applying the template.
+        value_after_apply: u64,
+    },
+}
+
+impl CustomCpuTemplate {
+    /// Plan the KVM operations applying the template to a vCPU of `host` implies, without
+    /// executing them.
+    ///
+    /// There is one operation for every CPUID entry matched by a leaf modifier and one for every
+    /// MSR modifier, in the order of the template. Each operation sets the value the entry or MSR
+    /// has after applying the whole template.
+    pub fn plan(&self, host: &HostSnapshot) -> Result<Vec<KvmOp>, CpuConfigurationError> {
+        let host_config = CpuConfiguration {
+            cpuid: host.cpuid.clone(),
+            msrs: host.msrs.iter().map(|(addr, value)| (*addr, *value)).collect(),
+        };
+        let guest_config = host_config.apply_template(self)?;
+
+        let mut ops = Vec::new();
+        for leaf_modifier in &self.cpuid_modifiers {
+            for (key, entry) in guest_config.cpuid.inner() {
+                let matches_subleaf = leaf_modifier.subleaf == SUBLEAF_WILDCARD
+                    || key.subleaf == leaf_modifier.subleaf;
+                if key.leaf == leaf_modifier.leaf && matches_subleaf {
+                    ops.push(KvmOp::SetCpuidEntry {
+                        leaf: key.leaf,
+                        subleaf: key.subleaf,
+                        flags: entry.flags,
+                        register_values: entry.result.clone(),
+                    });
+                }
+            }
+        }
+        for modifier in &self.msr_modifiers {
+            if let Some(value) = guest_config.msrs.get(&modifier.addr) {
+                ops.push(KvmOp::SetMsr {
+                    addr: modifier.addr,
+                    value_after_apply: *value,
+                });
+            }
+        }
+        Ok(ops)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashMap};
+
+    use super::*;
+    use crate::arch::x86_64::cpu_model::CpuModel;
+    use crate::cpu_config::x86_64::cpuid::{
+        Cpuid, CpuidEntry, CpuidKey, IntelCpuid, VENDOR_ID_INTEL,
+    };
+    use crate::cpu_config::x86_64::test_utils::build_test_template;
+
+    fn build_snapshot() -> HostSnapshot {
+        HostSnapshot {
+            vendor: *VENDOR_ID_INTEL,
+            model: CpuModel::from(&0x0005_0657),
+            cpuid: Cpuid::Intel(IntelCpuid(BTreeMap::from([
+                (CpuidKey::leaf(0x1), CpuidEntry::default()),
+                (CpuidKey::leaf(0x3), CpuidEntry::default()),
+            ]))),
+            msrs: HashMap::from([(0x8000, 0b1000), (0x9999, 0b1010), (0x10, 0x1)]),
+        }
+    }
+
+    #[test]
+    fn test_plan() {
+        let template = build_test_template();
+        let plan = template.plan(&build_snapshot()).unwrap();
+        assert_eq!(
+            plan,
+            vec![
+                KvmOp::SetCpuidEntry {
+                    leaf: 0x3,
+                    subleaf: 0x0,
+                    flags: template.cpuid_modifiers[0].flags,
+                    register_values: CpuidRegisters {
+                        eax: 0b0101,
+                        ebx: 0b0100,
+                        ecx: 0b0111,
+                        edx: 0b0001,
+                    },
+                },
+                KvmOp::SetMsr {
+                    addr: 0x9999,
+                    value_after_apply: 0b1010,
+                },
+                KvmOp::SetMsr {
+                    addr: 0x8000,
+                    value_after_apply: 0b1000,
+                },
+            ]
+        );
+
+        let json = serde_json::to_string(&plan).unwrap();
+        assert_eq!(serde_json::from_str::<Vec<KvmOp>>(&json).unwrap(), plan);
+    }
+
+    #[test]
+    fn test_plan_unsupported() {
+        let mut snapshot = build_snapshot();
+        snapshot.msrs.remove(&0x8000);
+        assert_eq!(
+            build_test_template().plan(&snapshot).unwrap_err(),
+            CpuConfigurationError::MsrNotSupported(0x8000)
+        );
+        assert_eq!(
+            CustomCpuTemplate::default().plan(&snapshot).unwrap(),
+            vec![]
+        );
+    }
+}
@@ -0,0 +1,72 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::cpu_config::templates::CustomCpuTemplate;
+
+/// Custom CPU templates indexed by the role of the microVMs they are meant for, e.g. "db".
+///
+/// The registry is (de)serialized as a JSON object mapping roles to templates.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct TemplateRegistry(BTreeMap<String, CustomCpuTemplate>);
+
+impl TemplateRegistry {
+    /// Register `template` for `role`, returning the template previously registered for it.
+    pub fn register(
+        &mut self,
+        role: &str,
+        template: CustomCpuTemplate,
+    ) -> Option<CustomCpuTemplate> {
+        self.0.insert(role.to_string(), template)
+    }
+
+    /// Get the template registered for `role`.
+    pub fn get(&self, role: &str) -> Option<&CustomCpuTemplate> {
+        self.0.get(role)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu_config::x86_64::test_utils::{build_test_template, TEST_TEMPLATE_JSON};
+
+    #[test]
+    fn test_registry_register_get() {
+        let mut registry = TemplateRegistry::default();
+        assert_eq!(registry.register("db", build_test_template()), None);
+        assert_eq!(registry.get("db"), Some(&build_test_template()));
+        assert_eq!(registry.get("web"), None);
+
+        let web = CustomCpuTemplate::try_from(TEST_TEMPLATE_JSON).unwrap();
+        assert_eq!(
+            registry.register("db", web.clone()),
+            Some(build_test_template())
+        );
+        assert_eq!(registry.get("db"), Some(&web));
+    }
+
+    #[test]
+    fn test_registry_serde() {
+        let mut registry = TemplateRegistry::default();
+        registry.register("db", build_test_template());
+        registry.register("web", CustomCpuTemplate::default());
+
+        let json = serde_json::to_string(&registry).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            value["db"],
+            serde_json::to_value(build_test_template()).unwrap()
+        );
+        assert_eq!(
+            serde_json::from_str::<TemplateRegistry>(&json).unwrap(),
+            registry
+        );
+
+        serde_json::from_str::<TemplateRegistry>(r#"{"db": {"unknown": []}}"#).unwrap_err();
+    }
+}
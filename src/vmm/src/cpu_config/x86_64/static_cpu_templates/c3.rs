@@ -80,6 +80,7 @@ pub fn c3() -> CustomCpuTemplate {
                             filter: 0b0000_11111111_1111_00_11_1111_1111_1111,
                             value: 0b0000_00000000_0011_00_00_0110_1110_0100,
                         },
+                        passthrough: false,
                     },
                     // ECX: Feature Information
                     // - Bit 02: DTES64
@@ -99,6 +100,7 @@ pub fn c3() -> CustomCpuTemplate {
                             filter: 0b0000_0000_0100_0000_1101_1101_0011_1100,
                             value: 0b0000_0000_0000_0000_0000_0000_0000_0000,
                         },
+                        passthrough: false,
                     },
                     // EDX: Feature Information
                     // - Bit 07: MCE
@@ -115,6 +117,7 @@ pub fn c3() -> CustomCpuTemplate {
                             filter: 0b1010_1000_0110_0100_0001_0000_1000_0000,
                             value: 0b0000_0000_0000_0000_0001_0000_1000_0000,
                         },
+                        passthrough: false,
                     },
                 ],
             },
@@ -154,6 +157,7 @@ pub fn c3() -> CustomCpuTemplate {
                             filter: 0b1111_1111_1010_1111_1101_1101_0011_1100,
                             value: 0b0000_0000_0000_0000_0000_0000_0000_0000,
                         },
+                        passthrough: false,
                     },
                     // ECX:
                     // - Bit 01: AVX512_VBMI
@@ -171,6 +175,7 @@ pub fn c3() -> CustomCpuTemplate {
                             filter: 0b0100_0000_0100_0001_0100_1000_0001_1110,
                             value: 0b0000_0000_0000_0000_0000_0000_0000_0000,
                         },
+                        passthrough: false,
                     },
                     // EDX:
                     // - Bit 02: AVX512_4VNNIW
@@ -181,6 +186,7 @@ pub fn c3() -> CustomCpuTemplate {
                             filter: 0b0000_0000_0000_0000_0000_0000_0000_1100,
                             value: 0b0000_0000_0000_0000_0000_0000_0000_0000,
                         },
+                        passthrough: false,
                     },
                 ],
             },
@@ -199,6 +205,7 @@ pub fn c3() -> CustomCpuTemplate {
                             filter: 0b0000_0000_0000_0000_0000_00_1_0_111_11_000,
                             value: 0b0000_0000_0000_0000_0000_00_0_0_000_00_000,
                         },
+                        passthrough: false,
                     },
                 ],
             },
@@ -217,6 +224,7 @@ pub fn c3() -> CustomCpuTemplate {
                             filter: 0b0000_0000_0000_0000_0000_0000_0000_1110,
                             value: 0b0000_0000_0000_0000_0000_0000_0000_0000,
                         },
+                        passthrough: false,
                     },
                 ],
             },
@@ -234,6 +242,7 @@ pub fn c3() -> CustomCpuTemplate {
                             filter: 0b0000_0000_0000_0000_0000_0001_0010_0000,
                             value: 0b0000_0000_0000_0000_0000_0000_0000_0000,
                         },
+                        passthrough: false,
                     },
                     // EDX:
                     // - Bit 26: 1-GByte pages
@@ -243,6 +252,7 @@ pub fn c3() -> CustomCpuTemplate {
                             filter: 0b0000_0100_0000_0000_0000_0000_0000_0000,
                             value: 0b0000_0000_0000_0000_0000_0000_0000_0000,
                         },
+                        passthrough: false,
                     },
                 ],
             },
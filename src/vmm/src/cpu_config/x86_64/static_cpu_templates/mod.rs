@@ -1,9 +1,14 @@
 // Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
+use std::str::FromStr;
+
 use derive_more::Display;
 use serde::{Deserialize, Serialize};
 
+use crate::arch::x86_64::cpu_model::CpuModel;
+use crate::cpu_config::x86_64::cpuid::VENDOR_ID_INTEL;
+
 /// Module with C3 CPU template for x86_64
 pub mod c3;
 /// Module with T2 CPU template for x86_64
@@ -41,15 +46,88 @@ pub enum StaticCpuTemplate {
 }
 
 impl StaticCpuTemplate {
+    /// All static CPU templates, including [`StaticCpuTemplate::None`].
+    pub const ALL: [StaticCpuTemplate; 6] = [
+        StaticCpuTemplate::C3,
+        StaticCpuTemplate::T2,
+        StaticCpuTemplate::T2S,
+        StaticCpuTemplate::None,
+        StaticCpuTemplate::T2CL,
+        StaticCpuTemplate::T2A,
+    ];
+
     /// Check if no template specified
     pub fn is_none(&self) -> bool {
         self == &StaticCpuTemplate::None
     }
+
+    /// Get the nearest static CPU template that can be used instead of this one on CPU models
+    /// that do not support it.
+    pub fn fallback(&self) -> Option<StaticCpuTemplate> {
+        match self {
+            StaticCpuTemplate::T2CL => Some(StaticCpuTemplate::T2),
+            _ => None,
+        }
+    }
+
+    /// Get a more specific static CPU template that can be used instead of this one on a CPU
+    /// with the given vendor and model. This is only advisory and does not affect which
+    /// template is used.
+    pub fn suggest_upgrade(
+        &self,
+        vendor: &[u8; 12],
+        model: &CpuModel,
+    ) -> Option<StaticCpuTemplate> {
+        let is_cascade_lake = vendor == VENDOR_ID_INTEL && model.is_at_least_cascade_lake();
+        match self {
+            StaticCpuTemplate::T2 if is_cascade_lake => Some(StaticCpuTemplate::T2CL),
+            _ => None,
+        }
+    }
+}
+
+/// Invalid static CPU template {0}, must be one of: {1}
+#[derive(Debug, PartialEq, Eq, thiserror::Error, displaydoc::Display)]
+pub struct StaticCpuTemplateFromStrError(String, String);
+
+impl FromStr for StaticCpuTemplate {
+    type Err = StaticCpuTemplateFromStrError;
+    /// Parse a static CPU template from its name as displayed, ignoring case.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|template| template.to_string().eq_ignore_ascii_case(s))
+            .ok_or_else(|| {
+                let names: Vec<_> = Self::ALL.iter().map(ToString::to_string).collect();
+                StaticCpuTemplateFromStrError(String::from(s), names.join(", "))
+            })
+    }
+}
+
+/// Serialize every static CPU template to JSON, for documentation purposes.
+///
+/// Templates are returned regardless of whether they can be used on the host CPU.
+pub fn dump_all_static_templates() -> Vec<(StaticCpuTemplate, String)> {
+    [
+        (StaticCpuTemplate::C3, c3::c3()),
+        (StaticCpuTemplate::T2, t2::t2()),
+        (StaticCpuTemplate::T2S, t2s::t2s()),
+        (StaticCpuTemplate::T2CL, t2cl::t2cl()),
+        (StaticCpuTemplate::T2A, t2a::t2a()),
+    ]
+    .into_iter()
+    .map(|(name, template)| {
+        let json = serde_json::to_string_pretty(&template)
+            .expect("Static CPU templates are always serializable");
+        (name, json)
+    })
+    .collect()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::cpu_config::templates::CustomCpuTemplate;
     use crate::cpu_config::test_utils::get_json_template;
 
     #[test]
@@ -67,4 +145,62 @@ mod tests {
             assert_eq!(hardcoded_template, json_template);
         }
     }
+
+    #[test]
+    fn test_dump_all_static_templates() {
+        let dumps = dump_all_static_templates();
+        assert_eq!(dumps.len(), 5);
+        assert!(dumps.iter().all(|(name, _)| !name.is_none()));
+
+        for (name, json) in dumps {
+            let template: CustomCpuTemplate = serde_json::from_str(&json).unwrap();
+            assert!(
+                !template.cpuid_modifiers.is_empty() || !template.msr_modifiers.is_empty(),
+                "{name} dumped an empty template"
+            );
+        }
+    }
+
+    #[test]
+    fn test_suggest_upgrade() {
+        use crate::cpu_config::x86_64::cpuid::VENDOR_ID_AMD;
+
+        let cascade_lake = CpuModel::from(&0x0005_0657);
+        let skylake = CpuModel::from(&0x0005_0654);
+
+        assert_eq!(
+            StaticCpuTemplate::T2.suggest_upgrade(VENDOR_ID_INTEL, &cascade_lake),
+            Some(StaticCpuTemplate::T2CL)
+        );
+        assert_eq!(
+            StaticCpuTemplate::T2.suggest_upgrade(VENDOR_ID_INTEL, &skylake),
+            None
+        );
+        assert_eq!(
+            StaticCpuTemplate::T2.suggest_upgrade(VENDOR_ID_AMD, &cascade_lake),
+            None
+        );
+        assert_eq!(
+            StaticCpuTemplate::T2CL.suggest_upgrade(VENDOR_ID_INTEL, &cascade_lake),
+            None
+        );
+    }
+
+    #[test]
+    fn test_static_cpu_template_from_str() {
+        for template in StaticCpuTemplate::ALL {
+            let name = template.to_string();
+            assert_eq!(StaticCpuTemplate::from_str(&name).unwrap(), template);
+            assert_eq!(
+                StaticCpuTemplate::from_str(&name.to_lowercase()).unwrap(),
+                template
+            );
+        }
+        assert_eq!(StaticCpuTemplate::None.to_string(), "None");
+
+        assert_eq!(
+            StaticCpuTemplate::from_str("T3").unwrap_err().to_string(),
+            "Invalid static CPU template T3, must be one of: C3, T2, T2S, None, T2CL, T2A"
+        );
+    }
 }
@@ -80,6 +80,7 @@ pub fn t2() -> CustomCpuTemplate {
                             filter: 0b0000_11111111_1111_00_11_1111_1111_1111,
                             value: 0b0000_00000000_0011_00_00_0110_1111_0010,
                         },
+                        passthrough: false,
                     },
                     // ECX: Feature Information
                     // - Bit 02: DTES64
@@ -100,6 +101,7 @@ pub fn t2() -> CustomCpuTemplate {
                             filter: 0b0000_0000_0000_0100_1100_1101_1111_1100,
                             value: 0b0000_0000_0000_0000_0000_0000_0000_0000,
                         },
+                        passthrough: false,
                     },
                     // EDX: Feature Information
                     // - Bit 07: MCE
@@ -117,6 +119,7 @@ pub fn t2() -> CustomCpuTemplate {
                             filter: 0b1110_1000_0110_0100_0001_0000_1000_0000,
                             value: 0b0000_0000_0000_0000_0001_0000_1000_0000,
                         },
+                        passthrough: false,
                     },
                 ],
             },
@@ -154,6 +157,7 @@ pub fn t2() -> CustomCpuTemplate {
                             filter: 0b1111_1111_1110_1111_1101_1010_0001_0100,
                             value: 0b0000_0000_0000_0000_0000_0010_0000_0000,
                         },
+                        passthrough: false,
                     },
                     // ECX:
                     // - Bit 01: AVX512_VBMI
@@ -176,6 +180,7 @@ pub fn t2() -> CustomCpuTemplate {
                             filter: 0b0100_0000_0100_0001_0101_1111_0101_1110,
                             value: 0b0000_0000_0000_0000_0000_0000_0000_0000,
                         },
+                        passthrough: false,
                     },
                     // EDX:
                     // - Bit 02: AVX512_4VNNIW
@@ -188,6 +193,7 @@ pub fn t2() -> CustomCpuTemplate {
                             filter: 0b0000_0000_0000_0000_0000_0001_0001_1100,
                             value: 0b0000_0000_0000_0000_0000_0000_0000_0000,
                         },
+                        passthrough: false,
                     },
                 ],
             },
@@ -206,6 +212,7 @@ pub fn t2() -> CustomCpuTemplate {
                             filter: 0b0000_0000_0000_0000_0000_00_1_0_111_11_000,
                             value: 0b0000_0000_0000_0000_0000_00_0_0_000_00_000,
                         },
+                        passthrough: false,
                     },
                 ],
             },
@@ -224,6 +231,7 @@ pub fn t2() -> CustomCpuTemplate {
                             filter: 0b0000_0000_0000_0000_0000_0000_0000_1110,
                             value: 0b0000_0000_0000_0000_0000_0000_0000_0000,
                         },
+                        passthrough: false,
                     },
                 ],
             },
@@ -241,6 +249,7 @@ pub fn t2() -> CustomCpuTemplate {
                             filter: 0b0010_0000_0000_0000_0000_0001_0000_0000,
                             value: 0b0000_0000_0000_0000_0000_0000_0000_0000,
                         },
+                        passthrough: false,
                     },
                     // EDX:
                     // - Bit 26: 1-GByte pages
@@ -250,6 +259,7 @@ pub fn t2() -> CustomCpuTemplate {
                             filter: 0b0000_0100_0000_0000_0000_0000_0000_0000,
                             value: 0b0000_0000_0000_0000_0000_0000_0000_0000,
                         },
+                        passthrough: false,
                     },
                 ],
             },
@@ -266,6 +276,7 @@ pub fn t2() -> CustomCpuTemplate {
                             filter: 0b0000_0000_0000_0000_0000_0010_0000_0000,
                             value: 0b0000_0000_0000_0000_0000_0000_0000_0000,
                         },
+                        passthrough: false,
                     },
                 ],
             },
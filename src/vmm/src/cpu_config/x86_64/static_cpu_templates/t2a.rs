@@ -37,6 +37,7 @@ pub fn t2a() -> CustomCpuTemplate {
                             filter: 0b0000_11111111_1111_00_11_1111_1111_1111,
                             value: 0b0000_00000000_0011_00_00_0110_1111_0010,
                         },
+                        passthrough: false,
                     },
                     // ECX: Feature Information
                     // - Bit 02: Reserved (AMD APM) / DTES64 (Intel SDM)
@@ -57,6 +58,7 @@ pub fn t2a() -> CustomCpuTemplate {
                             filter: 0b0000_0000_0000_0100_1100_1101_1111_1100,
                             value: 0b0000_0000_0000_0000_0000_0000_0000_0000,
                         },
+                        passthrough: false,
                     },
                     // EDX: Feature Information
                     // - Bit 07: MCE (AMD APM) / MCE (Intel SDM)
@@ -74,6 +76,7 @@ pub fn t2a() -> CustomCpuTemplate {
                             filter: 0b1110_1000_0110_0100_0001_0000_1000_0000,
                             value: 0b0000_0000_0000_0000_0001_0000_1000_0000,
                         },
+                        passthrough: false,
                     },
                 ],
             },
@@ -113,6 +116,7 @@ pub fn t2a() -> CustomCpuTemplate {
                             filter: 0b1111_1111_1110_1111_1101_1010_0001_0100,
                             value: 0b0000_0000_0000_0000_0000_0010_0000_0000,
                         },
+                        passthrough: false,
                     },
                     // ECX:
                     // - Bit 01: Reserved (AMD APM) / AVX512_VBMI (Intel SDM)
@@ -135,6 +139,7 @@ pub fn t2a() -> CustomCpuTemplate {
                             filter: 0b0100_0000_0100_0001_0101_1111_0101_1110,
                             value: 0b0000_0000_0000_0000_0000_0000_0000_0000,
                         },
+                        passthrough: false,
                     },
                     // EDX:
                     // - Bit 02: Reserved (AMD APM) / AVX512_4VNNIW (Intel SDM)
@@ -147,6 +152,7 @@ pub fn t2a() -> CustomCpuTemplate {
                             filter: 0b0000_0000_0000_0000_0000_0001_0001_1100,
                             value: 0b0000_0000_0000_0000_0000_0000_0000_0000,
                         },
+                        passthrough: false,
                     },
                 ],
             },
@@ -165,6 +171,7 @@ pub fn t2a() -> CustomCpuTemplate {
                             filter: 0b0000_0000_0000_0000_0000_00_1_0_111_11_000,
                             value: 0b0000_0000_0000_0000_0000_00_0_0_000_00_000,
                         },
+                        passthrough: false,
                     },
                 ],
             },
@@ -185,6 +192,7 @@ pub fn t2a() -> CustomCpuTemplate {
                             filter: 0b0000_0000_0000_0000_0000_0000_0000_1110,
                             value: 0b0000_0000_0000_0000_0000_0000_0000_0000,
                         },
+                        passthrough: false,
                     },
                 ],
             },
@@ -205,6 +213,7 @@ pub fn t2a() -> CustomCpuTemplate {
                             filter: 0b0010_0000_0000_0000_0000_0001_1100_0100,
                             value: 0b0000_0000_0000_0000_0000_0000_0000_0000,
                         },
+                        passthrough: false,
                     },
                     // EDX:
                     // - Bit 22: MmxExt (AMD APM) / Reserved (Intel SDM)
@@ -216,6 +225,7 @@ pub fn t2a() -> CustomCpuTemplate {
                             filter: 0b0000_0110_0100_0000_0000_0000_0000_0000,
                             value: 0b0000_0000_0000_0000_0000_0000_0000_0000,
                         },
+                        passthrough: false,
                     },
                 ],
             },
@@ -237,6 +247,7 @@ pub fn t2a() -> CustomCpuTemplate {
                             filter: 0b0000_0000_0001_1100_0000_0010_0000_0101,
                             value: 0b0000_0000_0001_1100_0000_0000_0000_0100,
                         },
+                        passthrough: false,
                     },
                 ],
             },
@@ -38,6 +38,7 @@ pub fn t2cl() -> CustomCpuTemplate {
                             filter: 0b0000_11111111_1111_00_11_1111_1111_1111,
                             value: 0b0000_00000000_0011_00_00_0110_1111_0010,
                         },
+                        passthrough: false,
                     },
                     // ECX: Feature Information
                     // - Bit 02: DTES64 (Intel SDM) / Reserved (AMD APM)
@@ -58,6 +59,7 @@ pub fn t2cl() -> CustomCpuTemplate {
                             filter: 0b0000_0000_0000_0100_1100_1101_1111_1100,
                             value: 0b0000_0000_0000_0000_0000_0000_0000_0000,
                         },
+                        passthrough: false,
                     },
                     // EDX: Feature Information
                     // - Bit 07: MCE (Intel SDM) / MCE (AMD APM)
@@ -75,6 +77,7 @@ pub fn t2cl() -> CustomCpuTemplate {
                             filter: 0b1110_1000_0110_0100_0001_0000_1000_0000,
                             value: 0b0000_0000_0000_0000_0001_0000_1000_0000,
                         },
+                        passthrough: false,
                     },
                 ],
             },
@@ -114,6 +117,7 @@ pub fn t2cl() -> CustomCpuTemplate {
                             filter: 0b1111_1111_1110_1111_1101_1010_0001_0100,
                             value: 0b0000_0000_0000_0000_0000_0010_0000_0000,
                         },
+                        passthrough: false,
                     },
                     // ECX:
                     // - Bit 01: AVX512_VBMI (Intel SDM) / Reserved (AMD APM)
@@ -136,6 +140,7 @@ pub fn t2cl() -> CustomCpuTemplate {
                             filter: 0b0100_0000_0100_0001_0101_1111_0101_1110,
                             value: 0b0000_0000_0000_0000_0000_0000_0000_0000,
                         },
+                        passthrough: false,
                     },
                     // EDX:
                     // - Bit 02: AVX512_4VNNIW (Intel SDM) / Reserved (AMD APM)
@@ -148,6 +153,7 @@ pub fn t2cl() -> CustomCpuTemplate {
                             filter: 0b0000_0000_0000_0000_0000_0001_0001_1100,
                             value: 0b0000_0000_0000_0000_0000_0000_0000_0000,
                         },
+                        passthrough: false,
                     },
                 ],
             },
@@ -166,6 +172,7 @@ pub fn t2cl() -> CustomCpuTemplate {
                             filter: 0b0000_0000_0000_0000_0000_00_1_0_111_11_000,
                             value: 0b0000_0000_0000_0000_0000_00_0_0_000_00_000,
                         },
+                        passthrough: false,
                     },
                 ],
             },
@@ -186,6 +193,7 @@ pub fn t2cl() -> CustomCpuTemplate {
                             filter: 0b0000_0000_0000_0000_0000_0000_0000_1110,
                             value: 0b0000_0000_0000_0000_0000_0000_0000_0000,
                         },
+                        passthrough: false,
                     },
                 ],
             },
@@ -205,6 +213,7 @@ pub fn t2cl() -> CustomCpuTemplate {
                             filter: 0b0010_0000_0000_0000_0000_0001_1100_0000,
                             value: 0b0000_0000_0000_0000_0000_0000_0000_0000,
                         },
+                        passthrough: false,
                     },
                     // EDX:
                     // - Bit 22: Reserved (Intel SDM) / MmxExt (AMD APM)
@@ -218,6 +227,7 @@ pub fn t2cl() -> CustomCpuTemplate {
                             filter: 0b0000_0111_1100_0000_0000_0000_0000_0000,
                             value: 0b0000_0000_0000_0000_0000_0000_0000_0000,
                         },
+                        passthrough: false,
                     },
                 ],
             },
@@ -234,6 +244,7 @@ pub fn t2cl() -> CustomCpuTemplate {
                             filter: 0b0000_0000_0000_0000_0000_0010_0000_0000,
                             value: 0b0000_0000_0000_0000_0000_0000_0000_0000,
                         },
+                        passthrough: false,
                     },
                 ],
             },
@@ -287,6 +298,7 @@ pub fn t2cl() -> CustomCpuTemplate {
                     filter: 0b1111_1111_1111_1111_1111_1111_1111_1111_1110_0010_1111_0101_0001_1110_0000_0000,
                     value: 0b0000_0000_0000_0000_0000_0000_0000_0000_0000_0000_0000_0000_0000_0000_0000_0000,
                 },
+                source: None,
             },
         ],
         ..Default::default()
@@ -37,6 +37,7 @@ pub fn t2s() -> CustomCpuTemplate {
                             filter: 0b0000_11111111_1111_00_11_1111_1111_1111,
                             value: 0b0000_00000000_0011_00_00_0110_1111_0010,
                         },
+                        passthrough: false,
                     },
                     // ECX: Feature Information
                     // - Bit 02: DTES64
@@ -57,6 +58,7 @@ pub fn t2s() -> CustomCpuTemplate {
                             filter: 0b0000_0000_0000_0100_1100_1101_1111_1100,
                             value: 0b0000_0000_0000_0000_0000_0000_0000_0000,
                         },
+                        passthrough: false,
                     },
                     // EDX: Feature Information
                     // - Bit 07: MCE
@@ -74,6 +76,7 @@ pub fn t2s() -> CustomCpuTemplate {
                             filter: 0b1110_1000_0110_0100_0001_0000_1000_0000,
                             value: 0b0000_0000_0000_0000_0001_0000_1000_0000,
                         },
+                        passthrough: false,
                     },
                 ],
             },
@@ -111,6 +114,7 @@ pub fn t2s() -> CustomCpuTemplate {
                             filter: 0b1111_1111_1110_1111_1101_1010_0001_0100,
                             value: 0b0000_0000_0000_0000_0000_0010_0000_0000,
                         },
+                        passthrough: false,
                     },
                     // ECX:
                     // - Bit 01: AVX512_VBMI
@@ -133,6 +137,7 @@ pub fn t2s() -> CustomCpuTemplate {
                             filter: 0b0100_0000_0100_0001_0101_1111_0101_1110,
                             value: 0b0000_0000_0000_0000_0000_0000_0000_0000,
                         },
+                        passthrough: false,
                     },
                     // EDX:
                     // - Bit 02: AVX512_4VNNIW
@@ -145,6 +150,7 @@ pub fn t2s() -> CustomCpuTemplate {
                             filter: 0b0000_0000_0000_0000_0000_0001_0001_1100,
                             value: 0b0000_0000_0000_0000_0000_0000_0000_0000,
                         },
+                        passthrough: false,
                     },
                 ],
             },
@@ -163,6 +169,7 @@ pub fn t2s() -> CustomCpuTemplate {
                             filter: 0b0000_0000_0000_0000_0000_00_1_0_111_11_000,
                             value: 0b0000_0000_0000_0000_0000_00_0_0_000_00_000,
                         },
+                        passthrough: false,
                     },
                 ],
             },
@@ -181,6 +188,7 @@ pub fn t2s() -> CustomCpuTemplate {
                             filter: 0b0000_0000_0000_0000_0000_0000_0000_1110,
                             value: 0b0000_0000_0000_0000_0000_0000_0000_0000,
                         },
+                        passthrough: false,
                     },
                 ],
             },
@@ -198,6 +206,7 @@ pub fn t2s() -> CustomCpuTemplate {
                             filter: 0b0010_0000_0000_0000_0000_0001_0000_0000,
                             value: 0b0000_0000_0000_0000_0000_0000_0000_0000,
                         },
+                        passthrough: false,
                     },
                     // EDX:
                     // - Bit 26: 1-GByte pages
@@ -207,6 +216,7 @@ pub fn t2s() -> CustomCpuTemplate {
                             filter: 0b0000_0100_0000_0000_0000_0000_0000_0000,
                             value: 0b0000_0000_0000_0000_0000_0000_0000_0000,
                         },
+                        passthrough: false,
                     },
                 ],
             },
@@ -223,6 +233,7 @@ pub fn t2s() -> CustomCpuTemplate {
                             filter: 0b0000_0000_0000_0000_0000_0010_0000_0000,
                             value: 0b0000_0000_0000_0000_0000_0000_0000_0000,
                         },
+                        passthrough: false,
                     },
                 ],
             },
@@ -263,6 +274,7 @@ pub fn t2s() -> CustomCpuTemplate {
                 filter: 0b1111_1111_1111_1111_1111_1111_1111_1111_1111_1111_1111_1111_1111_1111_1111_1111,
                 value: 0b0000_0000_0000_0000_0000_0000_0000_0000_0000_1100_0000_1000_0000_1100_0100_1100,
             },
+            source: None,
         }],
         ..Default::default()
     }
@@ -123,6 +123,7 @@ pub fn build_test_template() -> CustomCpuTemplate {
                         filter: 0b0111,
                         value: 0b0101,
                     },
+                    passthrough: false,
                 },
                 CpuidRegisterModifier {
                     register: CpuidRegister::Ebx,
@@ -130,6 +131,7 @@ pub fn build_test_template() -> CustomCpuTemplate {
                         filter: 0b0111,
                         value: 0b0100,
                     },
+                    passthrough: false,
                 },
                 CpuidRegisterModifier {
                     register: CpuidRegister::Ecx,
@@ -137,6 +139,7 @@ pub fn build_test_template() -> CustomCpuTemplate {
                         filter: 0b0111,
                         value: 0b0111,
                     },
+                    passthrough: false,
                 },
                 CpuidRegisterModifier {
                     register: CpuidRegister::Edx,
@@ -144,6 +147,7 @@ pub fn build_test_template() -> CustomCpuTemplate {
                         filter: 0b0111,
                         value: 0b0001,
                     },
+                    passthrough: false,
                 },
             ],
         }],
@@ -154,6 +158,7 @@ pub fn build_test_template() -> CustomCpuTemplate {
                     filter: 0,
                     value: 0,
                 },
+                source: None,
             },
             RegisterModifier {
                 addr: 0x8000,
@@ -161,6 +166,7 @@ pub fn build_test_template() -> CustomCpuTemplate {
                     filter: 0,
                     value: 0,
                 },
+                source: None,
             },
         ],
         ..Default::default()
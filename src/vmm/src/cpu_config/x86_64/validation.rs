@@ -0,0 +1,1078 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::ops::RangeInclusive;
+
+use kvm_ioctls::Kvm;
+
+use crate::cpu_config::templates::CustomCpuTemplate;
+use crate::cpu_config::x86_64::cpuid::{
+    KvmCpuidFlags, VENDOR_ID_AMD, VENDOR_ID_AMD_STR, VENDOR_ID_INTEL, VENDOR_ID_INTEL_STR,
+};
+use crate::cpu_config::x86_64::custom_cpu_template::{
+    CpuidRegister, ModifierRef, SUBLEAF_WILDCARD,
+};
+use crate::cpu_config::x86_64::drift::cpuid_register_value;
+use crate::cpu_config::x86_64::host::HostSnapshot;
+use crate::cpu_config::x86_64::loader::LayeringMode;
+use crate::cpu_config::x86_64::TemplateError;
+use crate::logger::warn;
+
+/// CPUID leaves that KVM reports with `KVM_CPUID_FLAG_SIGNIFCANT_INDEX` set, i.e. leaves whose
+/// output depends on the subleaf passed in ECX.
+///
+/// Based on `__do_cpuid_func()` in `arch/x86/kvm/cpuid.c`.
+pub const SIGNIFICANT_INDEX_LEAVES: [u32; 15] = [
+    0x4, 0x7, 0xb, 0xd, 0xf, 0x10, 0x12, 0x14, 0x17, 0x18, 0x1d, 0x1e, 0x1f, 0x8000001d,
+    0x80000020,
+];
+
+/// CPUID leaves that are only defined on Intel CPUs.
+///
+/// The list is not exhaustive and only contains leaves that AMD documents as reserved.
+pub const INTEL_ONLY_LEAVES: [u32; 8] = [0x4, 0xa, 0x12, 0x14, 0x15, 0x16, 0x18, 0x1a];
+
+/// CPUID leaves that are only defined on AMD CPUs.
+///
+/// The list is not exhaustive and only contains leaves that Intel documents as reserved.
+pub const AMD_ONLY_LEAVES: [u32; 9] = [
+    0x8000000a, 0x80000019, 0x8000001a, 0x8000001b, 0x8000001d, 0x8000001e, 0x8000001f,
+    0x80000020, 0x80000021,
+];
+
+/// Registers that carry defined data in CPUID leaves whose other registers are reserved by the
+/// vendors defining the leaf.
+///
+/// The table is not exhaustive. Leaves that are not listed may have data in any register.
+pub const DEFINED_REGISTERS: [(u32, &[CpuidRegister]); 6] = [
+    // TSC and nominal core crystal clock.
+    (
+        0x15,
+        &[CpuidRegister::Eax, CpuidRegister::Ebx, CpuidRegister::Ecx],
+    ),
+    // Processor frequency.
+    (
+        0x16,
+        &[CpuidRegister::Eax, CpuidRegister::Ebx, CpuidRegister::Ecx],
+    ),
+    // Key Locker.
+    (
+        0x19,
+        &[CpuidRegister::Eax, CpuidRegister::Ebx, CpuidRegister::Ecx],
+    ),
+    // 1 GiB page TLB.
+    (0x80000019, &[CpuidRegister::Eax, CpuidRegister::Ebx]),
+    // Performance optimization identifiers.
+    (0x8000001a, &[CpuidRegister::Eax]),
+    // Processor topology.
+    (
+        0x8000001e,
+        &[CpuidRegister::Eax, CpuidRegister::Ebx, CpuidRegister::Ecx],
+    ),
+];
+
+/// CPUID leaf with the hypervisor signature in EBX, ECX and EDX.
+pub const HYPERVISOR_SIGNATURE_LEAF: u32 = 0x4000_0000;
+
+/// Hypervisor signature of KVM, which is what guests of Firecracker see in
+/// [`HYPERVISOR_SIGNATURE_LEAF`].
+pub const KVM_SIGNATURE: &[u8; 12] = b"KVMKVMKVM\0\0\0";
+
+/// Template sets hypervisor signature {signature:?} in CPUID leaf 0x40000000 instead of {expected:?}.
+#[derive(Debug, PartialEq, Eq, thiserror::Error, displaydoc::Display)]
+pub struct HypervisorSignatureError {
+    /// Signature set by the template.
+    pub signature: String,
+    /// Expected signature.
+    pub expected: String,
+}
+
+/// Template modifies register {register:?} of CPUID leaf {leaf:#x}, which the leaf does not define.
+#[derive(Debug, PartialEq, Eq, thiserror::Error, displaydoc::Display)]
+pub struct UndefinedRegisterError {
+    /// Leaf value.
+    pub leaf: u32,
+    /// CPUID register not defined by the leaf.
+    pub register: CpuidRegister,
+}
+
+/// Errors associated with the KVM flags of CPUID leaf modifiers.
+#[derive(Debug, PartialEq, Eq, thiserror::Error, displaydoc::Display)]
+pub enum KvmCpuidFlagsError {
+    /// Leaf {0:#x} is indexed by subleaf and requires the SIGNIFICANT_INDEX flag.
+    MissingSignificantIndex(u32),
+    /// Leaf {0:#x} is not indexed by subleaf and must not set the SIGNIFICANT_INDEX flag.
+    UnexpectedSignificantIndex(u32),
+}
+
+/// Template sets vendor {template_vendor} but modifies {leaf_vendor} specific leaf {leaf:#x}.
+#[derive(Debug, PartialEq, Eq, thiserror::Error, displaydoc::Display)]
+pub struct VendorMismatchError {
+    /// CPU vendor set by the template in leaf 0x0.
+    pub template_vendor: &'static str,
+    /// Vendor specific leaf modified by the template.
+    pub leaf: u32,
+    /// CPU vendor the leaf is specific to.
+    pub leaf_vendor: &'static str,
+}
+
+/// How to handle modifiers that do not change any bit of the targeted register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoopModifierPolicy {
+    /// Log a warning for every such modifier.
+    Warn,
+    /// Fail on the first such modifier.
+    Error,
+}
+
+/// Errors associated with modifiers that do not change any bit of the targeted register.
+#[derive(Debug, PartialEq, Eq, thiserror::Error, displaydoc::Display)]
+pub enum NoopModifierError {
+    /// Modifier of CPUID leaf {0:#x}, subleaf {1:#x}, register {2:?} does not change any bit.
+    Cpuid(u32, u32, CpuidRegister),
+    /// Modifier of MSR {0:#x} does not change any bit.
+    Msr(u32),
+}
+
+/// Errors associated with querying the MSRs supported by KVM on the host.
+#[derive(Debug, thiserror::Error, displaydoc::Display)]
+pub enum HostMsrError {
+    /// Failed to open KVM: {0}
+    Kvm(kvm_ioctls::Error),
+    /// Failed to get MSR index list: {0}
+    GetMsrIndexList(kvm_ioctls::Error),
+}
+
+/// Restrictions on the modifiers a custom CPU template may contain.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TemplatePolicy {
+    /// Whether the template may modify MSRs.
+    pub allow_msr_modifiers: bool,
+    /// Whether the template may modify CPUID leaves.
+    pub allow_cpuid_modifiers: bool,
+    /// Maximum number of register modifiers, counting CPUID register and MSR modifiers.
+    pub max_modifiers: Option<usize>,
+    /// MSRs the template may modify. Any MSR may be modified if not set.
+    pub msr_allowlist: Option<Vec<u32>>,
+    /// Ranges of CPUID leaves the template may modify. Any leaf may be modified if not set.
+    pub allowed_leaves: Option<Vec<RangeInclusive<u32>>>,
+}
+
+impl Default for TemplatePolicy {
+    /// Policy that allows any template.
+    fn default() -> Self {
+        Self {
+            allow_msr_modifiers: true,
+            allow_cpuid_modifiers: true,
+            max_modifiers: None,
+            msr_allowlist: None,
+            allowed_leaves: None,
+        }
+    }
+}
+
+/// Errors associated with templates violating a [`TemplatePolicy`].
+#[derive(Debug, PartialEq, Eq, thiserror::Error, displaydoc::Display)]
+pub enum TemplatePolicyError {
+    /// Template modifies MSR {0:#x}, but MSR modifiers are not allowed.
+    MsrModifiersNotAllowed(u32),
+    /// Template modifies CPUID leaf {0:#x}, but CPUID modifiers are not allowed.
+    CpuidModifiersNotAllowed(u32),
+    /// Template has {0} modifiers, but at most {1} are allowed.
+    TooManyModifiers(usize, usize),
+    /// Template modifies MSR {0:#x}, which is not in the allowlist.
+    MsrNotAllowed(u32),
+    /// Template modifies CPUID leaf {0:#x}, which is not in the allowed leaves.
+    LeafNotAllowed(u32),
+}
+
+/// Context of [`CustomCpuTemplate::validate_all`]. Validations whose context is not set are
+/// skipped.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ValidationContext {
+    /// How to handle modifiers without effect.
+    pub noop_modifier_policy: Option<NoopModifierPolicy>,
+    /// Policy the template must comply with.
+    pub policy: Option<TemplatePolicy>,
+    /// MSRs supported by KVM on the host, e.g. as returned by [`capture_supported_msrs`].
+    pub supported_msrs: Option<Vec<u32>>,
+    /// Hypervisor signature guests are expected to see, usually [`KVM_SIGNATURE`]. Templates
+    /// that spoof the signature on purpose can set the spoofed one instead.
+    pub hypervisor_signature: Option<[u8; 12]>,
+    /// How multiple modifiers of the same MSR must be layered, see
+    /// [`CustomCpuTemplate::layer_msr_modifiers`]. Templates derived from a static template
+    /// usually override some of its MSRs, so this is meant for standalone templates.
+    pub msr_layering: Option<LayeringMode>,
+}
+
+/// Get the indices of the MSRs supported by KVM on the host.
+pub fn capture_supported_msrs() -> Result<Vec<u32>, HostMsrError> {
+    let kvm = Kvm::new().map_err(HostMsrError::Kvm)?;
+    let msr_index_list = kvm.get_msr_index_list().map_err(HostMsrError::GetMsrIndexList)?;
+    Ok(msr_index_list.as_slice().to_vec())
+}
+
+impl CustomCpuTemplate {
+    /// Check that every CPUID leaf modifier sets the `SIGNIFICANT_INDEX` flag if and only if KVM
+    /// expects it for that leaf.
+    pub fn validate_significant_index(&self) -> Result<(), KvmCpuidFlagsError> {
+        for leaf_modifier in &self.cpuid_modifiers {
+            let significant_index =
+                leaf_modifier.flags.0 & KvmCpuidFlags::SIGNIFICANT_INDEX.0 != 0;
+            let expected = SIGNIFICANT_INDEX_LEAVES.contains(&leaf_modifier.leaf);
+
+            match (expected, significant_index) {
+                (true, false) => {
+                    return Err(KvmCpuidFlagsError::MissingSignificantIndex(
+                        leaf_modifier.leaf,
+                    ))
+                }
+                (false, true) => {
+                    return Err(KvmCpuidFlagsError::UnexpectedSignificantIndex(
+                        leaf_modifier.leaf,
+                    ))
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that no CPUID register modifier targets a register that is not defined by its leaf,
+    /// according to [`DEFINED_REGISTERS`]. Modifying such a register is most likely a mistake.
+    pub fn validate_defined_registers(&self) -> Result<(), UndefinedRegisterError> {
+        for leaf_modifier in &self.cpuid_modifiers {
+            let Some((_, defined_registers)) = DEFINED_REGISTERS
+                .iter()
+                .find(|(leaf, _)| *leaf == leaf_modifier.leaf)
+            else {
+                continue;
+            };
+            if let Some(reg_modifier) = leaf_modifier
+                .modifiers
+                .iter()
+                .find(|reg_modifier| !defined_registers.contains(&reg_modifier.register))
+            {
+                return Err(UndefinedRegisterError {
+                    leaf: leaf_modifier.leaf,
+                    register: reg_modifier.register.clone(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Check the template for modifiers whose bitmap consists only of don't-care bits, and thus
+    /// have no effect. Depending on `policy`, such modifiers are either logged or rejected.
+    pub fn check_noop_modifiers(
+        &self,
+        policy: NoopModifierPolicy,
+    ) -> Result<(), NoopModifierError> {
+        for noop_modifier in self.noop_modifiers() {
+            match policy {
+                NoopModifierPolicy::Warn => warn!("CPU template: {noop_modifier}"),
+                NoopModifierPolicy::Error => return Err(noop_modifier),
+            }
+        }
+        Ok(())
+    }
+
+    /// Get the modifiers of the template whose bitmap consists only of don't-care bits, except
+    /// CPUID register modifiers marked as passthrough.
+    pub(crate) fn noop_modifiers(&self) -> impl Iterator<Item = NoopModifierError> + '_ {
+        self.into_iter().filter_map(|modifier| match modifier {
+            ModifierRef::Cpuid(leaf_modifier, reg_modifier)
+                if reg_modifier.bitmap.is_noop() && !reg_modifier.passthrough =>
+            {
+                Some(NoopModifierError::Cpuid(
+                    leaf_modifier.leaf,
+                    leaf_modifier.subleaf,
+                    reg_modifier.register.clone(),
+                ))
+            }
+            ModifierRef::Msr(modifier) if modifier.bitmap.is_noop() => {
+                Some(NoopModifierError::Msr(modifier.addr))
+            }
+            _ => None,
+        })
+    }
+
+    /// Get the modifiers of the template that do not change any bit on `host`, because the host
+    /// already has the value they set for every bit they touch. Each modifier is checked against
+    /// the host values on its own, regardless of the modifiers before it. Modifiers of CPUID
+    /// entries or MSRs the host does not have, and passthrough CPUID register modifiers, are not
+    /// reported.
+    pub fn lint_against_host(&self, host: &HostSnapshot) -> Vec<NoopModifierError> {
+        let mut noop_modifiers = Vec::new();
+        for modifier in self {
+            match modifier {
+                // Passthrough modifiers are meant to leave the register unchanged.
+                ModifierRef::Cpuid(_, reg_modifier) if reg_modifier.passthrough => {}
+                ModifierRef::Cpuid(leaf_modifier, reg_modifier) => {
+                    let host_values: Vec<u32> = host
+                        .cpuid
+                        .inner()
+                        .iter()
+                        .filter(|(key, _)| {
+                            key.leaf == leaf_modifier.leaf
+                                && (leaf_modifier.subleaf == SUBLEAF_WILDCARD
+                                    || key.subleaf == leaf_modifier.subleaf)
+                        })
+                        .map(|(_, entry)| {
+                            cpuid_register_value(&entry.result, &reg_modifier.register)
+                        })
+                        .collect();
+                    let is_noop = !host_values.is_empty()
+                        && host_values
+                            .iter()
+                            .all(|value| reg_modifier.bitmap.apply(*value) == *value);
+                    if is_noop {
+                        noop_modifiers.push(NoopModifierError::Cpuid(
+                            leaf_modifier.leaf,
+                            leaf_modifier.subleaf,
+                            reg_modifier.register.clone(),
+                        ));
+                    }
+                }
+                ModifierRef::Msr(modifier) => {
+                    let bitmap = modifier.resolve_bitmap(|addr| host.msrs.get(&addr).copied());
+                    match (bitmap, host.msrs.get(&modifier.addr)) {
+                        (Ok(bitmap), Some(value)) if bitmap.apply(*value) == *value => {
+                            noop_modifiers.push(NoopModifierError::Msr(modifier.addr));
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+        noop_modifiers
+    }
+
+    /// Get the CPU vendor ID set by the template, if every bit of EBX, EDX and ECX of leaf 0x0 is
+    /// pinned by the template.
+    pub fn vendor_id(&self) -> Option<[u8; 12]> {
+        let effect_map = self.effect_map();
+        let register_value = |register: CpuidRegister| {
+            effect_map
+                .cpuid
+                .iter()
+                .find(|(key, _)| key.leaf == 0x0 && key.subleaf == 0x0 && key.register == register)
+                .filter(|(_, bitmap)| bitmap.is_full(32))
+                .map(|(_, bitmap)| bitmap.value)
+        };
+
+        // The ordering of the vendor string is ebx,edx,ecx this is not a mistake.
+        let ebx = register_value(CpuidRegister::Ebx)?.to_le_bytes();
+        let edx = register_value(CpuidRegister::Edx)?.to_le_bytes();
+        let ecx = register_value(CpuidRegister::Ecx)?.to_le_bytes();
+        Some([
+            ebx[0], ebx[1], ebx[2], ebx[3], edx[0], edx[1], edx[2], edx[3], ecx[0], ecx[1], ecx[2],
+            ecx[3],
+        ])
+    }
+
+    /// Check that the CPU vendor set by the template in leaf 0x0, if any, is consistent with the
+    /// vendor specific leaves modified by the template.
+    pub fn validate_vendor_consistency(&self) -> Result<(), VendorMismatchError> {
+        let (template_vendor, foreign_leaves, leaf_vendor) = match self.vendor_id() {
+            Some(vendor_id) if &vendor_id == VENDOR_ID_INTEL => {
+                (VENDOR_ID_INTEL_STR, &AMD_ONLY_LEAVES[..], VENDOR_ID_AMD_STR)
+            }
+            Some(vendor_id) if &vendor_id == VENDOR_ID_AMD => {
+                (VENDOR_ID_AMD_STR, &INTEL_ONLY_LEAVES[..], VENDOR_ID_INTEL_STR)
+            }
+            _ => return Ok(()),
+        };
+
+        match self
+            .cpuid_modifiers
+            .iter()
+            .find(|leaf_modifier| foreign_leaves.contains(&leaf_modifier.leaf))
+        {
+            Some(leaf_modifier) => Err(VendorMismatchError {
+                template_vendor,
+                leaf: leaf_modifier.leaf,
+                leaf_vendor,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Check that the template does not change the hypervisor signature in
+    /// [`HYPERVISOR_SIGNATURE_LEAF`] away from `expected`. Guests may misbehave if they see an
+    /// unexpected signature, so templates should only change it to spoof another hypervisor on
+    /// purpose, in which case the spoofed signature is the expected one.
+    pub fn validate_hypervisor_signature(
+        &self,
+        expected: &[u8; 12],
+    ) -> Result<(), HypervisorSignatureError> {
+        let mut signature = *expected;
+        for (key, bitmap) in self.effect_map().cpuid {
+            // The signature is stored in ebx, ecx and edx, in this order.
+            let offset = match (key.leaf, key.register) {
+                (HYPERVISOR_SIGNATURE_LEAF, CpuidRegister::Ebx) => 0,
+                (HYPERVISOR_SIGNATURE_LEAF, CpuidRegister::Ecx) => 4,
+                (HYPERVISOR_SIGNATURE_LEAF, CpuidRegister::Edx) => 8,
+                _ => continue,
+            };
+            let bytes = &mut signature[offset..offset + 4];
+            let mut value = [0; 4];
+            value.copy_from_slice(bytes);
+            bytes.copy_from_slice(&bitmap.apply(u32::from_le_bytes(value)).to_le_bytes());
+        }
+
+        if &signature == expected {
+            Ok(())
+        } else {
+            Err(HypervisorSignatureError {
+                signature: String::from_utf8_lossy(&signature).into_owned(),
+                expected: String::from_utf8_lossy(expected).into_owned(),
+            })
+        }
+    }
+
+    /// Check that the template complies with `policy`.
+    pub fn validate_policy(&self, policy: &TemplatePolicy) -> Result<(), TemplatePolicyError> {
+        if !policy.allow_msr_modifiers {
+            if let Some(modifier) = self.msr_modifiers.first() {
+                return Err(TemplatePolicyError::MsrModifiersNotAllowed(modifier.addr));
+            }
+        }
+        if !policy.allow_cpuid_modifiers {
+            if let Some(leaf_modifier) = self.cpuid_modifiers.first() {
+                return Err(TemplatePolicyError::CpuidModifiersNotAllowed(
+                    leaf_modifier.leaf,
+                ));
+            }
+        }
+        if let Some(max_modifiers) = policy.max_modifiers {
+            let num_modifiers = self.into_iter().count();
+            if num_modifiers > max_modifiers {
+                return Err(TemplatePolicyError::TooManyModifiers(
+                    num_modifiers,
+                    max_modifiers,
+                ));
+            }
+        }
+        if let Some(msr_allowlist) = &policy.msr_allowlist {
+            if let Some(addr) = self.msr_index_iter().find(|addr| !msr_allowlist.contains(addr)) {
+                return Err(TemplatePolicyError::MsrNotAllowed(addr));
+            }
+        }
+        if let Some(allowed_leaves) = &policy.allowed_leaves {
+            let is_allowed = |leaf: &u32| allowed_leaves.iter().any(|range| range.contains(leaf));
+            if let Some(leaf_modifier) = self
+                .cpuid_modifiers
+                .iter()
+                .find(|leaf_modifier| !is_allowed(&leaf_modifier.leaf))
+            {
+                return Err(TemplatePolicyError::LeafNotAllowed(leaf_modifier.leaf));
+            }
+        }
+        Ok(())
+    }
+
+    /// Run every validation of the template that applies in `ctx` and collect all errors,
+    /// instead of stopping at the first one. The `SIGNIFICANT_INDEX` flag, the consistency of
+    /// the CPU vendor and the registers modified in each leaf are always validated.
+    pub fn validate_all(&self, ctx: &ValidationContext) -> Result<(), Vec<TemplateError>> {
+        let mut errors = Vec::new();
+
+        if let Some(msr_layering) = ctx.msr_layering {
+            if let Err(err) = self.without_cpuid().layer_msr_modifiers(msr_layering) {
+                errors.push(TemplateError::from(err));
+            }
+        }
+        if let Err(err) = self.validate_significant_index() {
+            errors.push(TemplateError::from(err));
+        }
+        if let Err(err) = self.validate_vendor_consistency() {
+            errors.push(TemplateError::from(err));
+        }
+        if let Err(err) = self.validate_defined_registers() {
+            errors.push(TemplateError::from(err));
+        }
+        if let Some(noop_modifier_policy) = ctx.noop_modifier_policy {
+            if let Err(err) = self.check_noop_modifiers(noop_modifier_policy) {
+                errors.push(TemplateError::from(err));
+            }
+        }
+        if let Some(policy) = &ctx.policy {
+            if let Err(err) = self.validate_policy(policy) {
+                errors.push(TemplateError::from(err));
+            }
+        }
+        if let Some(supported_msrs) = &ctx.supported_msrs {
+            for addr in self.validate_against_supported_msrs(supported_msrs) {
+                errors.push(TemplateError::UnsupportedMsr(addr));
+            }
+        }
+        if let Some(hypervisor_signature) = &ctx.hypervisor_signature {
+            if let Err(err) = self.validate_hypervisor_signature(hypervisor_signature) {
+                errors.push(TemplateError::from(err));
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Get the addresses of the MSRs modified by the template, or copied from without a default
+    /// value, that are not in `supported`, e.g. as returned by [`capture_supported_msrs`]. Each
+    /// address is reported once, in ascending order.
+    pub fn validate_against_supported_msrs(&self, supported: &[u32]) -> Vec<u32> {
+        let required_sources = self.msr_modifiers.iter().filter_map(|modifier| {
+            let source = modifier.source?;
+            source.default.is_none().then_some(source.msr)
+        });
+        let mut unsupported: Vec<_> = self
+            .msr_index_iter()
+            .chain(required_sources)
+            .filter(|addr| !supported.contains(addr))
+            .collect();
+        unsupported.sort_unstable();
+        unsupported.dedup();
+        unsupported
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu_config::templates::RegisterValueFilter;
+    use crate::cpu_config::x86_64::custom_cpu_template::{
+        CpuidLeafModifier, CpuidRegister, CpuidRegisterModifier, MsrFieldSource, RegisterModifier,
+    };
+    use crate::cpu_config::x86_64::loader::LoadError;
+    use crate::cpu_config::x86_64::static_cpu_templates::{c3, t2, t2a, t2cl, t2s};
+    use crate::cpu_config::x86_64::test_utils::{build_test_template, TEST_TEMPLATE_JSON};
+
+    fn build_leaf_template(leaf: u32, flags: KvmCpuidFlags) -> CustomCpuTemplate {
+        CustomCpuTemplate {
+            cpuid_modifiers: vec![CpuidLeafModifier {
+                leaf,
+                subleaf: 0x0,
+                flags,
+                modifiers: vec![CpuidRegisterModifier {
+                    register: CpuidRegister::Ebx,
+                    bitmap: RegisterValueFilter {
+                        filter: 0b1,
+                        value: 0b0,
+                    },
+                    passthrough: false,
+                }],
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_significant_index_missing() {
+        let template = build_leaf_template(0x7, KvmCpuidFlags::EMPTY);
+        assert_eq!(
+            template.validate_significant_index().unwrap_err(),
+            KvmCpuidFlagsError::MissingSignificantIndex(0x7)
+        );
+        assert_eq!(
+            template
+                .validate_significant_index()
+                .unwrap_err()
+                .to_string(),
+            "Leaf 0x7 is indexed by subleaf and requires the SIGNIFICANT_INDEX flag."
+        );
+    }
+
+    #[test]
+    fn test_significant_index_unexpected() {
+        let template = build_leaf_template(0x1, KvmCpuidFlags::SIGNIFICANT_INDEX);
+        assert_eq!(
+            template.validate_significant_index().unwrap_err(),
+            KvmCpuidFlagsError::UnexpectedSignificantIndex(0x1)
+        );
+    }
+
+    #[test]
+    fn test_significant_index_valid() {
+        build_leaf_template(0x1, KvmCpuidFlags::EMPTY)
+            .validate_significant_index()
+            .unwrap();
+        build_leaf_template(0x7, KvmCpuidFlags::SIGNIFICANT_INDEX)
+            .validate_significant_index()
+            .unwrap();
+        build_test_template().validate_significant_index().unwrap();
+
+        for template in [c3::c3(), t2::t2(), t2s::t2s(), t2cl::t2cl(), t2a::t2a()] {
+            template.validate_significant_index().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_validate_defined_registers() {
+        // Leaf 0x8000001a only defines EAX.
+        let template = build_leaf_template(0x8000001a, KvmCpuidFlags::EMPTY);
+        assert_eq!(
+            template.validate_defined_registers().unwrap_err(),
+            UndefinedRegisterError {
+                leaf: 0x8000001a,
+                register: CpuidRegister::Ebx,
+            }
+        );
+        assert_eq!(
+            template
+                .validate_defined_registers()
+                .unwrap_err()
+                .to_string(),
+            "Template modifies register Ebx of CPUID leaf 0x8000001a, which the leaf does not \
+             define."
+        );
+
+        build_leaf_template(0x16, KvmCpuidFlags::EMPTY)
+            .validate_defined_registers()
+            .unwrap();
+        // Leaves that are not in the table are not checked.
+        build_test_template().validate_defined_registers().unwrap();
+        for template in [c3::c3(), t2::t2(), t2s::t2s(), t2cl::t2cl(), t2a::t2a()] {
+            template.validate_defined_registers().unwrap();
+        }
+    }
+
+    fn build_vendor_template(vendor_id: &[u8; 12], leaf: u32) -> CustomCpuTemplate {
+        let vendor_register = |offset: usize| CpuidRegisterModifier {
+            register: match offset {
+                0 => CpuidRegister::Ebx,
+                4 => CpuidRegister::Edx,
+                _ => CpuidRegister::Ecx,
+            },
+            bitmap: RegisterValueFilter {
+                filter: u32::MAX,
+                value: u32::from_le_bytes(vendor_id[offset..offset + 4].try_into().unwrap()),
+            },
+            passthrough: false,
+        };
+
+        let mut template = build_leaf_template(leaf, KvmCpuidFlags::EMPTY);
+        template.cpuid_modifiers.insert(
+            0,
+            CpuidLeafModifier {
+                leaf: 0x0,
+                subleaf: 0x0,
+                flags: KvmCpuidFlags::EMPTY,
+                modifiers: vec![vendor_register(0), vendor_register(4), vendor_register(8)],
+            },
+        );
+        template
+    }
+
+    #[test]
+    fn test_vendor_id() {
+        assert_eq!(
+            build_vendor_template(VENDOR_ID_INTEL, 0x1).vendor_id(),
+            Some(*VENDOR_ID_INTEL)
+        );
+        assert_eq!(
+            build_vendor_template(VENDOR_ID_AMD, 0x1).vendor_id(),
+            Some(*VENDOR_ID_AMD)
+        );
+        assert_eq!(build_test_template().vendor_id(), None);
+    }
+
+    #[test]
+    fn test_vendor_consistency_valid() {
+        build_vendor_template(VENDOR_ID_INTEL, 0x4)
+            .validate_vendor_consistency()
+            .unwrap();
+        build_vendor_template(VENDOR_ID_AMD, 0x8000001f)
+            .validate_vendor_consistency()
+            .unwrap();
+        // Templates that do not set the vendor are not checked.
+        build_leaf_template(0x8000001f, KvmCpuidFlags::EMPTY)
+            .validate_vendor_consistency()
+            .unwrap();
+    }
+
+    #[test]
+    fn test_vendor_consistency_mismatch() {
+        let error = build_vendor_template(VENDOR_ID_INTEL, 0x8000001f)
+            .validate_vendor_consistency()
+            .unwrap_err();
+        assert_eq!(
+            error,
+            VendorMismatchError {
+                template_vendor: VENDOR_ID_INTEL_STR,
+                leaf: 0x8000001f,
+                leaf_vendor: VENDOR_ID_AMD_STR,
+            }
+        );
+        assert_eq!(
+            error.to_string(),
+            "Template sets vendor GenuineIntel but modifies AuthenticAMD specific leaf 0x8000001f."
+        );
+    }
+
+    #[test]
+    fn test_noop_modifiers() {
+        let template = serde_json::from_str::<CustomCpuTemplate>(
+            r#"{
+                "msr_modifiers": [
+                    {
+                        "addr": "0x10a",
+                        "bitmap": "0bxxxx"
+                    }
+                ]
+            }"#,
+        )
+        .unwrap();
+        template
+            .check_noop_modifiers(NoopModifierPolicy::Warn)
+            .unwrap();
+        let error = template
+            .check_noop_modifiers(NoopModifierPolicy::Error)
+            .unwrap_err();
+        assert_eq!(error, NoopModifierError::Msr(0x10a));
+        assert_eq!(
+            error.to_string(),
+            "Modifier of MSR 0x10a does not change any bit."
+        );
+
+        let mut template = build_leaf_template(0x1, KvmCpuidFlags::EMPTY);
+        template.cpuid_modifiers[0].modifiers[0].bitmap = RegisterValueFilter::default();
+        assert_eq!(
+            template
+                .check_noop_modifiers(NoopModifierPolicy::Error)
+                .unwrap_err(),
+            NoopModifierError::Cpuid(0x1, 0x0, CpuidRegister::Ebx)
+        );
+
+        // Registers intentionally left unchanged are not reported.
+        template.cpuid_modifiers[0].modifiers[0].passthrough = true;
+        template
+            .check_noop_modifiers(NoopModifierPolicy::Error)
+            .unwrap();
+
+        build_leaf_template(0x1, KvmCpuidFlags::EMPTY)
+            .check_noop_modifiers(NoopModifierPolicy::Error)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_lint_against_host() {
+        use std::collections::{BTreeMap, HashMap};
+
+        use crate::arch::x86_64::cpu_model::CpuModel;
+        use crate::cpu_config::x86_64::cpuid::{
+            Cpuid, CpuidEntry, CpuidKey, CpuidRegisters, IntelCpuid,
+        };
+        use crate::cpu_config::x86_64::custom_cpu_template::RegisterModifier;
+
+        let host = HostSnapshot {
+            vendor: *VENDOR_ID_INTEL,
+            model: CpuModel::from(&0x0005_0657),
+            cpuid: Cpuid::Intel(IntelCpuid(BTreeMap::from([(
+                CpuidKey::leaf(0x1),
+                CpuidEntry {
+                    flags: KvmCpuidFlags::EMPTY,
+                    result: CpuidRegisters {
+                        eax: 0x0,
+                        ebx: 0b1010,
+                        ecx: 0x0,
+                        edx: 0x0,
+                    },
+                },
+            )]))),
+            msrs: HashMap::from([(0x10, 0x1234), (0x10a, 0xff)]),
+        };
+
+        let msr_modifier = |addr, filter, value| RegisterModifier {
+            addr,
+            bitmap: RegisterValueFilter { filter, value },
+            source: None,
+        };
+        let mut template = build_leaf_template(0x1, KvmCpuidFlags::EMPTY);
+        // EBX is set to the value the host already has, ECX is changed.
+        template.cpuid_modifiers[0].modifiers[0].bitmap = RegisterValueFilter {
+            filter: 0b11,
+            value: 0b10,
+        };
+        template.cpuid_modifiers[0].modifiers.push(CpuidRegisterModifier {
+            register: CpuidRegister::Ecx,
+            bitmap: RegisterValueFilter {
+                filter: 0b1,
+                value: 0b1,
+            },
+            passthrough: false,
+        });
+        // Passthrough modifiers are not reported.
+        template.cpuid_modifiers[0].modifiers.push(CpuidRegisterModifier {
+            register: CpuidRegister::Edx,
+            bitmap: RegisterValueFilter::default(),
+            passthrough: true,
+        });
+        template.msr_modifiers = vec![
+            msr_modifier(0x10a, 0xf, 0xf),
+            msr_modifier(0x10, 0b1, 0b1),
+            msr_modifier(0x20, 0b1, 0b1),
+        ];
+
+        assert_eq!(
+            template.lint_against_host(&host),
+            vec![
+                NoopModifierError::Cpuid(0x1, 0x0, CpuidRegister::Ebx),
+                NoopModifierError::Msr(0x10a),
+            ]
+        );
+
+        // Leaves the host does not have are not reported, and neither are modifiers that change
+        // the value of the host.
+        template.cpuid_modifiers[0].leaf = 0x2;
+        template.msr_modifiers.truncate(2);
+        template.msr_modifiers[0].bitmap.value = 0x0;
+        assert_eq!(
+            template.lint_against_host(&host),
+            Vec::<NoopModifierError>::new()
+        );
+    }
+
+    #[test]
+    fn test_capture_supported_msrs() {
+        // KVM always supports saving the TSC (MSR 0x10).
+        let supported = capture_supported_msrs().unwrap();
+        assert!(supported.contains(&0x10));
+    }
+
+    #[test]
+    fn test_validate_against_supported_msrs() {
+        let template = build_test_template();
+        assert_eq!(
+            template.validate_against_supported_msrs(&[0x8000, 0x9999]),
+            Vec::<u32>::new()
+        );
+        assert_eq!(
+            template.validate_against_supported_msrs(&[0x8000, 0x10]),
+            vec![0x9999]
+        );
+
+        let mut template = build_test_template();
+        template.msr_modifiers[0].bitmap.filter = 0b1;
+        template.msr_modifiers[0].source = Some(MsrFieldSource {
+            msr: 0x10,
+            bits: [0, 0],
+            default: None,
+        });
+        assert_eq!(
+            template.validate_against_supported_msrs(&[0x8000, 0x9999]),
+            vec![0x10]
+        );
+        template.msr_modifiers[0].source.as_mut().unwrap().default = Some(0x1);
+        assert_eq!(
+            template.validate_against_supported_msrs(&[0x8000, 0x9999]),
+            Vec::<u32>::new()
+        );
+    }
+
+    #[test]
+    fn test_validate_policy_msr_modifiers() {
+        let policy = TemplatePolicy {
+            allow_msr_modifiers: false,
+            ..Default::default()
+        };
+        let error = build_test_template().validate_policy(&policy).unwrap_err();
+        assert_eq!(error, TemplatePolicyError::MsrModifiersNotAllowed(0x9999));
+        assert_eq!(
+            error.to_string(),
+            "Template modifies MSR 0x9999, but MSR modifiers are not allowed."
+        );
+
+        let template = CustomCpuTemplate {
+            msr_modifiers: vec![],
+            ..build_test_template()
+        };
+        template.validate_policy(&policy).unwrap();
+    }
+
+    #[test]
+    fn test_validate_policy() {
+        let template = build_test_template();
+        template.validate_policy(&TemplatePolicy::default()).unwrap();
+
+        let policy = TemplatePolicy {
+            allow_cpuid_modifiers: false,
+            ..Default::default()
+        };
+        assert_eq!(
+            template.validate_policy(&policy).unwrap_err(),
+            TemplatePolicyError::CpuidModifiersNotAllowed(0x3)
+        );
+
+        // 4 CPUID register modifiers and 2 MSR modifiers.
+        let policy = TemplatePolicy {
+            max_modifiers: Some(5),
+            ..Default::default()
+        };
+        assert_eq!(
+            template.validate_policy(&policy).unwrap_err(),
+            TemplatePolicyError::TooManyModifiers(6, 5)
+        );
+
+        let policy = TemplatePolicy {
+            msr_allowlist: Some(vec![0x8000]),
+            ..Default::default()
+        };
+        assert_eq!(
+            template.validate_policy(&policy).unwrap_err(),
+            TemplatePolicyError::MsrNotAllowed(0x9999)
+        );
+        let policy = TemplatePolicy {
+            msr_allowlist: Some(vec![0x8000, 0x9999]),
+            ..Default::default()
+        };
+        template.validate_policy(&policy).unwrap();
+    }
+
+    #[test]
+    fn test_validate_policy_allowed_leaves() {
+        // Only standard leaves, forbidding extended leaves from 0x80000000.
+        let policy = TemplatePolicy {
+            allowed_leaves: Some(vec![0x0..=0x7fff_ffff]),
+            ..Default::default()
+        };
+
+        build_test_template().validate_policy(&policy).unwrap();
+        let error = CustomCpuTemplate::try_from(TEST_TEMPLATE_JSON)
+            .unwrap()
+            .validate_policy(&policy)
+            .unwrap_err();
+        assert_eq!(error, TemplatePolicyError::LeafNotAllowed(0x80000001));
+        assert_eq!(
+            error.to_string(),
+            "Template modifies CPUID leaf 0x80000001, which is not in the allowed leaves."
+        );
+    }
+
+    #[test]
+    fn test_validate_hypervisor_signature() {
+        let signature_modifier = |register, value| CpuidRegisterModifier {
+            register,
+            bitmap: RegisterValueFilter {
+                filter: u32::MAX,
+                value,
+            },
+            passthrough: false,
+        };
+        let mut template = CustomCpuTemplate {
+            cpuid_modifiers: vec![CpuidLeafModifier {
+                leaf: HYPERVISOR_SIGNATURE_LEAF,
+                subleaf: 0x0,
+                flags: KvmCpuidFlags::EMPTY,
+                modifiers: vec![
+                    signature_modifier(CpuidRegister::Ebx, u32::from_le_bytes(*b"XenV")),
+                    signature_modifier(CpuidRegister::Ecx, u32::from_le_bytes(*b"MMXe")),
+                    signature_modifier(CpuidRegister::Edx, u32::from_le_bytes(*b"nVMM")),
+                ],
+            }],
+            ..Default::default()
+        };
+
+        let error = template
+            .validate_hypervisor_signature(KVM_SIGNATURE)
+            .unwrap_err();
+        assert_eq!(error.signature, "XenVMMXenVMM");
+        assert_eq!(
+            error.to_string(),
+            "Template sets hypervisor signature \"XenVMMXenVMM\" in CPUID leaf 0x40000000 instead \
+             of \"KVMKVMKVM\\0\\0\\0\"."
+        );
+        // Spoofing the signature on purpose.
+        template
+            .validate_hypervisor_signature(b"XenVMMXenVMM")
+            .unwrap();
+
+        // Setting the expected signature, or only changing the maximum leaf in EAX, is fine.
+        template.cpuid_modifiers[0].modifiers = vec![
+            signature_modifier(CpuidRegister::Eax, 0x4000_0001),
+            signature_modifier(CpuidRegister::Ebx, u32::from_le_bytes(*b"KVMK")),
+            signature_modifier(CpuidRegister::Edx, u32::from_le_bytes(*b"M\0\0\0")),
+        ];
+        template
+            .validate_hypervisor_signature(KVM_SIGNATURE)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_validate_all() {
+        build_test_template()
+            .validate_all(&ValidationContext::default())
+            .unwrap();
+
+        // Leaf 0x7 requires the SIGNIFICANT_INDEX flag and MSR 0x9999 is modified twice.
+        let mut template = build_test_template();
+        template.cpuid_modifiers[0].leaf = 0x7;
+        template.msr_modifiers.push(template.msr_modifiers[0]);
+        let errors = template
+            .validate_all(&ValidationContext::default())
+            .unwrap_err();
+        assert_eq!(errors.len(), 1, "{errors:?}");
+        let ctx = ValidationContext {
+            msr_layering: Some(LayeringMode::Strict),
+            ..Default::default()
+        };
+        let errors = template.validate_all(&ctx).unwrap_err();
+        assert_eq!(errors.len(), 2, "{errors:?}");
+        assert!(matches!(
+            errors[0],
+            TemplateError::Load(LoadError::DuplicateMsr(0x9999))
+        ));
+        assert!(matches!(
+            errors[1],
+            TemplateError::KvmCpuidFlags(KvmCpuidFlagsError::MissingSignificantIndex(0x7))
+        ));
+
+        // The MSR modifiers have no effect and MSR 0x9999 is not supported.
+        let ctx = ValidationContext {
+            noop_modifier_policy: Some(NoopModifierPolicy::Error),
+            policy: Some(TemplatePolicy::default()),
+            supported_msrs: Some(vec![0x8000]),
+            hypervisor_signature: Some(*KVM_SIGNATURE),
+            msr_layering: Some(LayeringMode::Strict),
+        };
+        let errors = build_test_template().validate_all(&ctx).unwrap_err();
+        assert_eq!(errors.len(), 2, "{errors:?}");
+        assert!(matches!(
+            errors[0],
+            TemplateError::NoopModifier(NoopModifierError::Msr(0x9999))
+        ));
+        assert!(matches!(errors[1], TemplateError::UnsupportedMsr(0x9999)));
+    }
+
+    #[test]
+    fn test_validate_all_with_base() {
+        // T2S modifies MSR 0x10a, which templates derived from it may override.
+        let mut template = t2s::t2s();
+        template.msr_modifiers.push(RegisterModifier {
+            addr: 0x10a,
+            bitmap: RegisterValueFilter {
+                filter: 0b1,
+                value: 0b1,
+            },
+            source: None,
+        });
+        template
+            .validate_all(&ValidationContext::default())
+            .unwrap();
+
+        let ctx = ValidationContext {
+            msr_layering: Some(LayeringMode::Strict),
+            ..Default::default()
+        };
+        let errors = template.validate_all(&ctx).unwrap_err();
+        assert!(
+            matches!(
+                errors[..],
+                [TemplateError::Load(LoadError::DuplicateMsr(0x10a))]
+            ),
+            "{errors:?}"
+        );
+    }
+}
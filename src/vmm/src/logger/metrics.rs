@@ -826,6 +826,32 @@ impl VmmMetrics {
     }
 }
 
+/// Metrics related to applying CPU templates.
+#[cfg(feature = "cpu-template-metrics")]
+#[derive(Debug, Default, Serialize)]
+pub struct CpuTemplateMetrics {
+    /// Number of CPUID registers modified by CPU templates.
+    pub cpuid_registers_modified: SharedIncMetric,
+    /// Number of MSRs modified by CPU templates.
+    pub msrs_modified: SharedIncMetric,
+    /// Provides Min/max/sum for applying the CPUID modifiers of CPU templates.
+    pub apply_cpuid_agg: LatencyAggregateMetrics,
+    /// Provides Min/max/sum for applying the MSR modifiers of CPU templates.
+    pub apply_msrs_agg: LatencyAggregateMetrics,
+}
+#[cfg(feature = "cpu-template-metrics")]
+impl CpuTemplateMetrics {
+    /// Const default construction.
+    pub const fn new() -> Self {
+        Self {
+            cpuid_registers_modified: SharedIncMetric::new(),
+            msrs_modified: SharedIncMetric::new(),
+            apply_cpuid_agg: LatencyAggregateMetrics::new(),
+            apply_msrs_agg: LatencyAggregateMetrics::new(),
+        }
+    }
+}
+
 // The sole purpose of this struct is to produce an UTC timestamp when an instance is serialized.
 #[derive(Debug, Default)]
 struct SerializeToUtcTimestampMs;
@@ -908,6 +934,9 @@ pub struct FirecrackerMetrics {
     pub vcpu: VcpuMetrics,
     /// Metrics related to the virtual machine manager.
     pub vmm: VmmMetrics,
+    #[cfg(feature = "cpu-template-metrics")]
+    /// Metrics related to applying CPU templates.
+    pub cpu_template: CpuTemplateMetrics,
     /// Metrics related to signals.
     pub signals: SignalMetrics,
     #[serde(flatten)]
@@ -940,6 +969,8 @@ impl FirecrackerMetrics {
             seccomp: SeccompMetrics::new(),
             vcpu: VcpuMetrics::new(),
             vmm: VmmMetrics::new(),
+            #[cfg(feature = "cpu-template-metrics")]
+            cpu_template: CpuTemplateMetrics::new(),
             signals: SignalMetrics::new(),
             vsock_ser: VsockMetricsSerializeProxy {},
             entropy_ser: EntropyMetricsSerializeProxy {},
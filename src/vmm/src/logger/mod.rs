@@ -16,6 +16,8 @@ pub use metrics::{
     IncMetric, LatencyAggregateMetrics, MetricsError, ProcessTimeReporter, SharedIncMetric,
     SharedStoreMetric, StoreMetric, METRICS,
 };
+#[cfg(feature = "cpu-template-metrics")]
+pub use metrics::CpuTemplateMetrics;
 use utils::time::{get_time_us, ClockType};
 
 /// Alias for `std::io::LineWriter<std::fs::File>`.
@@ -5,7 +5,7 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the THIRD-PARTY file.
 
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::Debug;
 
 use kvm_bindings::{
@@ -21,6 +21,7 @@ use crate::arch::x86_64::gen::msr_index::{MSR_IA32_TSC, MSR_IA32_TSC_DEADLINE};
 use crate::arch::x86_64::interrupts;
 use crate::arch::x86_64::msr::{create_boot_msr_entries, MsrError};
 use crate::arch::x86_64::regs::{SetupFpuError, SetupRegistersError, SetupSpecialRegistersError};
+use crate::cpu_config::x86_64::drift::VcpuCpuidMsrAccess;
 use crate::cpu_config::x86_64::{cpuid, CpuConfiguration};
 use crate::logger::{IncMetric, METRICS};
 use crate::vstate::memory::{Address, GuestAddress, GuestMemoryMmap};
@@ -625,6 +626,19 @@ impl KvmVcpu {
     }
 }
 
+impl VcpuCpuidMsrAccess for KvmVcpu {
+    type Error = KvmVcpuError;
+
+    fn read_cpuid(&self) -> Result<cpuid::Cpuid, KvmVcpuError> {
+        Ok(cpuid::Cpuid::try_from(self.get_cpuid()?)?)
+    }
+
+    fn read_msrs(&self, addrs: &[u32]) -> Result<HashMap<u32, u64>, KvmVcpuError> {
+        let msrs = self.get_msrs(addrs.iter().copied())?;
+        Ok(msrs.into_iter().collect())
+    }
+}
+
 impl Peripherals {
     /// Runs the vCPU in KVM context and handles the kvm exit reason.
     ///
@@ -0,0 +1,74 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Tests that apply CPU templates to vCPUs of a real KVM VM, enabled by the `kvm-integration`
+//! feature on hosts with `/dev/kvm`.
+
+#![cfg(all(feature = "kvm-integration", target_arch = "x86_64"))]
+
+use std::collections::BTreeMap;
+
+use kvm_bindings::{CpuId, KVM_MAX_CPUID_ENTRIES};
+use vmm::cpu_config::templates::{CustomCpuTemplate, RegisterValueFilter};
+use vmm::cpu_config::x86_64::cpuid::{Cpuid, KvmCpuidFlags};
+use vmm::cpu_config::x86_64::custom_cpu_template::{
+    CpuidLeafModifier, CpuidRegister, CpuidRegisterModifier,
+};
+use vmm::cpu_config::x86_64::CpuConfiguration;
+use vmm::vstate::vcpu::KvmVcpu;
+use vmm::vstate::vm::Vm;
+
+/// SSE3 feature bit in ECX of CPUID leaf 0x1, supported by every x86_64 CPU KVM runs on.
+const SSE3_BIT: u32 = 0;
+
+/// Create a VM with a single vCPU.
+fn setup_vcpu() -> (Vm, KvmVcpu) {
+    let vm = Vm::new(vec![]).unwrap();
+    let vcpu = KvmVcpu::new(0, &vm).unwrap();
+    (vm, vcpu)
+}
+
+#[test]
+fn test_cpuid_modifier_takes_effect() {
+    let (vm, vcpu) = setup_vcpu();
+    let template = CustomCpuTemplate {
+        cpuid_modifiers: vec![CpuidLeafModifier {
+            leaf: 0x1,
+            subleaf: 0x0,
+            flags: KvmCpuidFlags::EMPTY,
+            modifiers: vec![CpuidRegisterModifier {
+                register: CpuidRegister::Ecx,
+                bitmap: RegisterValueFilter {
+                    filter: 1 << SSE3_BIT,
+                    value: 0,
+                },
+                passthrough: false,
+            }],
+        }],
+        ..Default::default()
+    };
+
+    let host_leaf_0x1 = vm
+        .supported_cpuid()
+        .as_slice()
+        .iter()
+        .find(|entry| entry.function == 0x1)
+        .unwrap();
+    assert_ne!(host_leaf_0x1.ecx & (1 << SSE3_BIT), 0);
+
+    let host_config = CpuConfiguration {
+        cpuid: Cpuid::try_from(vm.supported_cpuid().clone()).unwrap(),
+        msrs: BTreeMap::new(),
+    };
+    let guest_config = host_config.apply_template(&template).unwrap();
+    let kvm_cpuid = CpuId::try_from(guest_config.cpuid).unwrap();
+    vcpu.fd.set_cpuid2(&kvm_cpuid).unwrap();
+
+    let guest_cpuid = vcpu.fd.get_cpuid2(KVM_MAX_CPUID_ENTRIES).unwrap();
+    let leaf_0x1 = guest_cpuid
+        .as_slice()
+        .iter()
+        .find(|entry| entry.function == 0x1)
+        .unwrap();
+    assert_eq!(leaf_0x1.ecx & (1 << SSE3_BIT), 0);
+}